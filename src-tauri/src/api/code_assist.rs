@@ -2,25 +2,135 @@
 
 #![allow(dead_code)]
 
+use super::error::UpstreamError;
+use super::sse::SseParser;
+use crate::cancel::CancelToken;
+use crate::config;
+use crate::credentials::AntigravityCredentials;
 use anyhow::Result;
+use chrono::Utc;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 use tracing::{debug, info};
 
-/// Code Assist API 端点
-pub const CODE_ASSIST_ENDPOINT: &str = "https://cloudcode-pa.googleapis.com";
-pub const CODE_ASSIST_API_VERSION: &str = "v1internal";
+/// 按当前配置的环境和 API 版本构建方法 URL，例如 `.../v1internal:loadCodeAssist`
+pub fn method_url(method: &str) -> String {
+    let config = config::current();
+    let api = &config.settings.api;
+    format!("{}/{}:{}", api.base_url(), api.api_version, method)
+}
+
+/// 隐私声明
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PrivacyNotice {
+    #[serde(default)]
+    pub show_notice: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notice_text: Option<String>,
+}
+
+/// Code Assist 层级（loadCodeAssist 的 currentTier / allowedTiers 元素）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Tier {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// 是否需要用户自行提供 cloudaicompanionProject
+    #[serde(default)]
+    pub user_defined_cloudaicompanion_project: bool,
+    #[serde(default)]
+    pub is_default: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privacy_notice: Option<PrivacyNotice>,
+}
+
+impl Tier {
+    pub fn user_tier(&self) -> UserTier {
+        UserTier::from_id(&self.id)
+    }
+
+    /// 用于展示的名称
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.id)
+    }
+}
+
+/// 不可用层级及原因
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct IneligibleTier {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tier_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tier_name: Option<String>,
+}
+
+impl std::fmt::Display for IneligibleTier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tier = self
+            .tier_name
+            .as_deref()
+            .or(self.tier_id.as_deref())
+            .unwrap_or("unknown tier");
+        write!(
+            f,
+            "{}: {} ({})",
+            tier,
+            self.reason_message.as_deref().unwrap_or("ineligible"),
+            self.reason_code.as_deref().unwrap_or("UNKNOWN")
+        )
+    }
+}
 
 /// Load Code Assist 响应
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct LoadCodeAssistResponse {
     #[serde(rename = "cloudaicompanionProject")]
     pub cloud_ai_companion_project: Option<String>,
     #[serde(rename = "currentTier")]
-    pub current_tier: Option<serde_json::Value>,
-    #[serde(rename = "allowedTiers")]
-    pub allowed_tiers: Option<serde_json::Value>,
+    pub current_tier: Option<Tier>,
+    #[serde(rename = "allowedTiers", default)]
+    pub allowed_tiers: Vec<Tier>,
+    #[serde(rename = "ineligibleTiers", default)]
+    pub ineligible_tiers: Vec<IneligibleTier>,
+}
+
+/// 凭证的层级信息快照（用于排查账户无法 onboard 的原因）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct TierInfo {
+    #[serde(default)]
+    pub current_tier: Option<Tier>,
+    #[serde(default)]
+    pub allowed_tiers: Vec<Tier>,
+    #[serde(default)]
+    pub ineligible_tiers: Vec<IneligibleTier>,
+    /// 检查时间（RFC3339）
+    #[serde(default)]
+    pub checked_at: Option<String>,
+}
+
+impl From<&LoadCodeAssistResponse> for TierInfo {
+    fn from(load_res: &LoadCodeAssistResponse) -> Self {
+        Self {
+            current_tier: load_res.current_tier.clone(),
+            allowed_tiers: load_res.allowed_tiers.clone(),
+            ineligible_tiers: load_res.ineligible_tiers.clone(),
+            checked_at: Some(Utc::now().to_rfc3339()),
+        }
+    }
 }
 
 /// 用户层级
@@ -29,14 +139,29 @@ pub enum UserTier {
     Legacy,
     Free,
     Pro,
+    Standard,
+    /// 未识别的层级，保留原始 ID
+    Other(String),
 }
 
 impl UserTier {
+    pub fn from_id(id: &str) -> Self {
+        match id {
+            "LEGACY" | "legacy-tier" => UserTier::Legacy,
+            "FREE" | "free-tier" => UserTier::Free,
+            "PRO" | "pro-tier" => UserTier::Pro,
+            "STANDARD" | "standard-tier" => UserTier::Standard,
+            other => UserTier::Other(other.to_string()),
+        }
+    }
+
     pub fn as_str(&self) -> &str {
         match self {
             UserTier::Legacy => "LEGACY",
             UserTier::Free => "FREE",
             UserTier::Pro => "PRO",
+            UserTier::Standard => "STANDARD",
+            UserTier::Other(id) => id,
         }
     }
 }
@@ -48,7 +173,7 @@ pub async fn load_code_assist(
 ) -> Result<LoadCodeAssistResponse> {
     let client = Client::builder()
         .connect_timeout(std::time::Duration::from_secs(30))
-        .timeout(std::time::Duration::from_secs(60))
+        .timeout(config::current().request_timeout())
         .build()?;
 
     // 对于个人账户（无 projectId），先调用 tokeninfo/userinfo
@@ -85,10 +210,7 @@ pub async fn load_code_assist(
         request["metadata"]["duetProject"] = json!(pid);
     }
 
-    let url = format!(
-        "{}/{}:loadCodeAssist",
-        CODE_ASSIST_ENDPOINT, CODE_ASSIST_API_VERSION
-    );
+    let url = method_url("loadCodeAssist");
 
     debug!("调用 loadCodeAssist: {}", url);
 
@@ -100,10 +222,8 @@ pub async fn load_code_assist(
         .send()
         .await?;
 
-    let status = response.status();
-    if !status.is_success() {
-        let body = response.text().await.unwrap_or_default();
-        anyhow::bail!("loadCodeAssist 失败: {} - {}", status, body);
+    if !response.status().is_success() {
+        return Err(upstream_error("loadCodeAssist", response).await.into());
     }
 
     let data: LoadCodeAssistResponse = response.json().await?;
@@ -112,51 +232,330 @@ pub async fn load_code_assist(
     Ok(data)
 }
 
-/// 获取 onboard 层级
-pub fn get_onboard_tier(load_res: &LoadCodeAssistResponse) -> UserTier {
-    if let Some(current) = &load_res.current_tier {
-        return match current["id"].as_str() {
-            Some("PRO") => UserTier::Pro,
-            Some("FREE") => UserTier::Free,
-            _ => UserTier::Legacy,
-        };
+/// 去掉模型名的 `models/` 前缀
+pub fn normalize_model(model: &str) -> &str {
+    model.strip_prefix("models/").unwrap_or(model)
+}
+
+/// 将标准 Gemini 请求包装为 v1internal 的 `{model, project, request}` 请求体
+pub fn wrap_request(
+    model: &str,
+    project_id: &str,
+    request: serde_json::Value,
+) -> serde_json::Value {
+    json!({
+        "model": normalize_model(model),
+        "project": project_id,
+        "request": request
+    })
+}
+
+/// 解包 v1internal 响应中的 `response` 字段
+pub fn unwrap_response(mut body: serde_json::Value) -> serde_json::Value {
+    match body.get_mut("response") {
+        Some(inner) => inner.take(),
+        None => body,
     }
+}
 
-    if let Some(tiers) = &load_res.allowed_tiers {
-        if let Some(arr) = tiers.as_array() {
-            for tier in arr {
-                if tier["isDefault"].as_bool().unwrap_or(false) {
-                    return match tier["id"].as_str() {
-                        Some("PRO") => UserTier::Pro,
-                        Some("FREE") => UserTier::Free,
-                        _ => UserTier::Legacy,
-                    };
+/// 调用 generateContent（v1internal），返回标准 Gemini 响应
+pub async fn generate_content(
+    access_token: &str,
+    project_id: &str,
+    model: &str,
+    request: serde_json::Value,
+) -> Result<serde_json::Value> {
+    let client = Client::builder()
+        .connect_timeout(std::time::Duration::from_secs(30))
+        .timeout(config::current().request_timeout())
+        .build()?;
+
+    let url = method_url("generateContent");
+    debug!("调用 generateContent: {} (model: {})", url, model);
+
+    let response = client
+        .post(&url)
+        .header("Authorization", format!("Bearer {}", access_token))
+        .header("Content-Type", "application/json")
+        .json(&wrap_request(model, project_id, request))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(upstream_error("generateContent", response).await.into());
+    }
+
+    let body: serde_json::Value = response.json().await?;
+    Ok(unwrap_response(body))
+}
+
+/// 读取非 2xx 响应并解析为 UpstreamError
+async fn upstream_error(method: &str, response: reqwest::Response) -> UpstreamError {
+    let status = response.status().as_u16();
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let body = response.text().await.unwrap_or_default();
+    UpstreamError::from_response(method, status, &body, retry_after.as_deref())
+}
+
+/// 流式生成汇总
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StreamSummary {
+    /// 已推送的分块数
+    pub chunks: usize,
+    /// 汇总的 usageMetadata（各计数取最大值，上游按累计值返回）
+    pub usage_metadata: Option<serde_json::Value>,
+    pub finish_reason: Option<String>,
+    pub model_version: Option<String>,
+}
+
+impl StreamSummary {
+    /// 记录一个分块
+    pub fn observe(&mut self, chunk: &serde_json::Value) {
+        self.chunks += 1;
+        if let Some(usage) = chunk.get("usageMetadata").and_then(|u| u.as_object()) {
+            let acc = self
+                .usage_metadata
+                .get_or_insert_with(|| json!({}))
+                .as_object_mut()
+                .unwrap();
+            for (k, v) in usage {
+                match (acc.get(k).and_then(|a| a.as_u64()), v.as_u64()) {
+                    (Some(old), Some(new)) if old >= new => {}
+                    _ => {
+                        acc.insert(k.clone(), v.clone());
+                    }
                 }
             }
         }
+        if let Some(reason) = chunk["candidates"][0]["finishReason"].as_str() {
+            self.finish_reason = Some(reason.to_string());
+        }
+        if let Some(version) = chunk["modelVersion"].as_str() {
+            self.model_version = Some(version.to_string());
+        }
+    }
+}
+
+/// 调用 streamGenerateContent?alt=sse，每收到一个分块（已解包 response）调用一次 on_chunk
+pub async fn stream_generate_content<F>(
+    access_token: &str,
+    project_id: &str,
+    model: &str,
+    request: serde_json::Value,
+    cancel: Option<CancelToken>,
+    mut on_chunk: F,
+) -> Result<StreamSummary>
+where
+    F: FnMut(serde_json::Value) + Send,
+{
+    // 流式响应不设置总超时，改为按分块间隔计算空闲超时
+    let idle_timeout = config::current().request_timeout();
+    let client = Client::builder()
+        .connect_timeout(std::time::Duration::from_secs(30))
+        .build()?;
+
+    let url = format!("{}?alt=sse", method_url("streamGenerateContent"));
+    debug!("调用 streamGenerateContent: {} (model: {})", url, model);
+
+    let mut response = client
+        .post(&url)
+        .header("Authorization", format!("Bearer {}", access_token))
+        .header("Content-Type", "application/json")
+        .header("Accept", "text/event-stream")
+        .json(&wrap_request(model, project_id, request))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(upstream_error("streamGenerateContent", response)
+            .await
+            .into());
+    }
+
+    let mut parser = SseParser::new();
+    let mut summary = StreamSummary::default();
+    let mut cancel = cancel;
+
+    loop {
+        let next = async {
+            tokio::time::timeout(idle_timeout, response.chunk())
+                .await
+                .map_err(|_| {
+                    anyhow::anyhow!("streamGenerateContent 超过 {:?} 未收到数据", idle_timeout)
+                })
+        };
+        let chunk = match cancel.as_mut() {
+            Some(token) => tokio::select! {
+                c = next => c??,
+                _ = token.cancelled() => anyhow::bail!("streamGenerateContent 已取消"),
+            },
+            None => next.await??,
+        };
+
+        let done = chunk.is_none();
+        let events = match chunk {
+            Some(bytes) => parser.feed(&bytes),
+            None => parser.finish().into_iter().collect(),
+        };
+        for event in events {
+            let data = event.data.trim();
+            if data.is_empty() || data == "[DONE]" {
+                continue;
+            }
+            let value: serde_json::Value = serde_json::from_str(data)
+                .map_err(|e| anyhow::anyhow!("无法解析流式分块: {} - {}", e, data))?;
+            if let Some(error) = value.get("error") {
+                let err = UpstreamError::from_error("streamGenerateContent", error, 500);
+                return Err(err.into());
+            }
+            let value = unwrap_response(value);
+            summary.observe(&value);
+            on_chunk(value);
+        }
+
+        if done {
+            break;
+        }
     }
 
-    UserTier::Legacy
+    info!("streamGenerateContent 完成，共 {} 个分块", summary.chunks);
+    Ok(summary)
 }
 
-/// Onboard 响应
+/// 选择用于 onboard 的层级：已有 currentTier 优先，其次是默认的 allowedTier
+pub fn select_onboard_tier(load_res: &LoadCodeAssistResponse) -> Option<&Tier> {
+    load_res
+        .current_tier
+        .as_ref()
+        .or_else(|| load_res.allowed_tiers.iter().find(|t| t.is_default))
+}
+
+/// 获取 onboard 层级
+pub fn get_onboard_tier(load_res: &LoadCodeAssistResponse) -> UserTier {
+    select_onboard_tier(load_res)
+        .map(Tier::user_tier)
+        .unwrap_or(UserTier::Legacy)
+}
+
+/// Onboard 响应（长运行操作）
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OnboardResponse {
+    /// 操作名称，例如 `operations/xxx`
+    #[serde(default)]
+    pub name: Option<String>,
     #[serde(default)]
     pub done: bool,
     #[serde(default)]
     pub response: Option<serde_json::Value>,
+    #[serde(default)]
+    pub error: Option<serde_json::Value>,
+}
+
+/// onboard 进度
+#[derive(Debug, Clone, Serialize)]
+pub struct OnboardProgress {
+    /// 已完成的请求次数（含首次 onboardUser 调用）
+    pub attempts: u32,
+    pub elapsed_ms: u64,
+    /// 正在轮询的操作名称
+    pub operation: Option<String>,
+    /// 下次轮询前的等待时间
+    pub next_poll_ms: u64,
 }
 
-/// 调用 onboardUser（包含轮询逻辑）
+/// onboard 失败
+#[derive(Debug, Error)]
+pub enum OnboardError {
+    #[error(
+        "onboardUser 超时: 已等待 {elapsed_ms}ms（上限 {deadline_ms}ms），共请求 {attempts} 次，操作 {} 仍未完成",
+        .operation.as_deref().unwrap_or("<未返回操作名>")
+    )]
+    Timeout {
+        attempts: u32,
+        elapsed_ms: u64,
+        deadline_ms: u64,
+        operation: Option<String>,
+    },
+    #[error("onboardUser 已取消: 已等待 {elapsed_ms}ms，共请求 {attempts} 次")]
+    Cancelled {
+        attempts: u32,
+        elapsed_ms: u64,
+        operation: Option<String>,
+    },
+    #[error("onboardUser 操作失败: {0}")]
+    Operation(serde_json::Value),
+}
+
+/// onboard 进度回调
+pub type ProgressCallback = Arc<dyn Fn(&OnboardProgress) + Send + Sync>;
+
+/// onboard 控制：取消与进度回调
+#[derive(Clone, Default)]
+pub struct OnboardControl {
+    pub cancel: Option<CancelToken>,
+    pub progress: Option<ProgressCallback>,
+}
+
+impl OnboardControl {
+    /// 等待 duration，期间被取消时返回 false
+    async fn sleep(&self, duration: Duration) -> bool {
+        match &self.cancel {
+            Some(token) => {
+                let mut token = token.clone();
+                tokio::select! {
+                    _ = tokio::time::sleep(duration) => true,
+                    _ = token.cancelled() => false,
+                }
+            }
+            None => {
+                tokio::time::sleep(duration).await;
+                true
+            }
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|t| t.is_cancelled())
+    }
+}
+
+/// 长运行操作的查询 URL，例如 `.../v1internal/operations/xxx`
+pub fn operation_url(name: &str) -> String {
+    let config = config::current();
+    let api = &config.settings.api;
+    format!(
+        "{}/{}/{}",
+        api.base_url(),
+        api.api_version,
+        name.trim_start_matches('/')
+    )
+}
+
+async fn read_operation(response: reqwest::Response, what: &str) -> Result<OnboardResponse> {
+    if !response.status().is_success() {
+        return Err(upstream_error(what, response).await.into());
+    }
+    Ok(response.json().await?)
+}
+
+/// 调用 onboardUser，并轮询长运行操作直到完成
+///
+/// 返回操作名时按名称查询操作状态，否则重新提交 onboardUser；
+/// 轮询间隔、退避和截止时间取自 settings.onboarding。
 pub async fn onboard_user(
     access_token: &str,
     tier_id: &str,
     project_id: Option<&str>,
+    control: &OnboardControl,
 ) -> Result<OnboardResponse> {
+    let settings = config::current().settings.onboarding.clone();
     let client = Client::builder()
         .connect_timeout(std::time::Duration::from_secs(30))
-        .timeout(std::time::Duration::from_secs(60))
+        .timeout(config::current().request_timeout())
         .build()?;
 
     let mut request = json!({
@@ -172,91 +571,267 @@ pub async fn onboard_user(
         request["cloudaicompanionProject"] = json!(pid);
     }
 
-    let url = format!(
-        "{}/{}:onboardUser",
-        CODE_ASSIST_ENDPOINT, CODE_ASSIST_API_VERSION
-    );
+    let url = method_url("onboardUser");
 
     info!("开始 onboardUser API 调用");
 
-    // 轮询直到长运行操作完成
+    let started = Instant::now();
+    let deadline = Duration::from_millis(settings.deadline_ms);
+    let mut interval = Duration::from_millis(settings.poll_interval_ms);
+    let max_interval = Duration::from_millis(settings.max_interval_ms);
     let mut attempts = 0;
-    const MAX_ATTEMPTS: u32 = 12; // 最多等待 1 分钟
+    let mut operation: Option<String> = None;
 
     loop {
-        let response = client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", access_token))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("onboardUser 失败: {} - {}", status, body);
+        if control.is_cancelled() {
+            return Err(OnboardError::Cancelled {
+                attempts,
+                elapsed_ms: started.elapsed().as_millis() as u64,
+                operation,
+            }
+            .into());
         }
 
-        let lro_res: OnboardResponse = response.json().await?;
+        let lro_res = match &operation {
+            Some(name) => {
+                let response = client
+                    .get(operation_url(name))
+                    .header("Authorization", format!("Bearer {}", access_token))
+                    .send()
+                    .await?;
+                read_operation(response, "查询 onboard 操作").await?
+            }
+            None => {
+                let response = client
+                    .post(&url)
+                    .header("Authorization", format!("Bearer {}", access_token))
+                    .header("Content-Type", "application/json")
+                    .json(&request)
+                    .send()
+                    .await?;
+                read_operation(response, "onboardUser").await?
+            }
+        };
+        attempts += 1;
+
+        if let Some(error) = lro_res.error {
+            return Err(OnboardError::Operation(error).into());
+        }
 
         if lro_res.done {
-            info!("onboardUser 完成");
+            info!("onboardUser 完成（共请求 {} 次）", attempts);
             return Ok(lro_res);
         }
 
-        attempts += 1;
-        if attempts >= MAX_ATTEMPTS {
-            anyhow::bail!("onboardUser 操作超时");
+        if operation.is_none() {
+            operation = lro_res.name.clone();
         }
 
-        info!("等待 onboardUser 完成... ({}/{})", attempts, MAX_ATTEMPTS);
-        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        let elapsed = started.elapsed();
+        if elapsed + interval > deadline {
+            return Err(OnboardError::Timeout {
+                attempts,
+                elapsed_ms: elapsed.as_millis() as u64,
+                deadline_ms: settings.deadline_ms,
+                operation,
+            }
+            .into());
+        }
+
+        let progress = OnboardProgress {
+            attempts,
+            elapsed_ms: elapsed.as_millis() as u64,
+            operation: operation.clone(),
+            next_poll_ms: interval.as_millis() as u64,
+        };
+        info!(
+            "等待 onboardUser 完成... (第 {} 次, 已等待 {}ms)",
+            attempts, progress.elapsed_ms
+        );
+        if let Some(callback) = &control.progress {
+            callback(&progress);
+        }
+
+        if !control.sleep(interval).await {
+            return Err(OnboardError::Cancelled {
+                attempts,
+                elapsed_ms: started.elapsed().as_millis() as u64,
+                operation,
+            }
+            .into());
+        }
+        interval = interval
+            .mul_f64(settings.backoff_multiplier)
+            .min(max_interval);
     }
 }
 
 /// 设置用户结果
 #[derive(Debug, Clone)]
 pub struct SetupUserResult {
-    pub project_id: String,
+    /// 调用方指定的 Project ID
+    pub project_id: Option<String>,
+    /// Code Assist 分配的 Project ID
+    pub temp_project_id: Option<String>,
     pub user_tier: UserTier,
+    pub tier_info: TierInfo,
+}
+
+impl SetupUserResult {
+    /// 实际用于请求的 Project ID
+    pub fn effective_project_id(&self) -> Option<&str> {
+        self.project_id
+            .as_deref()
+            .or(self.temp_project_id.as_deref())
+    }
+
+    /// 写回凭证
+    pub fn apply_to(&self, credential: &mut AntigravityCredentials) {
+        if self.project_id.is_some() {
+            credential.project_id = self.project_id.clone();
+        }
+        if self.temp_project_id.is_some() {
+            credential.temp_project_id = self.temp_project_id.clone();
+        }
+        credential.user_tier = Some(self.user_tier.as_str().to_string());
+        credential.tier_info = Some(self.tier_info.clone());
+        credential.updated_at = Some(Utc::now().to_rfc3339());
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "project_id": self.project_id,
+            "temp_project_id": self.temp_project_id,
+            "user_tier": self.user_tier.as_str(),
+            "tier_info": self.tier_info
+        })
+    }
+}
+
+/// setupUser 失败
+#[derive(Debug, Error, PartialEq)]
+pub enum SetupError {
+    #[error(
+        "层级 {tier_name} ({tier_id}) 需要用户指定的 Google Cloud 项目，但缺少 project_id（可通过参数 project_id、配置 settings.code_assist.project_id 或环境变量 ANTIGRAVITY_PROJECT_ID 提供）"
+    )]
+    ProjectRequired { tier_id: String, tier_name: String },
+    #[error("无效的 Google Cloud Project ID: {0:?}")]
+    InvalidProjectId(String),
+    #[error("账户没有可用的 Code Assist 层级: {}", .0.join("; "))]
+    NoEligibleTier(Vec<String>),
+}
+
+/// onboard 计划
+#[derive(Debug, Clone, PartialEq)]
+struct OnboardPlan {
+    tier_id: String,
+    tier: UserTier,
+    /// 提交给 onboardUser 的项目
+    project_id: Option<String>,
+}
+
+/// 根据 loadCodeAssist 结果确定 onboard 层级和项目
+fn plan_onboard(
+    load_res: &LoadCodeAssistResponse,
+    chosen_project: Option<&str>,
+) -> Result<OnboardPlan, SetupError> {
+    let tier = match select_onboard_tier(load_res) {
+        Some(t) => t,
+        None if !load_res.ineligible_tiers.is_empty() => {
+            return Err(SetupError::NoEligibleTier(
+                load_res
+                    .ineligible_tiers
+                    .iter()
+                    .map(|t| t.to_string())
+                    .collect(),
+            ));
+        }
+        None => {
+            return Ok(OnboardPlan {
+                tier_id: UserTier::Legacy.as_str().to_string(),
+                tier: UserTier::Legacy,
+                project_id: chosen_project
+                    .map(String::from)
+                    .or_else(|| load_res.cloud_ai_companion_project.clone()),
+            })
+        }
+    };
+
+    // Workspace / standard 层级要求使用用户自己的项目
+    if tier.user_defined_cloudaicompanion_project && chosen_project.is_none() {
+        return Err(SetupError::ProjectRequired {
+            tier_id: tier.id.clone(),
+            tier_name: tier.display_name().to_string(),
+        });
+    }
+
+    Ok(OnboardPlan {
+        tier_id: tier.id.clone(),
+        tier: tier.user_tier(),
+        project_id: chosen_project
+            .map(String::from)
+            .or_else(|| load_res.cloud_ai_companion_project.clone()),
+    })
 }
 
 /// 完整的用户设置流程
+///
+/// 项目优先使用 initial_project_id，其次是配置 settings.code_assist.project_id。
 pub async fn setup_user(
     access_token: &str,
     initial_project_id: Option<&str>,
+    control: &OnboardControl,
 ) -> Result<SetupUserResult> {
     info!("开始 setupUser 流程");
 
-    let project_id = initial_project_id.map(String::from);
+    let configured = config::current().settings.code_assist.project_id.clone();
+    let chosen_project = initial_project_id
+        .map(String::from)
+        .or(configured)
+        .filter(|p| !p.is_empty());
+    if let Some(pid) = &chosen_project {
+        if !config::is_valid_project_id(pid) {
+            return Err(SetupError::InvalidProjectId(pid.clone()).into());
+        }
+    }
 
     // 调用 loadCodeAssist
-    let load_res = load_code_assist(access_token, project_id.as_deref()).await?;
-
-    // 如果没有 projectId，尝试从 loadRes 获取
-    let project_id = project_id.or_else(|| load_res.cloud_ai_companion_project.clone());
+    let load_res = load_code_assist(access_token, chosen_project.as_deref()).await?;
 
-    let tier = get_onboard_tier(&load_res);
-    info!("用户层级: {:?}", tier);
+    let tier_info = TierInfo::from(&load_res);
+    let plan = plan_onboard(&load_res, chosen_project.as_deref())?;
+    info!("用户层级: {:?}", plan.tier);
 
     // 调用 onboardUser
-    let lro_res = onboard_user(access_token, tier.as_str(), project_id.as_deref()).await?;
+    let lro_res = onboard_user(
+        access_token,
+        &plan.tier_id,
+        plan.project_id.as_deref(),
+        control,
+    )
+    .await?;
 
-    // 从响应中获取 project_id
-    let final_project_id = lro_res
+    // 从响应中获取 Code Assist 分配的项目
+    let assigned_project_id = lro_res
         .response
         .as_ref()
         .and_then(|r| r["cloudaicompanionProject"]["id"].as_str())
         .map(String::from)
-        .or(project_id)
-        .unwrap_or_default();
+        .or(plan.project_id);
 
-    info!("setupUser 完成，project_id: {}", final_project_id);
+    info!(
+        "setupUser 完成，project_id: {:?}, 分配的项目: {:?}",
+        chosen_project, assigned_project_id
+    );
+
+    // 用户选择的项目记为 project_id，Code Assist 分配的记为 temp_project_id
+    let temp_project_id = assigned_project_id.filter(|p| Some(p) != chosen_project.as_ref());
 
     Ok(SetupUserResult {
-        project_id: final_project_id,
-        user_tier: tier,
+        project_id: chosen_project,
+        temp_project_id,
+        user_tier: plan.tier,
+        tier_info,
     })
 }
 
@@ -269,22 +844,189 @@ mod tests {
         assert_eq!(UserTier::Pro.as_str(), "PRO");
         assert_eq!(UserTier::Free.as_str(), "FREE");
         assert_eq!(UserTier::Legacy.as_str(), "LEGACY");
+        assert_eq!(UserTier::from_id("free-tier"), UserTier::Free);
+        assert_eq!(
+            UserTier::from_id("g1-ultra-tier"),
+            UserTier::Other("g1-ultra-tier".to_string())
+        );
+        assert_eq!(UserTier::from_id("g1-ultra-tier").as_str(), "g1-ultra-tier");
     }
 
     #[test]
-    fn test_get_onboard_tier() {
-        let load_res = LoadCodeAssistResponse {
-            cloud_ai_companion_project: None,
-            current_tier: Some(json!({"id": "PRO"})),
-            allowed_tiers: None,
+    fn test_parse_load_code_assist_response() {
+        let load_res: LoadCodeAssistResponse = serde_json::from_value(json!({
+            "allowedTiers": [{
+                "id": "standard-tier",
+                "name": "Gemini Code Assist",
+                "description": "Unlimited coding assistant",
+                "userDefinedCloudaicompanionProject": true,
+                "isDefault": true,
+                "privacyNotice": {"showNotice": true, "noticeText": "notice"}
+            }],
+            "ineligibleTiers": [{
+                "reasonCode": "INELIGIBLE_ACCOUNT",
+                "reasonMessage": "Not available for this account",
+                "tierId": "free-tier",
+                "tierName": "Gemini Code Assist for individuals"
+            }]
+        }))
+        .unwrap();
+
+        let tier = select_onboard_tier(&load_res).unwrap();
+        assert_eq!(tier.user_tier(), UserTier::Standard);
+        assert!(tier.user_defined_cloudaicompanion_project);
+        assert_eq!(
+            tier.privacy_notice.as_ref().unwrap().notice_text.as_deref(),
+            Some("notice")
+        );
+        assert_eq!(
+            load_res.ineligible_tiers[0].to_string(),
+            "Gemini Code Assist for individuals: Not available for this account (INELIGIBLE_ACCOUNT)"
+        );
+    }
+
+    #[test]
+    fn test_setup_result_apply_to() {
+        let mut credential = AntigravityCredentials {
+            project_id: Some("user-project".to_string()),
+            ..Default::default()
         };
-        assert_eq!(get_onboard_tier(&load_res), UserTier::Pro);
+        let result = SetupUserResult {
+            project_id: None,
+            temp_project_id: Some("assigned-123".to_string()),
+            user_tier: UserTier::Free,
+            tier_info: TierInfo::default(),
+        };
+        result.apply_to(&mut credential);
+        assert_eq!(credential.project_id.as_deref(), Some("user-project"));
+        assert_eq!(credential.temp_project_id.as_deref(), Some("assigned-123"));
+        assert_eq!(credential.user_tier.as_deref(), Some("FREE"));
+        assert_eq!(result.effective_project_id(), Some("assigned-123"));
+    }
 
-        let load_res = LoadCodeAssistResponse {
-            cloud_ai_companion_project: None,
-            current_tier: None,
-            allowed_tiers: Some(json!([{"id": "FREE", "isDefault": true}])),
+    #[test]
+    fn test_onboard_timeout_message() {
+        let err = OnboardError::Timeout {
+            attempts: 4,
+            elapsed_ms: 58_000,
+            deadline_ms: 60_000,
+            operation: Some("operations/abc".to_string()),
         };
+        assert_eq!(
+            err.to_string(),
+            "onboardUser 超时: 已等待 58000ms（上限 60000ms），共请求 4 次，操作 operations/abc 仍未完成"
+        );
+
+        let lro: OnboardResponse =
+            serde_json::from_value(json!({"name": "operations/abc", "done": false})).unwrap();
+        assert_eq!(lro.name.as_deref(), Some("operations/abc"));
+        assert_eq!(
+            operation_url("operations/abc"),
+            "https://cloudcode-pa.googleapis.com/v1internal/operations/abc"
+        );
+    }
+
+    #[test]
+    fn test_plan_onboard_requires_project_for_standard_tier() {
+        let load_res: LoadCodeAssistResponse = serde_json::from_value(json!({
+            "allowedTiers": [{
+                "id": "standard-tier",
+                "name": "Gemini Code Assist",
+                "userDefinedCloudaicompanionProject": true,
+                "isDefault": true
+            }]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            plan_onboard(&load_res, None).unwrap_err(),
+            SetupError::ProjectRequired {
+                tier_id: "standard-tier".to_string(),
+                tier_name: "Gemini Code Assist".to_string(),
+            }
+        );
+
+        let plan = plan_onboard(&load_res, Some("my-workspace-proj")).unwrap();
+        assert_eq!(plan.tier, UserTier::Standard);
+        assert_eq!(plan.project_id.as_deref(), Some("my-workspace-proj"));
+    }
+
+    #[test]
+    fn test_plan_onboard_free_tier_uses_assigned_project() {
+        let load_res: LoadCodeAssistResponse = serde_json::from_value(json!({
+            "cloudaicompanionProject": "assigned-123",
+            "allowedTiers": [{"id": "free-tier", "isDefault": true}]
+        }))
+        .unwrap();
+
+        let plan = plan_onboard(&load_res, None).unwrap();
+        assert_eq!(plan.tier_id, "free-tier");
+        assert_eq!(plan.project_id.as_deref(), Some("assigned-123"));
+    }
+
+    #[test]
+    fn test_wrap_and_unwrap() {
+        let request = json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]});
+        let wrapped = wrap_request("models/gemini-2.5-pro", "proj-1", request.clone());
+        assert_eq!(
+            wrapped,
+            json!({"model": "gemini-2.5-pro", "project": "proj-1", "request": request})
+        );
+
+        let body = json!({"response": {"candidates": []}, "traceId": "t"});
+        assert_eq!(unwrap_response(body), json!({"candidates": []}));
+        assert_eq!(
+            unwrap_response(json!({"candidates": []})),
+            json!({"candidates": []})
+        );
+    }
+
+    #[test]
+    fn test_stream_summary_aggregates_usage() {
+        let mut summary = StreamSummary::default();
+        summary.observe(&json!({
+            "candidates": [{"content": {"parts": [{"text": "a"}]}}],
+            "usageMetadata": {"promptTokenCount": 10, "candidatesTokenCount": 1}
+        }));
+        summary.observe(&json!({
+            "candidates": [{"content": {"parts": [{"text": "b"}]}, "finishReason": "STOP"}],
+            "usageMetadata": {"promptTokenCount": 10, "candidatesTokenCount": 5, "totalTokenCount": 15},
+            "modelVersion": "gemini-2.5-pro"
+        }));
+        assert_eq!(summary.chunks, 2);
+        assert_eq!(
+            summary.usage_metadata,
+            Some(json!({"promptTokenCount": 10, "candidatesTokenCount": 5, "totalTokenCount": 15}))
+        );
+        assert_eq!(summary.finish_reason.as_deref(), Some("STOP"));
+        assert_eq!(summary.model_version.as_deref(), Some("gemini-2.5-pro"));
+    }
+
+    #[test]
+    fn test_method_url() {
+        // 未加载配置时使用 prod 默认值
+        assert_eq!(
+            method_url("loadCodeAssist"),
+            "https://cloudcode-pa.googleapis.com/v1internal:loadCodeAssist"
+        );
+    }
+
+    #[test]
+    fn test_get_onboard_tier() {
+        let load_res: LoadCodeAssistResponse =
+            serde_json::from_value(json!({"currentTier": {"id": "PRO"}})).unwrap();
+        assert_eq!(get_onboard_tier(&load_res), UserTier::Pro);
+
+        let load_res: LoadCodeAssistResponse =
+            serde_json::from_value(json!({"allowedTiers": [{"id": "FREE", "isDefault": true}]}))
+                .unwrap();
         assert_eq!(get_onboard_tier(&load_res), UserTier::Free);
+
+        let load_res: LoadCodeAssistResponse =
+            serde_json::from_value(json!({"currentTier": {"id": "enterprise-tier"}})).unwrap();
+        assert_eq!(
+            get_onboard_tier(&load_res),
+            UserTier::Other("enterprise-tier".to_string())
+        );
     }
 }
@@ -0,0 +1,409 @@
+//! 上游 Google RPC 错误解析
+//!
+//! 错误体形如 `{"error": {"code": 429, "message": "...", "status": "RESOURCE_EXHAUSTED",
+//! "details": [{"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "12s"}]}}`。
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use thiserror::Error;
+
+const TYPE_QUOTA_FAILURE: &str = "type.googleapis.com/google.rpc.QuotaFailure";
+const TYPE_RETRY_INFO: &str = "type.googleapis.com/google.rpc.RetryInfo";
+const TYPE_ERROR_INFO: &str = "type.googleapis.com/google.rpc.ErrorInfo";
+/// 本地判定所有凭证都在冷却时使用的 ErrorInfo.reason
+const COOLING_DOWN_REASON: &str = "CREDENTIALS_COOLING_DOWN";
+
+/// google.rpc.Code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RpcStatus {
+    InvalidArgument,
+    FailedPrecondition,
+    Unauthenticated,
+    PermissionDenied,
+    NotFound,
+    ResourceExhausted,
+    Cancelled,
+    DeadlineExceeded,
+    Unavailable,
+    Internal,
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl RpcStatus {
+    fn parse(status: &str) -> Self {
+        match status {
+            "INVALID_ARGUMENT" => RpcStatus::InvalidArgument,
+            "FAILED_PRECONDITION" => RpcStatus::FailedPrecondition,
+            "UNAUTHENTICATED" => RpcStatus::Unauthenticated,
+            "PERMISSION_DENIED" => RpcStatus::PermissionDenied,
+            "NOT_FOUND" => RpcStatus::NotFound,
+            "RESOURCE_EXHAUSTED" => RpcStatus::ResourceExhausted,
+            "CANCELLED" => RpcStatus::Cancelled,
+            "DEADLINE_EXCEEDED" => RpcStatus::DeadlineExceeded,
+            "UNAVAILABLE" => RpcStatus::Unavailable,
+            "INTERNAL" => RpcStatus::Internal,
+            other => RpcStatus::Other(other.to_string()),
+        }
+    }
+
+    /// 错误体缺少 status 字段时按 HTTP 状态码推断
+    fn from_http(status: u16) -> Self {
+        match status {
+            400 => RpcStatus::InvalidArgument,
+            401 => RpcStatus::Unauthenticated,
+            403 => RpcStatus::PermissionDenied,
+            404 => RpcStatus::NotFound,
+            429 => RpcStatus::ResourceExhausted,
+            499 => RpcStatus::Cancelled,
+            503 => RpcStatus::Unavailable,
+            504 => RpcStatus::DeadlineExceeded,
+            _ => RpcStatus::Internal,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            RpcStatus::InvalidArgument => "INVALID_ARGUMENT",
+            RpcStatus::FailedPrecondition => "FAILED_PRECONDITION",
+            RpcStatus::Unauthenticated => "UNAUTHENTICATED",
+            RpcStatus::PermissionDenied => "PERMISSION_DENIED",
+            RpcStatus::NotFound => "NOT_FOUND",
+            RpcStatus::ResourceExhausted => "RESOURCE_EXHAUSTED",
+            RpcStatus::Cancelled => "CANCELLED",
+            RpcStatus::DeadlineExceeded => "DEADLINE_EXCEEDED",
+            RpcStatus::Unavailable => "UNAVAILABLE",
+            RpcStatus::Internal => "INTERNAL",
+            RpcStatus::Other(s) => s,
+        }
+    }
+}
+
+/// QuotaFailure.violations 中的一项
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuotaViolation {
+    pub subject: String,
+    pub description: String,
+}
+
+/// 上游返回的错误
+#[derive(Debug, Clone, Error, Serialize)]
+#[error("{method} 失败: {http_status} {} - {message}", .status.as_str())]
+pub struct UpstreamError {
+    pub method: String,
+    pub http_status: u16,
+    pub status: RpcStatus,
+    pub message: String,
+    /// RetryInfo.retryDelay，其次是 Retry-After 响应头
+    #[serde(
+        rename = "retry_after_seconds",
+        serialize_with = "serialize_secs",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub retry_after: Option<Duration>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub quota_violations: Vec<QuotaViolation>,
+    /// ErrorInfo.reason，例如 RATE_LIMIT_EXCEEDED
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// 原始 details，Gemini 协议原样返回
+    #[serde(skip)]
+    pub details: Vec<serde_json::Value>,
+}
+
+fn serialize_secs<S: serde::Serializer>(
+    value: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(d) => serializer.serialize_u64(retry_after_secs(*d)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Retry-After 秒数（向上取整，至少 1 秒）
+pub fn retry_after_secs(delay: Duration) -> u64 {
+    let secs = delay.as_secs() + u64::from(delay.subsec_nanos() > 0);
+    secs.max(1)
+}
+
+/// 解析 protobuf Duration 字符串，例如 `12s`、`0.5s`，以及 `1h2m3.5s`、`250ms`
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let mut total = 0f64;
+    let mut number = String::new();
+    let mut chars = value.trim().chars().peekable();
+    let mut matched = false;
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let n: f64 = number.parse().ok()?;
+        number.clear();
+        let unit = match c {
+            'h' => 3600.0,
+            'm' if chars.peek() == Some(&'s') => {
+                chars.next();
+                0.001
+            }
+            'm' => 60.0,
+            's' => 1.0,
+            _ => return None,
+        };
+        total += n * unit;
+        matched = true;
+    }
+    if !number.is_empty() || !matched {
+        return None;
+    }
+    // 超出 Duration 范围的值视为无法解析
+    Duration::try_from_secs_f64(total).ok()
+}
+
+impl UpstreamError {
+    /// 解析非 2xx 响应
+    pub fn from_response(
+        method: &str,
+        http_status: u16,
+        body: &str,
+        retry_after_header: Option<&str>,
+    ) -> Self {
+        let parsed: Option<serde_json::Value> = serde_json::from_str(body).ok();
+        // 部分端点以数组形式返回错误；空数组按 HTTP 状态码处理
+        let error = parsed.as_ref().and_then(|v| match v {
+            serde_json::Value::Array(items) => items.first().map(|i| &i["error"]),
+            other => Some(&other["error"]),
+        });
+        let mut err = match error {
+            Some(error) if error.is_object() => Self::from_error(method, error, http_status),
+            _ => Self {
+                method: method.to_string(),
+                http_status,
+                status: RpcStatus::from_http(http_status),
+                message: body.trim().to_string(),
+                retry_after: None,
+                quota_violations: Vec::new(),
+                reason: None,
+                metadata: BTreeMap::new(),
+                details: Vec::new(),
+            },
+        };
+        err.http_status = http_status;
+        if err.retry_after.is_none() {
+            err.retry_after = retry_after_header
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(Duration::from_secs);
+        }
+        err
+    }
+
+    /// 解析 error 对象（非 2xx 响应体或流中的 error 字段）
+    ///
+    /// error 对象缺少 code / status 时按 fallback_status（响应的 HTTP 状态码）判定。
+    pub fn from_error(method: &str, error: &serde_json::Value, fallback_status: u16) -> Self {
+        let http_status = error["code"]
+            .as_u64()
+            .and_then(|c| u16::try_from(c).ok())
+            .unwrap_or(fallback_status);
+        let status = error["status"]
+            .as_str()
+            .map(RpcStatus::parse)
+            .unwrap_or_else(|| RpcStatus::from_http(http_status));
+        let details = error["details"].as_array().cloned().unwrap_or_default();
+
+        let mut err = Self {
+            method: method.to_string(),
+            http_status,
+            status,
+            message: error["message"].as_str().unwrap_or_default().to_string(),
+            retry_after: None,
+            quota_violations: Vec::new(),
+            reason: None,
+            metadata: BTreeMap::new(),
+            details,
+        };
+        for detail in &err.details {
+            match detail["@type"].as_str() {
+                Some(TYPE_RETRY_INFO) => {
+                    err.retry_after = detail["retryDelay"].as_str().and_then(parse_duration);
+                }
+                Some(TYPE_QUOTA_FAILURE) => {
+                    for violation in detail["violations"].as_array().into_iter().flatten() {
+                        err.quota_violations.push(QuotaViolation {
+                            subject: violation["subject"]
+                                .as_str()
+                                .unwrap_or_default()
+                                .to_string(),
+                            description: violation["description"]
+                                .as_str()
+                                .unwrap_or_default()
+                                .to_string(),
+                        });
+                    }
+                }
+                Some(TYPE_ERROR_INFO) => {
+                    err.reason = detail["reason"].as_str().map(String::from);
+                    if let Some(metadata) = detail["metadata"].as_object() {
+                        err.metadata = metadata
+                            .iter()
+                            .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                            .collect();
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Code Assist 把配额重置时间放在 ErrorInfo.metadata.quotaResetDelay 中
+        if err.retry_after.is_none() {
+            err.retry_after = err
+                .metadata
+                .get("quotaResetDelay")
+                .and_then(|d| parse_duration(d));
+        }
+        err
+    }
+
+    /// 本地判定的配额耗尽（所有凭证都在冷却该模型），渲染方式与上游 429 相同
+    pub fn cooling_down(method: &str, model: &str, retry_after: Option<Duration>) -> Self {
+        Self {
+            method: method.to_string(),
+            http_status: 429,
+            status: RpcStatus::ResourceExhausted,
+            message: format!("All credentials are rate limited for model {}", model),
+            retry_after,
+            quota_violations: Vec::new(),
+            reason: Some(COOLING_DOWN_REASON.to_string()),
+            metadata: BTreeMap::new(),
+            details: Vec::new(),
+        }
+    }
+
+    /// 配额 / 速率限制耗尽
+    pub fn is_resource_exhausted(&self) -> bool {
+        self.status == RpcStatus::ResourceExhausted
+    }
+
+    /// 由 cooling_down 生成：池中所有凭证都在冷却该模型
+    pub fn is_cooling_down(&self) -> bool {
+        self.reason.as_deref() == Some(COOLING_DOWN_REASON)
+    }
+
+    /// 返回给客户端的 HTTP 状态码
+    pub fn client_status(&self) -> u16 {
+        match self.status {
+            RpcStatus::InvalidArgument | RpcStatus::FailedPrecondition => 400,
+            RpcStatus::Unauthenticated => 401,
+            RpcStatus::PermissionDenied => 403,
+            RpcStatus::NotFound => 404,
+            RpcStatus::ResourceExhausted => 429,
+            RpcStatus::Cancelled => 499,
+            RpcStatus::Unavailable => 503,
+            RpcStatus::DeadlineExceeded => 504,
+            RpcStatus::Internal | RpcStatus::Other(_) => 502,
+        }
+    }
+
+    /// Anthropic error.type
+    pub fn anthropic_type(&self) -> &'static str {
+        match self.status {
+            RpcStatus::InvalidArgument | RpcStatus::FailedPrecondition => "invalid_request_error",
+            RpcStatus::Unauthenticated => "authentication_error",
+            RpcStatus::PermissionDenied => "permission_error",
+            RpcStatus::NotFound => "not_found_error",
+            RpcStatus::ResourceExhausted => "rate_limit_error",
+            RpcStatus::Unavailable => "overloaded_error",
+            _ => "api_error",
+        }
+    }
+
+    /// OpenAI error.type 与 error.code
+    pub fn openai_type(&self) -> (&'static str, Option<&'static str>) {
+        match self.status {
+            RpcStatus::InvalidArgument | RpcStatus::FailedPrecondition => {
+                ("invalid_request_error", None)
+            }
+            RpcStatus::Unauthenticated => ("invalid_request_error", Some("invalid_api_key")),
+            RpcStatus::PermissionDenied => ("permission_error", None),
+            RpcStatus::NotFound => ("invalid_request_error", Some("model_not_found")),
+            RpcStatus::ResourceExhausted if !self.quota_violations.is_empty() => {
+                ("insufficient_quota", Some("insufficient_quota"))
+            }
+            RpcStatus::ResourceExhausted => ("rate_limit_error", Some("rate_limit_exceeded")),
+            _ => ("server_error", None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_google_rpc_error() {
+        let body = r#"{"error": {
+            "code": 429,
+            "message": "Resource has been exhausted (e.g. check quota).",
+            "status": "RESOURCE_EXHAUSTED",
+            "details": [
+                {"@type": "type.googleapis.com/google.rpc.ErrorInfo",
+                 "reason": "RATE_LIMIT_EXCEEDED", "domain": "cloudcode-pa.googleapis.com",
+                 "metadata": {"quotaResetDelay": "1m30s", "model": "gemini-2.5-pro"}},
+                {"@type": "type.googleapis.com/google.rpc.QuotaFailure",
+                 "violations": [{"subject": "project", "description": "daily limit"}]},
+                {"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "12.5s"}
+            ]
+        }}"#;
+        let err = UpstreamError::from_response("generateContent", 429, body, Some("60"));
+        assert!(err.is_resource_exhausted());
+        assert_eq!(err.reason.as_deref(), Some("RATE_LIMIT_EXCEEDED"));
+        assert_eq!(err.retry_after, Some(Duration::from_millis(12_500)));
+        assert_eq!(err.quota_violations[0].description, "daily limit");
+        assert_eq!(err.client_status(), 429);
+        assert_eq!(err.anthropic_type(), "rate_limit_error");
+        assert_eq!(err.openai_type().0, "insufficient_quota");
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json["status"], "RESOURCE_EXHAUSTED");
+        assert_eq!(json["retry_after_seconds"], 13);
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let err = UpstreamError::from_response("generateContent", 503, "upstream down", Some("7"));
+        assert_eq!(err.status, RpcStatus::Unavailable);
+        assert_eq!(err.retry_after, Some(Duration::from_secs(7)));
+        assert_eq!(err.anthropic_type(), "overloaded_error");
+
+        let body = r#"[{"error": {"code": 403, "message": "denied", "status": "PERMISSION_DENIED",
+            "details": [{"@type": "type.googleapis.com/google.rpc.ErrorInfo",
+                         "reason": "X", "metadata": {"quotaResetDelay": "250ms"}}]}}]"#;
+        let err = UpstreamError::from_response("streamGenerateContent", 403, body, None);
+        assert_eq!(err.status, RpcStatus::PermissionDenied);
+        assert_eq!(err.retry_after, Some(Duration::from_millis(250)));
+        assert_eq!(err.client_status(), 403);
+
+        let err = UpstreamError::from_response("streamGenerateContent", 429, "[]", None);
+        assert_eq!(err.status, RpcStatus::ResourceExhausted);
+        assert_eq!(err.message, "[]");
+
+        let body = r#"{"error": {"message": "Quota exceeded"}}"#;
+        let err = UpstreamError::from_response("generateContent", 429, body, Some("5"));
+        assert!(err.is_resource_exhausted());
+        assert_eq!(err.client_status(), 429);
+        assert_eq!(err.message, "Quota exceeded");
+        assert_eq!(err.retry_after, Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("12s"), Some(Duration::from_secs(12)));
+        assert_eq!(parse_duration("1h2m3s"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_duration("0.5s"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("12"), None);
+        assert_eq!(parse_duration("abc"), None);
+        assert_eq!(parse_duration("99999999999999999999999s"), None);
+    }
+}
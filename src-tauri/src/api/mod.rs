@@ -1,3 +1,6 @@
 //! API 模块
 
 pub mod code_assist;
+pub mod error;
+pub mod project_cache;
+pub mod sse;
@@ -0,0 +1,306 @@
+//! 按凭证缓存 setupUser 结果（Project ID 与层级）
+//!
+//! 避免每次获取凭证都重复 tokeninfo + userinfo + loadCodeAssist + onboardUser；
+//! 同一凭证的并发解析会合并为一次上游调用。
+
+use super::code_assist::{self, OnboardControl, SetupUserResult};
+use super::error::{RpcStatus, UpstreamError};
+use crate::config;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+struct CacheEntry {
+    /// 解析时使用的用户项目
+    chosen_project: Option<String>,
+    result: SetupUserResult,
+    cached_at: Instant,
+}
+
+static CACHE: Mutex<Option<HashMap<String, CacheEntry>>> = Mutex::new(None);
+static INFLIGHT: Mutex<Option<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> = Mutex::new(None);
+
+fn ttl() -> Duration {
+    Duration::from_secs(
+        config::current()
+            .settings
+            .code_assist
+            .project_cache_ttl_seconds,
+    )
+}
+
+/// 读取未过期的缓存
+pub fn get(credential_id: &str, chosen_project: Option<&str>) -> Option<SetupUserResult> {
+    let ttl = ttl();
+    let mut guard = CACHE.lock().unwrap();
+    let map = guard.get_or_insert_with(HashMap::new);
+    match map.get(credential_id) {
+        Some(entry)
+            if entry.chosen_project.as_deref() == chosen_project
+                && entry.cached_at.elapsed() < ttl =>
+        {
+            Some(entry.result.clone())
+        }
+        Some(_) => {
+            map.remove(credential_id);
+            None
+        }
+        None => None,
+    }
+}
+
+/// 写入缓存
+pub fn put(credential_id: &str, chosen_project: Option<&str>, result: &SetupUserResult) {
+    let ttl = ttl();
+    if ttl.is_zero() {
+        return;
+    }
+    let mut guard = CACHE.lock().unwrap();
+    let map = guard.get_or_insert_with(HashMap::new);
+    // 顺带清理已过期的条目，避免删除的凭证一直留在缓存里
+    map.retain(|_, entry| entry.cached_at.elapsed() < ttl);
+    map.insert(
+        credential_id.to_string(),
+        CacheEntry {
+            chosen_project: chosen_project.map(String::from),
+            result: result.clone(),
+            cached_at: Instant::now(),
+        },
+    );
+}
+
+/// 使缓存失效
+pub fn invalidate(credential_id: &str) {
+    if let Some(map) = CACHE.lock().unwrap().as_mut() {
+        if map.remove(credential_id).is_some() {
+            info!("已清除凭证 {} 的项目缓存", credential_id);
+        }
+    }
+}
+
+/// 上游错误是否表明项目无效（项目不存在、被删除或无权访问）
+///
+/// 只看上游返回的状态码与 ErrorInfo.reason；配额耗尽等其他错误一律不算。
+pub fn is_invalid_project_error(error: &anyhow::Error) -> bool {
+    let Some(e) = error.downcast_ref::<UpstreamError>() else {
+        return false;
+    };
+    if let Some(reason) = e.reason.as_deref() {
+        if INVALID_PROJECT_REASONS.contains(&reason) {
+            return true;
+        }
+    }
+    let message = e.message.to_ascii_lowercase();
+    match e.status {
+        RpcStatus::NotFound | RpcStatus::PermissionDenied => message.contains("project"),
+        RpcStatus::InvalidArgument => {
+            message.contains("invalid project") || message.contains("cloudaicompanionproject")
+        }
+        _ => false,
+    }
+}
+
+/// 表明项目无效的 ErrorInfo.reason
+const INVALID_PROJECT_REASONS: &[&str] = &[
+    "CONSUMER_INVALID",
+    "USER_PROJECT_DENIED",
+    "PROJECT_NOT_FOUND",
+    "PROJECT_DELETED",
+];
+
+/// 上游错误表明项目无效时清除缓存，返回是否清除
+pub fn invalidate_on_error(credential_id: &str, error: &anyhow::Error) -> bool {
+    if is_invalid_project_error(error) {
+        invalidate(credential_id);
+        true
+    } else {
+        false
+    }
+}
+
+fn inflight_lock(credential_id: &str) -> Arc<tokio::sync::Mutex<()>> {
+    INFLIGHT
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .entry(credential_id.to_string())
+        .or_default()
+        .clone()
+}
+
+/// 解析凭证的项目与层级：命中缓存直接返回，否则执行 setupUser 并缓存
+pub async fn resolve(
+    credential_id: &str,
+    access_token: &str,
+    chosen_project: Option<&str>,
+    control: &OnboardControl,
+    force: bool,
+) -> Result<SetupUserResult> {
+    if force {
+        invalidate(credential_id);
+    } else if let Some(hit) = get(credential_id, chosen_project) {
+        debug!("项目缓存命中: {}", credential_id);
+        return Ok(hit);
+    }
+
+    // 同一凭证同时只做一次解析，其余等待结果
+    let lock = inflight_lock(credential_id);
+    let result = {
+        let _guard = lock.lock().await;
+        match get(credential_id, chosen_project) {
+            Some(hit) => Ok(hit),
+            None => setup(credential_id, access_token, chosen_project, control).await,
+        }
+    };
+    release_inflight(credential_id, lock);
+    result
+}
+
+async fn setup(
+    credential_id: &str,
+    access_token: &str,
+    chosen_project: Option<&str>,
+    control: &OnboardControl,
+) -> Result<SetupUserResult> {
+    match code_assist::setup_user(access_token, chosen_project, control).await {
+        Ok(result) => {
+            put(credential_id, chosen_project, &result);
+            Ok(result)
+        }
+        Err(e) => {
+            invalidate_on_error(credential_id, &e);
+            Err(e)
+        }
+    }
+}
+
+/// 没有其他等待者时移除解析锁
+fn release_inflight(credential_id: &str, lock: Arc<tokio::sync::Mutex<()>>) {
+    let mut guard = INFLIGHT.lock().unwrap();
+    let Some(map) = guard.as_mut() else {
+        return;
+    };
+    // map 中一份、当前调用一份；更多引用说明还有调用在等待
+    if map
+        .get(credential_id)
+        .is_some_and(|current| Arc::ptr_eq(current, &lock) && Arc::strong_count(&lock) <= 2)
+    {
+        map.remove(credential_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::code_assist::{TierInfo, UserTier};
+    use serde_json::json;
+
+    #[test]
+    fn test_cache_roundtrip_and_invalidate() {
+        let result = SetupUserResult {
+            project_id: None,
+            temp_project_id: Some("assigned-1".to_string()),
+            user_tier: UserTier::Free,
+            tier_info: TierInfo::default(),
+        };
+        put("cache-test", None, &result);
+        assert_eq!(
+            get("cache-test", None).unwrap().temp_project_id.as_deref(),
+            Some("assigned-1")
+        );
+        // 换了用户项目时不命中
+        assert!(get("cache-test", Some("other-project")).is_none());
+
+        put("cache-test", None, &result);
+        let unavailable = anyhow::Error::new(UpstreamError::from_response(
+            "generateContent",
+            503,
+            "Service Unavailable",
+            None,
+        ));
+        assert!(!invalidate_on_error("cache-test", &unavailable));
+        assert!(get("cache-test", None).is_some());
+        let denied = upstream(
+            403,
+            "PERMISSION_DENIED",
+            "Permission denied on resource project assigned-1",
+        );
+        assert!(invalidate_on_error("cache-test", &denied));
+        assert!(get("cache-test", None).is_none());
+    }
+
+    fn upstream(code: u16, status: &str, message: &str) -> anyhow::Error {
+        let body = json!({"error": {"code": code, "status": status, "message": message}});
+        UpstreamError::from_response("loadCodeAssist", code, &body.to_string(), None).into()
+    }
+
+    #[test]
+    fn test_is_invalid_project_error() {
+        assert!(is_invalid_project_error(&upstream(
+            400,
+            "INVALID_ARGUMENT",
+            "Invalid project ID"
+        )));
+        assert!(is_invalid_project_error(&upstream(
+            404,
+            "NOT_FOUND",
+            "Requested entity was not found: project 1234"
+        )));
+        // 配额错误中的项目编号含 403/404 也不算
+        assert!(!is_invalid_project_error(&upstream(
+            429,
+            "RESOURCE_EXHAUSTED",
+            "Quota exceeded for project 140340404"
+        )));
+        assert!(!is_invalid_project_error(&upstream(
+            400,
+            "INVALID_ARGUMENT",
+            "Request contains an invalid argument."
+        )));
+        // 非上游错误（网络错误等）不算
+        assert!(!is_invalid_project_error(&anyhow::anyhow!(
+            "404 project not found"
+        )));
+
+        let body = json!({"error": {
+            "code": 403,
+            "status": "PERMISSION_DENIED",
+            "message": "Forbidden",
+            "details": [{
+                "@type": "type.googleapis.com/google.rpc.ErrorInfo",
+                "reason": "CONSUMER_INVALID"
+            }]
+        }});
+        let err = UpstreamError::from_response("generateContent", 403, &body.to_string(), None);
+        assert!(is_invalid_project_error(&err.into()));
+    }
+
+    #[tokio::test]
+    async fn test_inflight_lock_released() {
+        let lock = inflight_lock("inflight-test");
+        release_inflight("inflight-test", lock);
+        assert!(INFLIGHT
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|map| !map.contains_key("inflight-test")));
+
+        // 仍有等待者时保留
+        let first = inflight_lock("inflight-test");
+        let second = inflight_lock("inflight-test");
+        release_inflight("inflight-test", first);
+        assert!(INFLIGHT
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|map| map.contains_key("inflight-test")));
+        release_inflight("inflight-test", second);
+        assert!(!INFLIGHT
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|map| map.contains_key("inflight-test")));
+    }
+}
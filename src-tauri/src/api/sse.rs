@@ -0,0 +1,139 @@
+//! Server-Sent Events 解析器
+//!
+//! 按字节增量输入，支持跨块的半行、多行 data、CRLF 换行以及 `:` 开头的 keepalive 注释。
+
+/// 一个完整的 SSE 事件
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    /// 多行 data 以 `\n` 连接
+    pub data: String,
+    pub id: Option<String>,
+}
+
+/// 增量 SSE 解析器
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+    id: Option<String>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 输入一段字节，返回其中已完整的事件
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(bytes);
+
+        let mut events = Vec::new();
+        let mut start = 0;
+        while let Some(pos) = self.buffer[start..].iter().position(|&b| b == b'\n') {
+            let end = start + pos;
+            let mut line = &self.buffer[start..end];
+            if line.last() == Some(&b'\r') {
+                line = &line[..line.len() - 1];
+            }
+            let line = String::from_utf8_lossy(line).into_owned();
+            if let Some(event) = self.process_line(&line) {
+                events.push(event);
+            }
+            start = end + 1;
+        }
+        self.buffer.drain(..start);
+        events
+    }
+
+    /// 输入结束：处理没有换行结尾的最后一行以及未分发的事件
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if !self.buffer.is_empty() {
+            let line = String::from_utf8_lossy(&self.buffer).into_owned();
+            self.buffer.clear();
+            let line = line.trim_end_matches('\r').to_string();
+            if let Some(event) = self.process_line(&line) {
+                return Some(event);
+            }
+        }
+        self.dispatch()
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        // 注释行（常用作 keepalive）
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((f, v)) => (f, v.strip_prefix(' ').unwrap_or(v)),
+            None => (line, ""),
+        };
+        match field {
+            "data" => self.data.push(value.to_string()),
+            "event" => self.event = Some(value.to_string()),
+            "id" => self.id = Some(value.to_string()),
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if self.data.is_empty() {
+            return None;
+        }
+        Some(SseEvent {
+            event,
+            data: std::mem::take(&mut self.data).join("\n"),
+            id: self.id.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_chunks_and_keepalive() {
+        let mut parser = SseParser::new();
+        assert!(parser.feed(b": keepalive\n\nda").is_empty());
+        assert!(parser.feed(b"ta: {\"a\":").is_empty());
+        let events = parser.feed(b" 1}\r\n\r\ndata: {\"b\": 2}\n\n");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].data, "{\"a\": 1}");
+        assert_eq!(events[1].data, "{\"b\": 2}");
+        assert!(parser.finish().is_none());
+    }
+
+    #[test]
+    fn test_multiline_data_and_fields() {
+        let mut parser = SseParser::new();
+        let events = parser.feed(b"event: message\nid: 7\ndata: line1\ndata:line2\n\n");
+        assert_eq!(
+            events,
+            vec![SseEvent {
+                event: Some("message".to_string()),
+                data: "line1\nline2".to_string(),
+                id: Some("7".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_split_utf8_and_unterminated_tail() {
+        let mut parser = SseParser::new();
+        let bytes = "data: 你好\n\ndata: tail".as_bytes();
+        // 在多字节字符中间切开
+        let events = parser.feed(&bytes[..8]);
+        assert!(events.is_empty());
+        let events = parser.feed(&bytes[8..]);
+        assert_eq!(events[0].data, "你好");
+        assert_eq!(parser.finish().unwrap().data, "tail");
+    }
+}
@@ -2,6 +2,7 @@
 
 #![allow(dead_code)]
 
+use crate::config;
 use anyhow::Result;
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::Utc;
@@ -20,6 +21,39 @@ pub const OAUTH_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
 pub const OAUTH_AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
 pub const OAUTH_USERINFO_URL: &str = "https://www.googleapis.com/oauth2/v2/userinfo";
 
+/// Token 端点返回的错误
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{action}失败: {status} - {body}")]
+pub struct OAuthError {
+    pub action: &'static str,
+    pub status: u16,
+    /// 响应体中的 error 字段，例如 `invalid_grant`
+    pub error: Option<String>,
+    pub body: String,
+}
+
+impl OAuthError {
+    fn from_response(action: &'static str, status: u16, body: String) -> Self {
+        let error = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|v| v["error"].as_str().map(String::from));
+        Self {
+            action,
+            status,
+            error,
+            body,
+        }
+    }
+
+    /// 重试也无法恢复的失败（refresh_token 被撤销或过期、客户端无效），需要重新登录
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self.error.as_deref(),
+            Some("invalid_grant" | "invalid_client" | "unauthorized_client")
+        )
+    }
+}
+
 /// PKCE 验证器
 #[derive(Debug, Clone)]
 pub struct PkceVerifier {
@@ -99,7 +133,7 @@ pub async fn exchange_code_for_tokens(
 ) -> Result<TokenResponse> {
     let client = Client::builder()
         .connect_timeout(std::time::Duration::from_secs(30))
-        .timeout(std::time::Duration::from_secs(60))
+        .timeout(config::current().request_timeout())
         .build()?;
 
     debug!("交换授权码获取 tokens");
@@ -122,7 +156,7 @@ pub async fn exchange_code_for_tokens(
     let status = response.status();
     if !status.is_success() {
         let body = response.text().await.unwrap_or_default();
-        anyhow::bail!("Token 交换失败: {} - {}", status, body);
+        return Err(OAuthError::from_response("Token 交换", status.as_u16(), body).into());
     }
 
     let mut token_response: TokenResponse = response.json().await?;
@@ -142,7 +176,7 @@ pub async fn exchange_code_for_tokens(
 pub async fn refresh_access_token(refresh_token: &str) -> Result<TokenResponse> {
     let client = Client::builder()
         .connect_timeout(std::time::Duration::from_secs(30))
-        .timeout(std::time::Duration::from_secs(60))
+        .timeout(config::current().request_timeout())
         .build()?;
 
     debug!("刷新 Google OAuth Token");
@@ -163,7 +197,7 @@ pub async fn refresh_access_token(refresh_token: &str) -> Result<TokenResponse>
     let status = response.status();
     if !status.is_success() {
         let body = response.text().await.unwrap_or_default();
-        anyhow::bail!("Token 刷新失败: {} - {}", status, body);
+        return Err(OAuthError::from_response("Token 刷新", status.as_u16(), body).into());
     }
 
     let mut token_response: TokenResponse = response.json().await?;
@@ -227,12 +261,16 @@ pub async fn fetch_user_info(access_token: &str) -> Result<UserInfo> {
     }
 }
 
-/// 检查 Token 是否有效（本地检查）
+/// 检查 Token 是否有效（本地检查，提前量取自配置 refresh_skew_seconds）
 pub fn is_token_valid(expiry_date: Option<i64>) -> bool {
+    is_token_valid_with_skew(expiry_date, config::current().refresh_skew_millis())
+}
+
+/// 检查 Token 在 skew_ms 毫秒后是否仍然有效
+pub fn is_token_valid_with_skew(expiry_date: Option<i64>, skew_ms: i64) -> bool {
     if let Some(expiry) = expiry_date {
         let now = Utc::now().timestamp_millis();
-        // Token 有效期需要超过 5 分钟
-        expiry > now + 300_000
+        expiry > now + skew_ms
     } else {
         false
     }
@@ -242,7 +280,7 @@ pub fn is_token_valid(expiry_date: Option<i64>) -> bool {
 pub fn is_token_expiring_soon(expiry_date: Option<i64>) -> bool {
     if let Some(expiry) = expiry_date {
         let now = Utc::now().timestamp_millis();
-        let threshold = now + 3600_000; // 1 小时
+        let threshold = now + 3_600_000; // 1 小时
         expiry < threshold
     } else {
         true
@@ -275,7 +313,7 @@ mod tests {
     #[test]
     fn test_is_token_valid() {
         // 有效 token
-        let valid_expiry = Utc::now().timestamp_millis() + 3600_000;
+        let valid_expiry = Utc::now().timestamp_millis() + 3_600_000;
         assert!(is_token_valid(Some(valid_expiry)));
 
         // 即将过期 token（5 分钟内）
@@ -289,4 +327,20 @@ mod tests {
         // 无过期时间
         assert!(!is_token_valid(None));
     }
+
+    #[test]
+    fn test_oauth_error_is_permanent() {
+        let revoked = OAuthError::from_response(
+            "Token 刷新",
+            400,
+            r#"{"error": "invalid_grant", "error_description": "Bad Request"}"#.to_string(),
+        );
+        assert_eq!(revoked.error.as_deref(), Some("invalid_grant"));
+        assert!(revoked.is_permanent());
+        assert!(revoked.to_string().starts_with("Token 刷新失败: 400"));
+
+        let unavailable = OAuthError::from_response("Token 刷新", 503, "upstream".to_string());
+        assert_eq!(unavailable.error, None);
+        assert!(!unavailable.is_permanent());
+    }
 }
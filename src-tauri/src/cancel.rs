@@ -0,0 +1,135 @@
+//! 可取消请求的登记表（按 JSON-RPC 请求 ID）
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use thiserror::Error;
+use tokio::sync::watch;
+
+static REGISTRY: Mutex<Option<HashMap<String, watch::Sender<bool>>>> = Mutex::new(None);
+
+fn key(id: &serde_json::Value) -> String {
+    id.to_string()
+}
+
+/// 取消令牌
+#[derive(Debug, Clone)]
+pub struct CancelToken(watch::Receiver<bool>);
+
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// 等待直到被取消（登记被移除且未取消时永不返回）
+    pub async fn cancelled(&mut self) {
+        loop {
+            if *self.0.borrow_and_update() {
+                return;
+            }
+            if self.0.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// 登记守卫，离开作用域时移除登记
+pub struct CancelGuard(String);
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if let Some(map) = REGISTRY.lock().unwrap().as_mut() {
+            map.remove(&self.0);
+        }
+    }
+}
+
+/// 已有同一 ID 的请求在进行
+#[derive(Debug, Error)]
+#[error("请求 ID {0} 已有进行中的请求")]
+pub struct DuplicateId(pub String);
+
+/// 为请求登记取消令牌
+///
+/// 同一 ID 的请求仍在进行时拒绝登记，否则前一个请求将无法取消，
+/// 且它结束时会移除后一个请求的登记。
+pub fn register(id: &serde_json::Value) -> Result<(CancelToken, CancelGuard), DuplicateId> {
+    let key = key(id);
+    let mut registry = REGISTRY.lock().unwrap();
+    let map = registry.get_or_insert_with(HashMap::new);
+    if map.contains_key(&key) {
+        return Err(DuplicateId(key));
+    }
+    let (tx, rx) = watch::channel(false);
+    map.insert(key.clone(), tx);
+    Ok((CancelToken(rx), CancelGuard(key)))
+}
+
+/// 不登记的取消令牌的控制端（HTTP 代理按连接使用），调用 cancel 或被丢弃时取消
+pub struct Canceller(watch::Sender<bool>);
+
+impl Canceller {
+    pub fn cancel(&self) {
+        let _ = self.0.send(true);
+    }
+}
+
+impl Drop for Canceller {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// 创建不登记的取消令牌
+pub fn token() -> (CancelToken, Canceller) {
+    let (tx, rx) = watch::channel(false);
+    (CancelToken(rx), Canceller(tx))
+}
+
+/// 取消请求，返回请求是否仍在进行
+pub fn cancel(id: &serde_json::Value) -> bool {
+    match REGISTRY
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|m| m.get(&key(id)))
+    {
+        Some(tx) => {
+            let _ = tx.send(true);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_cancel_registered_request() {
+        let id = json!("cancel-test-1");
+        let (mut token, guard) = register(&id).unwrap();
+        assert!(!token.is_cancelled());
+        // 进行中的 ID 不能重复登记，也不影响已有的登记
+        assert!(register(&id).is_err());
+
+        assert!(cancel(&id));
+        token.cancelled().await;
+        assert!(token.is_cancelled());
+
+        drop(guard);
+        assert!(!cancel(&id));
+        assert!(register(&id).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_canceller_cancels_on_drop() {
+        let (mut token, canceller) = token();
+        assert!(!token.is_cancelled());
+        drop(canceller);
+        token.cancelled().await;
+        assert!(token.is_cancelled());
+    }
+}
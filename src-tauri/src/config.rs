@@ -0,0 +1,974 @@
+//! 运行时配置（plugin/config.json + 环境变量 + initialize 参数）
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::info;
+
+/// 指定配置文件路径的环境变量
+pub const CONFIG_PATH_ENV: &str = "ANTIGRAVITY_CONFIG";
+
+/// 环境变量覆盖表：环境变量名 -> 配置路径
+pub const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("ANTIGRAVITY_TIMEOUT_MS", "timeout_ms"),
+    ("ANTIGRAVITY_API_ENVIRONMENT", "settings.api.environment"),
+    (
+        "ANTIGRAVITY_API_BASE_URL_PROD",
+        "settings.api.base_url_prod",
+    ),
+    (
+        "ANTIGRAVITY_API_BASE_URL_DAILY",
+        "settings.api.base_url_daily",
+    ),
+    (
+        "ANTIGRAVITY_API_BASE_URL_AUTOPUSH",
+        "settings.api.base_url_autopush",
+    ),
+    ("ANTIGRAVITY_API_VERSION", "settings.api.api_version"),
+    (
+        "ANTIGRAVITY_SAFETY_HARASSMENT",
+        "settings.safety_settings.harassment",
+    ),
+    (
+        "ANTIGRAVITY_SAFETY_HATE_SPEECH",
+        "settings.safety_settings.hate_speech",
+    ),
+    (
+        "ANTIGRAVITY_SAFETY_SEXUALLY_EXPLICIT",
+        "settings.safety_settings.sexually_explicit",
+    ),
+    (
+        "ANTIGRAVITY_SAFETY_DANGEROUS_CONTENT",
+        "settings.safety_settings.dangerous_content",
+    ),
+    (
+        "ANTIGRAVITY_SAFETY_CIVIC_INTEGRITY",
+        "settings.safety_settings.civic_integrity",
+    ),
+    (
+        "ANTIGRAVITY_TOKEN_AUTO_REFRESH",
+        "settings.token_refresh.auto_refresh",
+    ),
+    (
+        "ANTIGRAVITY_TOKEN_REFRESH_SKEW_SECONDS",
+        "settings.token_refresh.refresh_skew_seconds",
+    ),
+    (
+        "ANTIGRAVITY_TOKEN_MAX_RETRY",
+        "settings.token_refresh.max_retry",
+    ),
+    (
+        "ANTIGRAVITY_TOKEN_REFRESH_RETRY_DELAY_SECONDS",
+        "settings.token_refresh.retry_delay_seconds",
+    ),
+    (
+        "ANTIGRAVITY_REASONING_DEFAULT_EFFORT",
+        "settings.reasoning.default_effort",
+    ),
+    (
+        "ANTIGRAVITY_REASONING_ENABLE_THINKING_MODELS",
+        "settings.reasoning.enable_thinking_models",
+    ),
+    (
+        "ANTIGRAVITY_STRUCTURED_OUTPUT_STRICT",
+        "settings.structured_output.strict",
+    ),
+    (
+        "ANTIGRAVITY_MEDIA_MAX_IMAGE_BYTES",
+        "settings.media.max_image_bytes",
+    ),
+    (
+        "ANTIGRAVITY_MEDIA_MAX_DOCUMENT_BYTES",
+        "settings.media.max_document_bytes",
+    ),
+    (
+        "ANTIGRAVITY_MEDIA_MAX_TOTAL_BYTES",
+        "settings.media.max_total_bytes",
+    ),
+    (
+        "ANTIGRAVITY_FAILOVER_MAX_ATTEMPTS",
+        "settings.failover.max_attempts",
+    ),
+    (
+        "ANTIGRAVITY_FAILOVER_DEFAULT_COOLDOWN_SECONDS",
+        "settings.failover.default_cooldown_seconds",
+    ),
+    (
+        "ANTIGRAVITY_FAILOVER_MAX_COOLDOWN_SECONDS",
+        "settings.failover.max_cooldown_seconds",
+    ),
+    ("ANTIGRAVITY_STORE_DIR", "settings.store.dir"),
+    (
+        "ANTIGRAVITY_ONBOARDING_POLL_INTERVAL_MS",
+        "settings.onboarding.poll_interval_ms",
+    ),
+    (
+        "ANTIGRAVITY_ONBOARDING_BACKOFF_MULTIPLIER",
+        "settings.onboarding.backoff_multiplier",
+    ),
+    (
+        "ANTIGRAVITY_ONBOARDING_MAX_INTERVAL_MS",
+        "settings.onboarding.max_interval_ms",
+    ),
+    (
+        "ANTIGRAVITY_ONBOARDING_DEADLINE_MS",
+        "settings.onboarding.deadline_ms",
+    ),
+    ("ANTIGRAVITY_PROJECT_ID", "settings.code_assist.project_id"),
+    (
+        "ANTIGRAVITY_PROJECT_CACHE_TTL_SECONDS",
+        "settings.code_assist.project_cache_ttl_seconds",
+    ),
+    ("ANTIGRAVITY_HOT_RELOAD", "settings.hot_reload.enabled"),
+    (
+        "ANTIGRAVITY_HOT_RELOAD_POLL_INTERVAL_MS",
+        "settings.hot_reload.poll_interval_ms",
+    ),
+];
+
+/// 配置错误
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("读取配置文件 {path} 失败: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("配置文件 {path} 不是合法 JSON: {source}")]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("配置项 {path} 无效: {message}")]
+    Invalid { path: String, message: String },
+}
+
+impl ConfigError {
+    fn invalid(path: &str, message: impl Into<String>) -> Self {
+        ConfigError::Invalid {
+            path: path.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Code Assist API 环境
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiEnvironment {
+    #[default]
+    Prod,
+    Daily,
+    Autopush,
+}
+
+impl ApiEnvironment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiEnvironment::Prod => "prod",
+            ApiEnvironment::Daily => "daily",
+            ApiEnvironment::Autopush => "autopush",
+        }
+    }
+}
+
+/// 推理强度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+/// Code Assist API 端点（prod 环境默认值）
+pub const CODE_ASSIST_ENDPOINT: &str = "https://cloudcode-pa.googleapis.com";
+pub const CODE_ASSIST_API_VERSION: &str = "v1internal";
+
+/// API 配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiSettings {
+    pub environment: ApiEnvironment,
+    pub base_url_prod: String,
+    pub base_url_daily: String,
+    pub base_url_autopush: String,
+    pub api_version: String,
+}
+
+impl ApiSettings {
+    /// 当前环境对应的 Base URL（不含结尾的 /）
+    pub fn base_url(&self) -> &str {
+        let url = match self.environment {
+            ApiEnvironment::Prod => &self.base_url_prod,
+            ApiEnvironment::Daily => &self.base_url_daily,
+            ApiEnvironment::Autopush => &self.base_url_autopush,
+        };
+        url.trim_end_matches('/')
+    }
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            environment: ApiEnvironment::Prod,
+            base_url_prod: CODE_ASSIST_ENDPOINT.to_string(),
+            base_url_daily: "https://daily-cloudcode-pa.sandbox.googleapis.com".to_string(),
+            base_url_autopush: "https://autopush-cloudcode-pa.sandbox.googleapis.com".to_string(),
+            api_version: CODE_ASSIST_API_VERSION.to_string(),
+        }
+    }
+}
+
+/// 安全设置阈值（按类别）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SafetySettings {
+    pub harassment: Option<String>,
+    pub hate_speech: Option<String>,
+    pub sexually_explicit: Option<String>,
+    pub dangerous_content: Option<String>,
+    pub civic_integrity: Option<String>,
+}
+
+/// 允许的安全阈值
+pub const SAFETY_THRESHOLDS: [&str; 6] = [
+    "HARM_BLOCK_THRESHOLD_UNSPECIFIED",
+    "BLOCK_LOW_AND_ABOVE",
+    "BLOCK_MEDIUM_AND_ABOVE",
+    "BLOCK_ONLY_HIGH",
+    "BLOCK_NONE",
+    "OFF",
+];
+
+pub fn is_valid_threshold(threshold: &str) -> bool {
+    SAFETY_THRESHOLDS.contains(&threshold)
+}
+
+impl SafetySettings {
+    /// (配置项名, 阈值)
+    pub fn entries(&self) -> [(&'static str, Option<&str>); 5] {
+        [
+            ("harassment", self.harassment.as_deref()),
+            ("hate_speech", self.hate_speech.as_deref()),
+            ("sexually_explicit", self.sexually_explicit.as_deref()),
+            ("dangerous_content", self.dangerous_content.as_deref()),
+            ("civic_integrity", self.civic_integrity.as_deref()),
+        ]
+    }
+}
+
+/// Token 刷新配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenRefreshSettings {
+    pub auto_refresh: bool,
+    /// 距离过期多少秒内视为需要刷新
+    pub refresh_skew_seconds: u64,
+    pub max_retry: u32,
+    /// 刷新暂时失败（网络错误、5xx 等）后暂停调度该凭证的秒数
+    pub retry_delay_seconds: u64,
+}
+
+impl Default for TokenRefreshSettings {
+    fn default() -> Self {
+        Self {
+            auto_refresh: true,
+            refresh_skew_seconds: 300,
+            max_retry: 3,
+            retry_delay_seconds: 60,
+        }
+    }
+}
+
+/// 推理配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReasoningSettings {
+    pub default_effort: ReasoningEffort,
+    pub enable_thinking_models: bool,
+}
+
+impl Default for ReasoningSettings {
+    fn default() -> Self {
+        Self {
+            default_effort: ReasoningEffort::Medium,
+            enable_thinking_models: true,
+        }
+    }
+}
+
+/// 结构化输出配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StructuredOutputSettings {
+    /// 响应不符合 response_format 时返回错误（关闭时只记录警告）
+    pub strict: bool,
+}
+
+/// 多模态内联数据大小限制（解码后的字节数）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MediaSettings {
+    pub max_image_bytes: u64,
+    pub max_document_bytes: u64,
+    /// 单个请求中所有内联数据的总量
+    pub max_total_bytes: u64,
+}
+
+impl Default for MediaSettings {
+    fn default() -> Self {
+        Self {
+            max_image_bytes: 7 * 1024 * 1024,
+            max_document_bytes: 20 * 1024 * 1024,
+            max_total_bytes: 20 * 1024 * 1024,
+        }
+    }
+}
+
+/// 配额耗尽时的凭证切换配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FailoverSettings {
+    /// 单个请求最多尝试的凭证数（1 表示不切换）
+    pub max_attempts: u32,
+    /// 上游未给出重试时间时的冷却时长
+    pub default_cooldown_seconds: u64,
+    /// 冷却时长上限
+    pub max_cooldown_seconds: u64,
+}
+
+impl Default for FailoverSettings {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            default_cooldown_seconds: 60,
+            max_cooldown_seconds: 3600,
+        }
+    }
+}
+
+/// 一条模型回退规则
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FallbackChain {
+    /// 匹配请求模型的通配符（支持 * 和 ?），例如 `gemini-3-pro*`
+    pub pattern: String,
+    /// 依次尝试的回退模型
+    pub models: Vec<String>,
+}
+
+/// 模型回退配置（按顺序取第一条匹配的规则）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelFallbackSettings {
+    pub chains: Vec<FallbackChain>,
+}
+
+/// 凭证存储配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreSettings {
+    /// 凭证文件目录（每个凭证一个 JSON 文件），未设置时凭证只保存在内存中
+    pub dir: Option<PathBuf>,
+}
+
+/// Code Assist 用户设置配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CodeAssistSettings {
+    /// 默认的用户 Google Cloud 项目（Workspace / standard 层级必需）
+    pub project_id: Option<String>,
+    /// 按凭证缓存 Project ID 与层级的时间，0 表示不缓存
+    pub project_cache_ttl_seconds: u64,
+}
+
+impl Default for CodeAssistSettings {
+    fn default() -> Self {
+        Self {
+            project_id: None,
+            project_cache_ttl_seconds: 3600,
+        }
+    }
+}
+
+/// onboardUser 长运行操作轮询配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OnboardingSettings {
+    /// 首次轮询间隔
+    pub poll_interval_ms: u64,
+    /// 每次轮询后间隔的放大倍数（1.0 表示固定间隔）
+    pub backoff_multiplier: f64,
+    /// 轮询间隔上限
+    pub max_interval_ms: u64,
+    /// 整个 onboard 过程的截止时间
+    pub deadline_ms: u64,
+}
+
+impl Default for OnboardingSettings {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: 5000,
+            backoff_multiplier: 1.0,
+            max_interval_ms: 30_000,
+            deadline_ms: 60_000,
+        }
+    }
+}
+
+/// 热加载配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HotReloadSettings {
+    pub enabled: bool,
+    pub poll_interval_ms: u64,
+}
+
+impl Default for HotReloadSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            poll_interval_ms: 2000,
+        }
+    }
+}
+
+/// settings 节
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub api: ApiSettings,
+    pub safety_settings: SafetySettings,
+    pub token_refresh: TokenRefreshSettings,
+    pub reasoning: ReasoningSettings,
+    pub structured_output: StructuredOutputSettings,
+    pub media: MediaSettings,
+    pub failover: FailoverSettings,
+    pub model_fallback: ModelFallbackSettings,
+    pub code_assist: CodeAssistSettings,
+    pub onboarding: OnboardingSettings,
+    pub store: StoreSettings,
+    pub hot_reload: HotReloadSettings,
+}
+
+/// 插件配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub enabled: bool,
+    pub timeout_ms: u64,
+    pub settings: Settings,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            timeout_ms: 120_000,
+            settings: Settings::default(),
+        }
+    }
+}
+
+impl Config {
+    /// 上游请求超时
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// Token 刷新提前量（毫秒）
+    pub fn refresh_skew_millis(&self) -> i64 {
+        (self.settings.token_refresh.refresh_skew_seconds as i64).saturating_mul(1000)
+    }
+
+    /// 语义校验
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.timeout_ms == 0 {
+            return Err(ConfigError::invalid("timeout_ms", "必须大于 0"));
+        }
+
+        let api = &self.settings.api;
+        for (path, url) in [
+            ("settings.api.base_url_prod", &api.base_url_prod),
+            ("settings.api.base_url_daily", &api.base_url_daily),
+            ("settings.api.base_url_autopush", &api.base_url_autopush),
+        ] {
+            if !(url.starts_with("https://") || url.starts_with("http://")) {
+                return Err(ConfigError::invalid(
+                    path,
+                    format!("{:?} 不是 http(s) URL", url),
+                ));
+            }
+        }
+        if api.api_version.trim().is_empty() || api.api_version.contains('/') {
+            return Err(ConfigError::invalid(
+                "settings.api.api_version",
+                format!("{:?} 不是合法的 API 版本", api.api_version),
+            ));
+        }
+
+        for (name, threshold) in self.settings.safety_settings.entries() {
+            if let Some(threshold) = threshold {
+                if !is_valid_threshold(threshold) {
+                    return Err(ConfigError::invalid(
+                        &format!("settings.safety_settings.{}", name),
+                        format!(
+                            "{:?} 不是合法的阈值，可选值: {}",
+                            threshold,
+                            SAFETY_THRESHOLDS.join(", ")
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let media = &self.settings.media;
+        for (path, limit) in [
+            ("settings.media.max_image_bytes", media.max_image_bytes),
+            (
+                "settings.media.max_document_bytes",
+                media.max_document_bytes,
+            ),
+            ("settings.media.max_total_bytes", media.max_total_bytes),
+        ] {
+            if limit == 0 {
+                return Err(ConfigError::invalid(path, "必须大于 0"));
+            }
+        }
+
+        let failover = &self.settings.failover;
+        if failover.max_attempts == 0 {
+            return Err(ConfigError::invalid(
+                "settings.failover.max_attempts",
+                "必须至少为 1",
+            ));
+        }
+        if failover.default_cooldown_seconds > failover.max_cooldown_seconds {
+            return Err(ConfigError::invalid(
+                "settings.failover.default_cooldown_seconds",
+                "不能大于 max_cooldown_seconds",
+            ));
+        }
+
+        for (i, chain) in self.settings.model_fallback.chains.iter().enumerate() {
+            if chain.pattern.trim().is_empty() {
+                return Err(ConfigError::invalid(
+                    &format!("settings.model_fallback.chains[{}].pattern", i),
+                    "不能为空",
+                ));
+            }
+            if chain.models.is_empty() || chain.models.iter().any(|m| m.trim().is_empty()) {
+                return Err(ConfigError::invalid(
+                    &format!("settings.model_fallback.chains[{}].models", i),
+                    "必须至少包含一个非空模型",
+                ));
+            }
+            if let Some(model) = chain
+                .models
+                .iter()
+                .find(|m| crate::protocol::models::resolve(m).is_err())
+            {
+                return Err(ConfigError::invalid(
+                    &format!("settings.model_fallback.chains[{}].models", i),
+                    format!("未知模型 {:?}", model),
+                ));
+            }
+        }
+
+        if self.settings.token_refresh.max_retry == 0 {
+            return Err(ConfigError::invalid(
+                "settings.token_refresh.max_retry",
+                "必须至少为 1",
+            ));
+        }
+
+        if let Some(pid) = &self.settings.code_assist.project_id {
+            if !pid.is_empty() && !is_valid_project_id(pid) {
+                return Err(ConfigError::invalid(
+                    "settings.code_assist.project_id",
+                    format!("{:?} 不是合法的 Google Cloud Project ID", pid),
+                ));
+            }
+        }
+
+        let onboarding = &self.settings.onboarding;
+        if onboarding.poll_interval_ms == 0 {
+            return Err(ConfigError::invalid(
+                "settings.onboarding.poll_interval_ms",
+                "必须大于 0",
+            ));
+        }
+        if !(onboarding.backoff_multiplier >= 1.0 && onboarding.backoff_multiplier.is_finite()) {
+            return Err(ConfigError::invalid(
+                "settings.onboarding.backoff_multiplier",
+                format!("{} 必须 >= 1.0", onboarding.backoff_multiplier),
+            ));
+        }
+        if onboarding.max_interval_ms < onboarding.poll_interval_ms {
+            return Err(ConfigError::invalid(
+                "settings.onboarding.max_interval_ms",
+                "不能小于 poll_interval_ms",
+            ));
+        }
+        if onboarding.deadline_ms < onboarding.poll_interval_ms {
+            return Err(ConfigError::invalid(
+                "settings.onboarding.deadline_ms",
+                "不能小于 poll_interval_ms",
+            ));
+        }
+
+        if self.settings.hot_reload.poll_interval_ms < 100 {
+            return Err(ConfigError::invalid(
+                "settings.hot_reload.poll_interval_ms",
+                "不能小于 100",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// 按路径（a.b.c）写入 JSON 值，必要时创建中间对象
+fn set_path(root: &mut Value, path: &str, value: Value) {
+    let mut current = root;
+    let mut parts = path.split('.').peekable();
+    while let Some(part) = parts.next() {
+        if !current.is_object() {
+            *current = Value::Object(Default::default());
+        }
+        let map = current.as_object_mut().unwrap();
+        if parts.peek().is_none() {
+            map.insert(part.to_string(), value);
+            return;
+        }
+        current = map
+            .entry(part.to_string())
+            .or_insert_with(|| Value::Object(Default::default()));
+    }
+}
+
+/// 深度合并：overlay 中的对象逐键覆盖 base
+pub fn merge(base: &mut Value, overlay: &Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (k, v) in overlay {
+                merge(base.entry(k.clone()).or_insert(Value::Null), v);
+            }
+        }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
+/// 环境变量值：能解析为 JSON 标量的按 JSON 处理，否则视为字符串
+/// 始终按字符串处理的配置项（例如纯数字的项目 ID 或目录名）
+const STRING_PATHS: [&str; 2] = ["settings.code_assist.project_id", "settings.store.dir"];
+
+fn env_value(path: &str, raw: &str) -> Value {
+    if STRING_PATHS.contains(&path) {
+        return Value::String(raw.to_string());
+    }
+    match serde_json::from_str::<Value>(raw) {
+        Ok(v @ (Value::Bool(_) | Value::Number(_))) => v,
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+/// 从环境变量收集覆盖项
+pub fn env_overrides<F>(lookup: F) -> Value
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut overlay = Value::Object(Default::default());
+    for (var, path) in ENV_OVERRIDES {
+        if let Some(raw) = lookup(var) {
+            set_path(&mut overlay, path, env_value(path, &raw));
+        }
+    }
+    overlay
+}
+
+/// 将合并后的 JSON 转换为类型化配置并校验
+pub fn from_value(value: Value) -> Result<Config, ConfigError> {
+    let config: Config = serde_path_to_error::deserialize(value).map_err(|e| {
+        let path = e.path().to_string();
+        ConfigError::Invalid {
+            path,
+            message: e.into_inner().to_string(),
+        }
+    })?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// 读取配置文件
+pub fn read_file(path: &Path) -> Result<Value, ConfigError> {
+    let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    serde_json::from_str(&content).map_err(|source| ConfigError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// 查找配置文件：显式路径 > ANTIGRAVITY_CONFIG > 可执行文件旁 > ./plugin/config.json
+pub fn resolve_path(explicit: Option<&Path>) -> Option<PathBuf> {
+    if let Some(p) = explicit {
+        return Some(p.to_path_buf());
+    }
+    if let Ok(p) = std::env::var(CONFIG_PATH_ENV) {
+        return Some(PathBuf::from(p));
+    }
+
+    let mut candidates = Vec::new();
+    if let Some(dir) = std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(Path::to_path_buf))
+    {
+        candidates.push(dir.join("config.json"));
+    }
+    candidates.push(PathBuf::from("plugin/config.json"));
+    candidates.into_iter().find(|p| p.is_file())
+}
+
+/// 检查 Google Cloud Project ID 格式（含 `example.com:project` 形式的域名项目）
+pub fn is_valid_project_id(project_id: &str) -> bool {
+    static PATTERN: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    PATTERN
+        .get_or_init(|| {
+            regex::Regex::new(r"^(?:[a-z0-9][a-z0-9.-]*[a-z0-9]:)?[a-z][a-z0-9-]{4,28}[a-z0-9]$")
+                .unwrap()
+        })
+        .is_match(project_id)
+}
+
+/// 配置来源及当前生效的配置
+struct ConfigState {
+    path: Option<PathBuf>,
+    host_overrides: Value,
+    config: Arc<Config>,
+}
+
+static STATE: RwLock<Option<ConfigState>> = RwLock::new(None);
+
+/// 按 文件 -> 环境变量 -> 宿主覆盖 的顺序构建配置
+fn build(path: Option<&Path>, host_overrides: &Value) -> Result<Config, ConfigError> {
+    let mut value = match path {
+        Some(p) => read_file(p)?,
+        None => Value::Object(Default::default()),
+    };
+    merge(&mut value, &env_overrides(|k| std::env::var(k).ok()));
+    merge(&mut value, host_overrides);
+    from_value(value)
+}
+
+/// 启动时加载配置
+pub fn init(explicit: Option<&Path>) -> Result<Arc<Config>, ConfigError> {
+    let path = resolve_path(explicit);
+    match &path {
+        Some(p) => info!("加载配置文件: {}", p.display()),
+        None => info!("未找到配置文件，使用默认配置"),
+    }
+
+    let host_overrides = Value::Object(Default::default());
+    let config = Arc::new(build(path.as_deref(), &host_overrides)?);
+    *STATE.write().unwrap() = Some(ConfigState {
+        path,
+        host_overrides,
+        config: config.clone(),
+    });
+    Ok(config)
+}
+
+/// 应用 initialize 参数中的配置覆盖
+pub fn apply_host_overrides(overrides: Value) -> Result<Arc<Config>, ConfigError> {
+    let mut guard = STATE.write().unwrap();
+    let path = guard.as_ref().and_then(|s| s.path.clone());
+    let config = Arc::new(build(path.as_deref(), &overrides)?);
+    *guard = Some(ConfigState {
+        path,
+        host_overrides: overrides,
+        config: config.clone(),
+    });
+    Ok(config)
+}
+
+/// 重新读取配置文件（保留宿主覆盖），失败时保持原配置不变
+pub fn reload() -> Result<Arc<Config>, ConfigError> {
+    let mut guard = STATE.write().unwrap();
+    let (path, host_overrides) = match guard.as_ref() {
+        Some(s) => (s.path.clone(), s.host_overrides.clone()),
+        None => (None, Value::Object(Default::default())),
+    };
+    let config = Arc::new(build(path.as_deref(), &host_overrides)?);
+    *guard = Some(ConfigState {
+        path,
+        host_overrides,
+        config: config.clone(),
+    });
+    Ok(config)
+}
+
+/// 当前使用的配置文件路径
+pub fn path() -> Option<PathBuf> {
+    STATE.read().unwrap().as_ref().and_then(|s| s.path.clone())
+}
+
+/// 当前生效的配置（未初始化时为默认配置）
+pub fn current() -> Arc<Config> {
+    STATE
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|s| s.config.clone())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_plugin_config_parses() {
+        let value: Value = serde_json::from_str(include_str!("../../plugin/config.json")).unwrap();
+        let config = from_value(value).unwrap();
+        assert_eq!(config.timeout_ms, 120_000);
+        assert_eq!(config.settings.api.environment, ApiEnvironment::Daily);
+        assert_eq!(
+            config.settings.api.base_url(),
+            "https://daily-cloudcode-pa.sandbox.googleapis.com"
+        );
+        assert_eq!(config.settings.token_refresh.refresh_skew_seconds, 3000);
+        assert_eq!(
+            config.settings.safety_settings.civic_integrity.as_deref(),
+            Some("BLOCK_NONE")
+        );
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let overlay = env_overrides(|k| match k {
+            "ANTIGRAVITY_API_ENVIRONMENT" => Some("autopush".to_string()),
+            "ANTIGRAVITY_TOKEN_MAX_RETRY" => Some("5".to_string()),
+            "ANTIGRAVITY_TOKEN_AUTO_REFRESH" => Some("false".to_string()),
+            _ => None,
+        });
+        let mut value = json!({"settings": {"api": {"environment": "daily"}}});
+        merge(&mut value, &overlay);
+        let config = from_value(value).unwrap();
+        assert_eq!(config.settings.api.environment, ApiEnvironment::Autopush);
+        assert_eq!(
+            config.settings.api.base_url(),
+            "https://autopush-cloudcode-pa.sandbox.googleapis.com"
+        );
+        assert_eq!(config.settings.token_refresh.max_retry, 5);
+        assert!(!config.settings.token_refresh.auto_refresh);
+
+        // gcloud 的 GOOGLE_CLOUD_PROJECT 不影响配置，项目 ID 不会被当作数字
+        let overlay = env_overrides(|k| match k {
+            "GOOGLE_CLOUD_PROJECT" => Some("Not_A_Project".to_string()),
+            "ANTIGRAVITY_STORE_DIR" => Some("2024".to_string()),
+            _ => None,
+        });
+        let config = from_value(overlay).unwrap();
+        assert_eq!(config.settings.code_assist.project_id, None);
+        assert_eq!(config.settings.store.dir, Some(PathBuf::from("2024")));
+        let overlay =
+            env_overrides(|k| (k == "ANTIGRAVITY_PROJECT_ID").then(|| "123456789".to_string()));
+        assert_eq!(
+            overlay["settings"]["code_assist"]["project_id"],
+            json!("123456789")
+        );
+    }
+
+    #[test]
+    fn test_reload_rereads_file_and_keeps_host_overrides() {
+        let path =
+            std::env::temp_dir().join(format!("antigravity-config-{}.json", uuid::Uuid::new_v4()));
+        let host = json!({"settings": {"token_refresh": {"max_retry": 7}}});
+
+        std::fs::write(&path, r#"{"timeout_ms": 1000}"#).unwrap();
+        let config = build(Some(&path), &host).unwrap();
+        assert_eq!(config.timeout_ms, 1000);
+        assert_eq!(config.settings.token_refresh.max_retry, 7);
+
+        let edited = r#"{"timeout_ms": 2000, "settings": {"token_refresh": {"max_retry": 1}}}"#;
+        std::fs::write(&path, edited).unwrap();
+        let config = build(Some(&path), &host).unwrap();
+        assert_eq!(config.timeout_ms, 2000);
+        assert_eq!(config.settings.token_refresh.max_retry, 7);
+
+        // 写坏的文件返回错误，由调用方保留原配置
+        std::fs::write(&path, r#"{"timeout_ms": 0}"#).unwrap();
+        assert!(build(Some(&path), &host).is_err());
+        std::fs::write(&path, "{").unwrap();
+        assert!(build(Some(&path), &host).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_is_valid_project_id() {
+        assert!(is_valid_project_id("my-project-123"));
+        assert!(is_valid_project_id("example.com:my-project"));
+        assert!(!is_valid_project_id("My_Project"));
+        assert!(!is_valid_project_id("abc"));
+        assert!(!is_valid_project_id("trailing-"));
+    }
+
+    #[test]
+    fn test_invalid_config_reports_path() {
+        let err = from_value(json!({"settings": {"api": {"environment": "staging"}}}))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("settings.api.environment"), "{}", err);
+
+        let err = from_value(json!({"settings": {"token_refresh": {"max_retyr": 1}}}))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("settings.token_refresh"), "{}", err);
+        assert!(err.contains("max_retyr"), "{}", err);
+
+        let err = from_value(json!({"timeout_ms": 0}))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "配置项 timeout_ms 无效: 必须大于 0");
+
+        let err =
+            from_value(json!({"settings": {"safety_settings": {"harassment": "BLOCK_SOME"}}}))
+                .unwrap_err()
+                .to_string();
+        assert!(
+            err.contains("settings.safety_settings.harassment"),
+            "{}",
+            err
+        );
+
+        let chains = |models: Value| {
+            json!({"settings": {"model_fallback": {"chains": [
+                {"pattern": "gemini-claude-*", "models": models}
+            ]}}})
+        };
+        assert!(from_value(chains(json!([
+            "gemini-2.5-pro",
+            "gemini-claude-sonnet-4-5"
+        ])))
+        .is_ok());
+        let err = from_value(chains(json!(["gemini-2.5-pro", "gpt-4o"])))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("settings.model_fallback.chains[0].models"),
+            "{}",
+            err
+        );
+        assert!(err.contains("gpt-4o"), "{}", err);
+    }
+}
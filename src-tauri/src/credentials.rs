@@ -1,24 +1,22 @@
 //! Antigravity Provider 凭证数据结构
 
+use crate::api::code_assist::TierInfo;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 
 /// 认证类型
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum AuthType {
     /// Google OAuth 2.0 + PKCE
+    #[default]
     OAuth,
 }
 
-impl Default for AuthType {
-    fn default() -> Self {
-        Self::OAuth
-    }
-}
-
 /// Antigravity 凭证
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct AntigravityCredentials {
     /// 凭证 ID
     pub id: String,
@@ -52,12 +50,21 @@ pub struct AntigravityCredentials {
     /// 临时 Project ID（Code Assist 分配）
     #[serde(default)]
     pub temp_project_id: Option<String>,
+    /// Code Assist 用户层级
+    #[serde(default)]
+    pub user_tier: Option<String>,
+    /// 最近一次 loadCodeAssist 返回的层级信息
+    #[serde(default)]
+    pub tier_info: Option<TierInfo>,
     /// 是否禁用
     #[serde(default)]
     pub disabled: bool,
-    /// 是否健康
+    /// 是否健康（refresh_token 永久失效时为 false，需要重新登录）
     #[serde(default = "default_true")]
     pub is_healthy: bool,
+    /// Token 刷新暂时失败后，在此时间之前不参与调度
+    #[serde(default)]
+    pub refresh_retry_at: Option<String>,
     /// 最后刷新时间
     #[serde(default)]
     pub last_refresh: Option<String>,
@@ -70,6 +77,9 @@ pub struct AntigravityCredentials {
     /// 限流时间
     #[serde(default)]
     pub rate_limited_at: Option<String>,
+    /// 按模型记录的限流冷却截止时间（上游配额按模型计算），冷却中的模型不会调度到该凭证
+    #[serde(default)]
+    pub cooldowns: BTreeMap<String, String>,
     /// 创建时间
     #[serde(default)]
     pub created_at: Option<String>,
@@ -96,18 +106,58 @@ impl Default for AntigravityCredentials {
             email: None,
             project_id: None,
             temp_project_id: None,
+            user_tier: None,
+            tier_info: None,
             disabled: false,
             is_healthy: true,
+            refresh_retry_at: None,
             last_refresh: None,
             last_error: None,
             rate_limit_status: None,
             rate_limited_at: None,
+            cooldowns: BTreeMap::new(),
             created_at: Some(Utc::now().to_rfc3339()),
             updated_at: Some(Utc::now().to_rfc3339()),
         }
     }
 }
 
+impl AntigravityCredentials {
+    /// 复制另一份凭证上的 Token 刷新结果（Token、过期时间与刷新状态），其余字段不变
+    pub fn apply_token_from(&mut self, source: &AntigravityCredentials) {
+        self.access_token = source.access_token.clone();
+        self.refresh_token = source.refresh_token.clone();
+        self.expiry_date = source.expiry_date;
+        self.expire = source.expire.clone();
+        self.last_refresh = source.last_refresh.clone();
+        self.is_healthy = source.is_healthy;
+        self.refresh_retry_at = source.refresh_retry_at.clone();
+        self.last_error = source.last_error.clone();
+    }
+}
+
+/// 由账户信息派生稳定的凭证 ID：优先邮箱，其次 refresh_token、access_token
+///
+/// 同一账户多次登录或多次以内联参数调用时得到相同的 ID，项目缓存等按 ID 记录的状态可以复用。
+pub fn derive_id(
+    email: Option<&str>,
+    refresh_token: Option<&str>,
+    access_token: Option<&str>,
+) -> Option<String> {
+    let source = [
+        ("email", email.map(|e| e.trim().to_lowercase())),
+        ("refresh_token", refresh_token.map(String::from)),
+        ("access_token", access_token.map(String::from)),
+    ]
+    .into_iter()
+    .find_map(|(kind, value)| {
+        value
+            .filter(|v| !v.is_empty())
+            .map(|v| format!("{}:{}", kind, v))
+    })?;
+    Some(hex::encode(&Sha256::digest(source.as_bytes())[..8]))
+}
+
 /// 获取的凭证（用于 API 请求）
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AcquiredCredential {
@@ -142,13 +192,23 @@ pub struct TokenResponse {
     pub scope: Option<String>,
 }
 
-/// Load Code Assist 响应
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LoadCodeAssistResponse {
-    #[serde(rename = "cloudaicompanionProject")]
-    pub cloud_ai_companion_project: Option<String>,
-    #[serde(rename = "currentTier")]
-    pub current_tier: Option<serde_json::Value>,
-    #[serde(rename = "allowedTiers")]
-    pub allowed_tiers: Option<serde_json::Value>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_id_is_stable() {
+        let id = derive_id(Some("Alice@Example.com"), Some("r1"), None).unwrap();
+        assert_eq!(id.len(), 16);
+        assert_eq!(
+            derive_id(Some(" alice@example.com"), Some("r2"), None),
+            Some(id.clone())
+        );
+        assert_ne!(derive_id(None, Some("r1"), None), Some(id));
+        assert_eq!(
+            derive_id(None, None, Some("t")),
+            derive_id(Some(""), None, Some("t"))
+        );
+        assert_eq!(derive_id(None, None, None), None);
+    }
 }
@@ -0,0 +1,772 @@
+//! 上游请求调度：从凭证池选择凭证，确保 Token 和项目可用后转发到 Code Assist
+
+use crate::api::code_assist::{self, OnboardControl, StreamSummary};
+use crate::api::error::UpstreamError;
+use crate::api::project_cache;
+use crate::cancel::CancelToken;
+use crate::config::FallbackChain;
+use crate::credentials::AntigravityCredentials;
+use crate::fallback::{self, ModelFallback};
+use crate::protocol::{self, models, safety, signature, thinking};
+use crate::{config, pool, token_refresh};
+use anyhow::Result;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// 已准备好的凭证
+#[derive(Debug, Clone)]
+pub struct PreparedCredential {
+    pub credential: AntigravityCredentials,
+    pub access_token: String,
+    pub project_id: String,
+}
+
+/// 生成结果
+#[derive(Debug, Clone)]
+pub struct Generated {
+    /// 标准 Gemini 响应（已解包 v1internal 的 response 字段）
+    pub response: serde_json::Value,
+    pub credential_id: String,
+    /// 实际使用的模型
+    pub model: String,
+    /// 依次尝试过的凭证与模型（最后一项为成功的尝试）
+    pub attempts: Vec<Attempt>,
+    /// 发生模型回退时的信息
+    pub fallback: Option<ModelFallback>,
+}
+
+/// 修改池中的凭证（仅限池中已有的凭证），只写入 update 改动的字段
+fn persist(id: &str, update: impl FnOnce(&mut AntigravityCredentials)) {
+    if let Err(e) = pool::update(id, update) {
+        error!("保存凭证 {} 失败: {}", id, e);
+    }
+}
+
+/// 选择凭证并确保 Token 有效、项目已解析
+///
+/// 指定 credential_id 时使用该凭证，否则从池中轮询选择（跳过 exclude 和冷却该模型的凭证）。
+/// 所有凭证都在冷却该模型时返回 RESOURCE_EXHAUSTED。
+pub async fn prepare(
+    credential_id: Option<&str>,
+    exclude: &[String],
+    model: &str,
+) -> Result<PreparedCredential> {
+    prepare_credential(select(credential_id, exclude, model)?).await
+}
+
+/// 选择凭证，不做 Token 与项目检查
+fn select(
+    credential_id: Option<&str>,
+    exclude: &[String],
+    model: &str,
+) -> Result<AntigravityCredentials> {
+    match credential_id {
+        Some(id) => pool::get(id).ok_or_else(|| anyhow::anyhow!("凭证不存在: {}", id)),
+        None => match pool::select(exclude, model) {
+            Some(credential) => Ok(credential),
+            None => {
+                if let Some(until) = pool::earliest_cooldown_end(model) {
+                    let retry_after = (until - chrono::Utc::now()).to_std().ok();
+                    return Err(UpstreamError::cooling_down("dispatch", model, retry_after).into());
+                }
+                anyhow::bail!("凭证池中没有可用凭证")
+            }
+        },
+    }
+}
+
+/// 确保凭证的 Token 有效、项目已解析
+async fn prepare_credential(mut credential: AntigravityCredentials) -> Result<PreparedCredential> {
+    let original = credential.clone();
+
+    let access_token = match token_refresh::ensure_valid_token(&mut credential).await {
+        Ok(t) => t,
+        Err(e) => {
+            let message = e.to_string();
+            if token_refresh::is_permanent_failure(&e) {
+                warn!(
+                    "凭证 {} 的 refresh_token 已失效，需要重新登录",
+                    credential.id
+                );
+                persist(&credential.id, |c| {
+                    c.last_error = Some(message);
+                    c.is_healthy = false;
+                });
+            } else {
+                let delay = config::current().settings.token_refresh.retry_delay_seconds;
+                let retry_at = chrono::Utc::now() + chrono::Duration::seconds(delay as i64);
+                warn!("凭证 {} 刷新 Token 失败，{} 秒后重试", credential.id, delay);
+                persist(&credential.id, |c| {
+                    c.last_error = Some(message);
+                    c.refresh_retry_at = Some(retry_at.to_rfc3339());
+                });
+            }
+            return Err(e);
+        }
+    };
+    if credential.last_refresh != original.last_refresh {
+        persist(&credential.id, |c| c.apply_token_from(&credential));
+    }
+
+    let project_id = match credential
+        .project_id
+        .clone()
+        .or_else(|| credential.temp_project_id.clone())
+    {
+        Some(p) => p,
+        None => {
+            let setup = project_cache::resolve(
+                &credential.id,
+                &access_token,
+                None,
+                &OnboardControl::default(),
+                false,
+            )
+            .await?;
+            setup.apply_to(&mut credential);
+            persist(&credential.id, |c| setup.apply_to(c));
+            setup
+                .effective_project_id()
+                .map(String::from)
+                .ok_or_else(|| anyhow::anyhow!("凭证 {} 没有可用的项目", credential.id))?
+        }
+    };
+
+    Ok(PreparedCredential {
+        credential,
+        access_token,
+        project_id,
+    })
+}
+
+/// 上游报告项目无效时清除缓存和凭证上分配的临时项目，下次请求重新解析
+pub fn handle_upstream_error(prepared: &PreparedCredential, error: &anyhow::Error) {
+    if project_cache::invalidate_on_error(&prepared.credential.id, error) {
+        warn!("凭证 {} 的项目已失效，将重新解析", prepared.credential.id);
+        persist(&prepared.credential.id, |c| c.temp_project_id = None);
+    }
+}
+
+/// 单次上游尝试
+#[derive(Debug, Clone, Serialize)]
+pub struct Attempt {
+    pub credential_id: String,
+    pub model: String,
+    /// 失败时的 Google RPC 状态（非 RPC 错误时为 None）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Attempt {
+    fn succeeded(credential_id: &str, model: &str) -> Self {
+        Self {
+            credential_id: credential_id.to_string(),
+            model: model.to_string(),
+            status: None,
+            error: None,
+        }
+    }
+
+    fn failed(credential_id: &str, model: &str, e: &anyhow::Error) -> Self {
+        Self {
+            credential_id: credential_id.to_string(),
+            model: model.to_string(),
+            status: e
+                .downcast_ref::<UpstreamError>()
+                .map(|u| u.status.as_str().to_string()),
+            error: Some(e.to_string()),
+        }
+    }
+}
+
+/// 配额耗尽（上游 RESOURCE_EXHAUSTED 或所有凭证都在冷却）
+fn is_exhausted(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<UpstreamError>()
+        .is_some_and(|u| u.is_resource_exhausted())
+}
+
+/// 该模型在池中已没有可用凭证（所有凭证都在冷却）
+fn is_cooling_down(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<UpstreamError>()
+        .is_some_and(|u| u.is_cooling_down())
+}
+
+/// 模型失败后能否回退到链中的下一个模型
+///
+/// 只有池中所有凭证都在冷却该模型时才回退；达到 max_attempts、指定了凭证或已经输出内容时不回退。
+fn should_fall_back(e: &anyhow::Error, streamed: bool) -> bool {
+    !streamed && is_cooling_down(e)
+}
+
+/// 没有凭证可选时返回的错误
+///
+/// 之前的尝试因配额耗尽失败、池中再无其他凭证时视为该模型的凭证已全部耗尽，
+/// 返回 cooling_down 以便回退模型；否则返回最近一次失败的错误。
+fn exhausted_error(
+    model: &str,
+    select_error: anyhow::Error,
+    last_error: Option<anyhow::Error>,
+) -> anyhow::Error {
+    if is_cooling_down(&select_error) {
+        return select_error;
+    }
+    match last_error {
+        Some(last) => match last.downcast_ref::<UpstreamError>() {
+            Some(upstream) if upstream.is_resource_exhausted() => {
+                UpstreamError::cooling_down("dispatch", model, upstream.retry_after).into()
+            }
+            _ => last,
+        },
+        None => select_error,
+    }
+}
+
+/// 单个模型上的凭证切换状态
+struct Failover {
+    /// 请求指定了凭证，不切换
+    pinned: bool,
+    max_attempts: usize,
+    /// 已尝试过的凭证（含准备失败的凭证）
+    exclude: Vec<String>,
+    /// 最近一次上游错误，没有凭证可选时返回它
+    last_error: Option<anyhow::Error>,
+}
+
+impl Failover {
+    fn new(credential_id: Option<&str>) -> Self {
+        Self {
+            pinned: credential_id.is_some(),
+            max_attempts: config::current().settings.failover.max_attempts as usize,
+            exclude: Vec::new(),
+            last_error: None,
+        }
+    }
+
+    /// 当前是第几次尝试（从 1 开始）
+    fn attempt(&self) -> usize {
+        self.exclude.len() + 1
+    }
+
+    /// 失败后能否换一个凭证：指定了凭证、已经向客户端输出内容或已尝试 max_attempts 个凭证时不能
+    fn can_switch(&self, streamed: bool) -> bool {
+        !self.pinned && !streamed && self.attempt() < self.max_attempts
+    }
+
+    /// 上游调用失败后是否换一个凭证重试：只在配额耗尽且 can_switch 时切换
+    fn should_retry(&self, e: &anyhow::Error, streamed: bool) -> bool {
+        is_exhausted(e) && self.can_switch(streamed)
+    }
+
+    /// 记录上游调用失败的凭证，准备下一次尝试
+    fn record(&mut self, credential_id: String, e: anyhow::Error) {
+        self.exclude.push(credential_id);
+        self.last_error = Some(e);
+    }
+
+    /// 跳过准备失败的凭证；已有上游错误时保留它，没有凭证可选时仍返回上游错误
+    fn skip(&mut self, credential_id: String, e: anyhow::Error) {
+        self.exclude.push(credential_id);
+        self.last_error.get_or_insert(e);
+    }
+}
+
+/// 处理失败的尝试，返回是否换一个凭证重试
+///
+/// 配额耗尽时把凭证对该模型置入冷却，是否切换由 Failover::should_retry 决定。
+fn fail_over(
+    prepared: &PreparedCredential,
+    model: &str,
+    e: &anyhow::Error,
+    failover: &Failover,
+    streamed: bool,
+) -> bool {
+    handle_upstream_error(prepared, e);
+    let Some(upstream) = e
+        .downcast_ref::<UpstreamError>()
+        .filter(|u| u.is_resource_exhausted())
+    else {
+        return false;
+    };
+
+    let settings = config::current().settings.failover.clone();
+    let delay = upstream
+        .retry_after
+        .unwrap_or(Duration::from_secs(settings.default_cooldown_seconds))
+        .min(Duration::from_secs(settings.max_cooldown_seconds));
+    let id = &prepared.credential.id;
+    match pool::cool_down(id, model, delay, upstream.status.as_str()) {
+        Ok(Some(until)) => warn!(
+            "凭证 {} 的模型 {} 配额耗尽，冷却至 {}",
+            id,
+            model,
+            until.to_rfc3339()
+        ),
+        Ok(None) => {}
+        Err(e) => error!("保存凭证 {} 的冷却状态失败: {}", id, e),
+    }
+
+    let retry = failover.should_retry(e, streamed);
+    if retry {
+        info!(
+            "模型 {} 第 {}/{} 次尝试失败，切换凭证重试",
+            model,
+            failover.attempt(),
+            failover.max_attempts
+        );
+    }
+    retry
+}
+
+/// 请求成功后清除凭证上该模型遗留的限流状态
+fn mark_succeeded(credential_id: &str, model: &str) {
+    if let Err(e) = pool::clear_cooldown(credential_id, model) {
+        error!("保存凭证 {} 失败: {}", credential_id, e);
+    }
+}
+
+/// 选择并准备下一个凭证
+///
+/// 某个凭证准备失败（Token 刷新、项目解析等）时记录这次尝试并换下一个凭证；
+/// 没有凭证可选时返回 exhausted_error，达到 max_attempts 时返回这次准备失败的错误。
+async fn prepare_attempt(
+    credential_id: Option<&str>,
+    model: &str,
+    failover: &mut Failover,
+    attempts: &mut Vec<Attempt>,
+) -> Result<PreparedCredential> {
+    loop {
+        let credential = match select(credential_id, &failover.exclude, model) {
+            Ok(credential) => credential,
+            Err(e) => return Err(exhausted_error(model, e, failover.last_error.take())),
+        };
+        let id = credential.id.clone();
+        match prepare_credential(credential).await {
+            Ok(prepared) => return Ok(prepared),
+            Err(e) => {
+                attempts.push(Attempt::failed(&id, model, &e));
+                if !failover.can_switch(false) {
+                    return Err(e);
+                }
+                warn!("凭证 {} 准备失败，换用其他凭证: {}", id, e);
+                failover.skip(id, e);
+            }
+        }
+    }
+}
+
+/// 本次请求依次尝试的模型（fallback 为 false 时只有请求的模型）
+///
+/// 回退规则按客户端请求的模型名匹配，返回的也是客户端模型名，调用上游前再换成上游模型 ID；
+/// 对应同一个上游模型的别名只保留第一个。
+fn model_chain(model: &str, fallback: bool) -> Vec<String> {
+    let config = config::current();
+    let chains: &[FallbackChain] = if fallback {
+        &config.settings.model_fallback.chains
+    } else {
+        &[]
+    };
+    chain_for(model, chains)
+}
+
+fn chain_for(model: &str, chains: &[FallbackChain]) -> Vec<String> {
+    let model = model.strip_prefix("models/").unwrap_or(model);
+    let models = fallback::chain(model, chains);
+    let mut upstream = Vec::new();
+    models
+        .into_iter()
+        .filter(|m| {
+            let id = protocol::upstream_model(m).to_string();
+            let first = !upstream.contains(&id);
+            upstream.push(id);
+            first
+        })
+        .collect()
+}
+
+/// 按模型调整请求：检查工具、图片和输入长度，截断输出上限与思考预算，不支持思考时去掉思考配置
+///
+/// 模型无法处理该请求时返回错误。
+fn fit_request(model: &str, request: &serde_json::Value) -> Result<serde_json::Value> {
+    let mut request = request.clone();
+    if let Ok(info) = models::resolve(model) {
+        info.validate(&request)?;
+        info.check_input(&request)?;
+        info.clamp_request(&mut request);
+    }
+    thinking::fit(&mut request, protocol::upstream_model(model));
+    Ok(request)
+}
+
+/// 使用池中凭证调用 generateContent
+///
+/// model 为客户端请求的模型名（可以是 gemini-claude-* 等别名）。
+/// 配额耗尽时先切换凭证重试；池中所有凭证都在冷却该模型且 fallback 为 true 时按配置的回退链
+/// 换用其他模型（跳过无法处理该请求的回退模型），
+/// 此时响应上带有 modelFallback 标记。
+pub async fn generate_content(
+    model: &str,
+    mut request: serde_json::Value,
+    credential_id: Option<&str>,
+    fallback: bool,
+) -> Result<Generated> {
+    safety::apply(&mut request, &config::current().settings.safety_settings)?;
+    let models = model_chain(model, fallback);
+    let mut attempts = Vec::new();
+    let mut last_error = None;
+
+    for (i, model) in models.iter().enumerate() {
+        let request = match fit_request(model, &request) {
+            Ok(request) => request,
+            Err(e) if i > 0 => {
+                warn!("回退模型 {} 无法处理该请求，跳过: {}", model, e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let upstream = protocol::upstream_model(model);
+        match generate_with_model(upstream, request, credential_id, &mut attempts).await {
+            Ok((mut response, credential_id)) => {
+                let fallback = (i > 0).then(|| ModelFallback {
+                    requested_model: models[0].clone(),
+                    model: model.clone(),
+                });
+                if let Some(fallback) = &fallback {
+                    fallback.annotate(&mut response);
+                }
+                return Ok(Generated {
+                    response,
+                    credential_id,
+                    model: model.clone(),
+                    attempts,
+                    fallback,
+                });
+            }
+            Err(e) if should_fall_back(&e, false) && i + 1 < models.len() => {
+                warn!("模型 {} 配额耗尽，尝试回退模型", model);
+                last_error = Some(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error.expect("模型回退链至少包含请求的模型"))
+}
+
+/// 使用单个模型调用 generateContent，配额耗尽时切换凭证重试
+async fn generate_with_model(
+    model: &str,
+    mut request: serde_json::Value,
+    credential_id: Option<&str>,
+    attempts: &mut Vec<Attempt>,
+) -> Result<(serde_json::Value, String)> {
+    let history = signature::restore_request(&mut request, thinking::supports_thinking(model));
+    let mut failover = Failover::new(credential_id);
+    loop {
+        let prepared = prepare_attempt(credential_id, model, &mut failover, attempts).await?;
+        let id = prepared.credential.id.clone();
+
+        match code_assist::generate_content(
+            &prepared.access_token,
+            &prepared.project_id,
+            model,
+            request.clone(),
+        )
+        .await
+        {
+            Ok(response) => {
+                mark_succeeded(&id, model);
+                attempts.push(Attempt::succeeded(&id, model));
+                signature::remember_response(&history, &response);
+                if let Some(block) = safety::blocked(&response) {
+                    warn!(
+                        "模型 {} 的 {} 被安全策略拦截: {} {:?}",
+                        model, block.source, block.reason, block.categories
+                    );
+                }
+                return Ok((response, id));
+            }
+            Err(e) => {
+                attempts.push(Attempt::failed(&id, model, &e));
+                if !fail_over(&prepared, model, &e, &failover, false) {
+                    return Err(e);
+                }
+                failover.record(id, e);
+            }
+        }
+    }
+}
+
+/// 流式生成结果
+#[derive(Debug, Clone)]
+pub struct Streamed {
+    pub summary: StreamSummary,
+    pub credential_id: String,
+    /// 实际使用的模型
+    pub model: String,
+    pub attempts: Vec<Attempt>,
+    pub fallback: Option<ModelFallback>,
+}
+
+/// 使用池中凭证调用 streamGenerateContent，每个分块交给 on_chunk
+///
+/// model 为客户端请求的模型名，回退规则与 generate_content 相同。
+/// 只有在还没有分块交给 on_chunk 时才会切换凭证或回退模型，已经输出的内容不会重复；
+/// 回退后的每个分块都带有 modelFallback 标记。
+pub async fn stream_generate_content<F>(
+    model: &str,
+    mut request: serde_json::Value,
+    credential_id: Option<&str>,
+    cancel: Option<CancelToken>,
+    fallback: bool,
+    mut on_chunk: F,
+) -> Result<Streamed>
+where
+    F: FnMut(serde_json::Value) + Send,
+{
+    safety::apply(&mut request, &config::current().settings.safety_settings)?;
+    let models = model_chain(model, fallback);
+    let mut attempts = Vec::new();
+    let mut last_error = None;
+
+    for (i, model) in models.iter().enumerate() {
+        let request = match fit_request(model, &request) {
+            Ok(request) => request,
+            Err(e) if i > 0 => {
+                warn!("回退模型 {} 无法处理该请求，跳过: {}", model, e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let fallback = (i > 0).then(|| ModelFallback {
+            requested_model: models[0].clone(),
+            model: model.clone(),
+        });
+        let mut streamed = false;
+        let result = stream_with_model(
+            protocol::upstream_model(model),
+            request,
+            credential_id,
+            cancel.clone(),
+            &mut attempts,
+            |mut chunk| {
+                streamed = true;
+                if let Some(fallback) = &fallback {
+                    fallback.annotate(&mut chunk);
+                }
+                on_chunk(chunk)
+            },
+        )
+        .await;
+        match result {
+            Ok((summary, credential_id)) => {
+                return Ok(Streamed {
+                    summary,
+                    credential_id,
+                    model: model.clone(),
+                    attempts,
+                    fallback,
+                });
+            }
+            Err(e) if should_fall_back(&e, streamed) && i + 1 < models.len() => {
+                warn!("模型 {} 配额耗尽，尝试回退模型", model);
+                last_error = Some(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error.expect("模型回退链至少包含请求的模型"))
+}
+
+/// 使用单个模型调用 streamGenerateContent，配额耗尽且尚未输出内容时切换凭证重试
+async fn stream_with_model<F>(
+    model: &str,
+    mut request: serde_json::Value,
+    credential_id: Option<&str>,
+    cancel: Option<CancelToken>,
+    attempts: &mut Vec<Attempt>,
+    mut on_chunk: F,
+) -> Result<(StreamSummary, String)>
+where
+    F: FnMut(serde_json::Value) + Send,
+{
+    let history = signature::restore_request(&mut request, thinking::supports_thinking(model));
+    let mut failover = Failover::new(credential_id);
+    loop {
+        let prepared = prepare_attempt(credential_id, model, &mut failover, attempts).await?;
+        let id = prepared.credential.id.clone();
+        let mut streamed = false;
+
+        let result = code_assist::stream_generate_content(
+            &prepared.access_token,
+            &prepared.project_id,
+            model,
+            request.clone(),
+            cancel.clone(),
+            |chunk| {
+                streamed = true;
+                signature::remember_response(&history, &chunk);
+                on_chunk(chunk)
+            },
+        )
+        .await;
+        match result {
+            Ok(summary) => {
+                mark_succeeded(&id, model);
+                attempts.push(Attempt::succeeded(&id, model));
+                return Ok((summary, id));
+            }
+            Err(e) => {
+                attempts.push(Attempt::failed(&id, model, &e));
+                if !fail_over(&prepared, model, &e, &failover, streamed) {
+                    return Err(e);
+                }
+                failover.record(id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(pinned: bool, max_attempts: usize) -> Failover {
+        Failover {
+            pinned,
+            max_attempts,
+            exclude: Vec::new(),
+            last_error: None,
+        }
+    }
+
+    fn exhausted() -> anyhow::Error {
+        UpstreamError::from_response("generateContent", 429, "quota", None).into()
+    }
+
+    #[test]
+    fn test_failover_bounded_by_max_attempts() {
+        let mut failover = policy(false, 3);
+        assert!(failover.should_retry(&exhausted(), false));
+        failover.record("a".to_string(), exhausted());
+        assert!(failover.should_retry(&exhausted(), false));
+        failover.record("b".to_string(), exhausted());
+        assert_eq!(failover.attempt(), 3);
+        assert!(!failover.should_retry(&exhausted(), false));
+
+        // 只有配额耗尽才切换
+        let unavailable: anyhow::Error =
+            UpstreamError::from_response("generateContent", 503, "down", None).into();
+        assert!(!policy(false, 3).should_retry(&unavailable, false));
+        assert!(!policy(false, 1).should_retry(&exhausted(), false));
+    }
+
+    #[test]
+    fn test_failover_not_after_first_chunk_or_when_pinned() {
+        assert!(!policy(false, 3).should_retry(&exhausted(), true));
+        assert!(!policy(true, 3).should_retry(&exhausted(), false));
+        assert!(!policy(true, 3).can_switch(false));
+    }
+
+    #[test]
+    fn test_prepare_failure_keeps_upstream_error() {
+        let mut failover = policy(false, 5);
+        failover.record("a".to_string(), exhausted());
+        failover.skip("b".to_string(), anyhow::anyhow!("Token 刷新失败"));
+        assert_eq!(failover.exclude, ["a", "b"]);
+        assert!(is_exhausted(failover.last_error.as_ref().unwrap()));
+
+        let mut failover = policy(false, 5);
+        failover.skip("b".to_string(), anyhow::anyhow!("Token 刷新失败"));
+        assert!(!is_exhausted(failover.last_error.as_ref().unwrap()));
+        assert!(failover.can_switch(false));
+    }
+
+    #[test]
+    fn test_model_chain_falls_back_only_when_pool_exhausted() {
+        let cooling: anyhow::Error =
+            UpstreamError::cooling_down("dispatch", "gemini-3-pro-preview", None).into();
+        assert!(should_fall_back(&cooling, false));
+        // 已经输出内容时不回退
+        assert!(!should_fall_back(&cooling, true));
+        // 达到 max_attempts 返回的上游 429 不回退
+        assert!(!should_fall_back(&exhausted(), false));
+
+        // 池中再无凭证：之前是配额耗尽时按冷却处理，保留上游的重试时间
+        let body = r#"{"error": {"code": 429, "status": "RESOURCE_EXHAUSTED", "details": [
+            {"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "9s"}]}}"#;
+        let last: anyhow::Error =
+            UpstreamError::from_response("generateContent", 429, body, None).into();
+        let e = exhausted_error("m", anyhow::anyhow!("凭证池中没有可用凭证"), Some(last));
+        assert!(should_fall_back(&e, false));
+        assert_eq!(
+            e.downcast_ref::<UpstreamError>().unwrap().retry_after,
+            Some(Duration::from_secs(9))
+        );
+        let e = exhausted_error("m", cooling, Some(anyhow::anyhow!("Token 刷新失败")));
+        assert!(is_cooling_down(&e));
+
+        // 其他失败原样返回，不回退
+        let e = exhausted_error(
+            "m",
+            anyhow::anyhow!("凭证池中没有可用凭证"),
+            Some(anyhow::anyhow!("Token 刷新失败")),
+        );
+        assert!(!should_fall_back(&e, false));
+        assert_eq!(e.to_string(), "Token 刷新失败");
+        let e = exhausted_error("m", anyhow::anyhow!("凭证池中没有可用凭证"), None);
+        assert_eq!(e.to_string(), "凭证池中没有可用凭证");
+    }
+
+    #[test]
+    fn test_chain_uses_client_model_names() {
+        let chains = [FallbackChain {
+            pattern: "gemini-claude-*".to_string(),
+            models: vec![
+                "gemini-claude-sonnet-4-5".to_string(),
+                "claude-sonnet-4-5".to_string(),
+                "gemini-2.5-pro".to_string(),
+            ],
+        }];
+        // 按客户端模型名匹配；与已有模型对应同一个上游模型的别名去掉
+        assert_eq!(
+            chain_for("gemini-claude-opus-4-1", &chains),
+            [
+                "gemini-claude-opus-4-1",
+                "gemini-claude-sonnet-4-5",
+                "gemini-2.5-pro"
+            ]
+        );
+        assert_eq!(chain_for("claude-opus-4-1", &chains), ["claude-opus-4-1"]);
+        assert_eq!(chain_for("models/gemini-2.5-pro", &[]), ["gemini-2.5-pro"]);
+        assert_eq!(
+            protocol::upstream_model(&chain_for("gemini-claude-opus-4-1", &chains)[1]),
+            "claude-sonnet-4-5"
+        );
+    }
+
+    #[test]
+    fn test_fit_request_for_fallback_model() {
+        let request = serde_json::json!({
+            "contents": [{"role": "user", "parts": [{"text": "hi"}]}],
+            "generationConfig": {
+                "maxOutputTokens": 100_000,
+                "thinkingConfig": {"thinkingBudget": 32768, "includeThoughts": true}
+            }
+        });
+        let fitted = fit_request("gemini-2.5-flash", &request).unwrap();
+        assert_eq!(fitted["generationConfig"]["maxOutputTokens"], 65_536);
+        assert_eq!(
+            fitted["generationConfig"]["thinkingConfig"]["thinkingBudget"],
+            24576
+        );
+        let fitted = fit_request("gemini-claude-sonnet-4-5", &request).unwrap();
+        assert!(fitted["generationConfig"].get("thinkingConfig").is_none());
+
+        let mut with_tools = request.clone();
+        with_tools["tools"] = serde_json::json!([{"functionDeclarations": [{"name": "f"}]}]);
+        assert!(fit_request("gemini-3-pro-image-preview", &with_tools).is_err());
+        assert!(fit_request("gemini-2.5-pro", &with_tools).is_ok());
+    }
+}
@@ -0,0 +1,125 @@
+//! 模型回退链：请求的模型在所有凭证上都配额耗尽时，按配置依次换用其他模型
+
+use crate::config::FallbackChain;
+use serde::Serialize;
+
+/// 回退后写入 Gemini 响应（及每个流式分块）的字段，协议转换时据此填充 model_fallback
+pub const RESPONSE_FIELD: &str = "modelFallback";
+
+/// 发生回退时返回给客户端的信息
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelFallback {
+    /// 客户端请求的模型
+    pub requested_model: String,
+    /// 实际使用的模型
+    pub model: String,
+}
+
+impl ModelFallback {
+    /// 读取 Gemini 响应上的回退标记
+    pub fn from_response(response: &serde_json::Value) -> Option<Self> {
+        let field = &response[RESPONSE_FIELD];
+        Some(Self {
+            requested_model: field["requestedModel"].as_str()?.to_string(),
+            model: field["model"].as_str()?.to_string(),
+        })
+    }
+
+    /// 标记 Gemini 响应
+    pub fn annotate(&self, response: &mut serde_json::Value) {
+        if response.is_object() {
+            response[RESPONSE_FIELD] = serde_json::json!({
+                "requestedModel": self.requested_model,
+                "model": self.model
+            });
+        }
+    }
+}
+
+/// 简单通配符匹配：`*` 匹配任意长度，`?` 匹配单个字符
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // 最近一个 * 的位置及其当时对应的文本位置
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// 按顺序尝试的模型：请求的模型在前，其后为第一条匹配规则中的回退模型（去重）
+pub fn chain(model: &str, chains: &[FallbackChain]) -> Vec<String> {
+    let mut models = vec![model.to_string()];
+    if let Some(rule) = chains.iter().find(|c| glob_match(&c.pattern, model)) {
+        for fallback in &rule.models {
+            if !models.contains(fallback) {
+                models.push(fallback.clone());
+            }
+        }
+    }
+    models
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("gemini-3-pro*", "gemini-3-pro-preview"));
+        assert!(glob_match("*-pro", "gemini-2.5-pro"));
+        assert!(glob_match("gemini-?.5-*", "gemini-2.5-flash"));
+        assert!(glob_match("*", "anything"));
+        assert!(!glob_match("gemini-3-pro*", "gemini-2.5-pro"));
+        assert!(!glob_match("gemini-2.5-pro", "gemini-2.5-pro-exp"));
+    }
+
+    #[test]
+    fn test_chain() {
+        let chains = vec![
+            FallbackChain {
+                pattern: "gemini-3-pro*".to_string(),
+                models: vec!["gemini-2.5-pro".to_string(), "gemini-2.5-flash".to_string()],
+            },
+            FallbackChain {
+                pattern: "gemini-*".to_string(),
+                models: vec!["gemini-2.5-flash".to_string()],
+            },
+        ];
+        assert_eq!(
+            chain("gemini-3-pro-preview", &chains),
+            vec!["gemini-3-pro-preview", "gemini-2.5-pro", "gemini-2.5-flash"]
+        );
+        assert_eq!(chain("gemini-2.5-flash", &chains), vec!["gemini-2.5-flash"]);
+        assert_eq!(
+            chain("claude-sonnet-4-5", &chains),
+            vec!["claude-sonnet-4-5"]
+        );
+    }
+
+    #[test]
+    fn test_annotate_roundtrip() {
+        let fallback = ModelFallback {
+            requested_model: "gemini-3-pro-preview".to_string(),
+            model: "gemini-2.5-pro".to_string(),
+        };
+        let mut response = serde_json::json!({"candidates": []});
+        fallback.annotate(&mut response);
+        assert_eq!(ModelFallback::from_response(&response), Some(fallback));
+        assert_eq!(ModelFallback::from_response(&serde_json::json!({})), None);
+    }
+}
@@ -4,7 +4,17 @@
 
 mod api;
 mod auth;
+mod cancel;
+mod config;
 mod credentials;
+mod dispatch;
+mod fallback;
+mod notification;
+mod pool;
+mod protocol;
+mod proxy;
+mod reload;
+mod session;
 mod token_refresh;
 
 use anyhow::Result;
@@ -12,7 +22,9 @@ use clap::{Parser, Subcommand};
 use credentials::{AcquiredCredential, AuthType, AntigravityCredentials};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::io::{self, BufRead, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tracing::{error, info};
 use tracing_subscriber::EnvFilter;
 
@@ -22,6 +34,10 @@ use tracing_subscriber::EnvFilter;
 #[command(about = "Antigravity Provider - Google Gemini CLI OAuth credential provider")]
 #[command(version)]
 struct Cli {
+    /// 配置文件路径（默认查找可执行文件旁的 config.json 或 ./plugin/config.json）
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -30,6 +46,12 @@ struct Cli {
 enum Commands {
     /// 启动 JSON-RPC 服务
     Serve,
+    /// 启动本地 HTTP 代理（Anthropic / OpenAI / Gemini 接口）
+    Proxy {
+        /// 监听地址
+        #[arg(long, default_value = "127.0.0.1:8045")]
+        listen: SocketAddr,
+    },
     /// 获取版本信息
     Version,
 }
@@ -84,6 +106,24 @@ impl JsonRpcResponse {
             id,
         }
     }
+
+    fn error_with_data(
+        id: serde_json::Value,
+        code: i32,
+        message: String,
+        data: serde_json::Value,
+    ) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            result: None,
+            error: Some(JsonRpcError {
+                code,
+                message,
+                data: Some(data),
+            }),
+            id,
+        }
+    }
 }
 
 /// 处理 JSON-RPC 请求
@@ -101,6 +141,14 @@ async fn handle_request(request: JsonRpcRequest) -> JsonRpcResponse {
         "validate_credential" => handle_validate_credential(id, request.params).await,
         "get_auth_url" => handle_get_auth_url(id, request.params).await,
         "exchange_code" => handle_exchange_code(id, request.params).await,
+        "setup_user" => handle_setup_user(id, request.params).await,
+        "load_code_assist" => handle_load_code_assist(id, request.params).await,
+        "cancel_request" => handle_cancel_request(id, request.params).await,
+        "generate_content" => handle_generate_content(id, request.params).await,
+        "stream_generate_content" => handle_stream_generate_content(id, request.params).await,
+        "create_message" => handle_create_message(id, request.params).await,
+        "chat_completion" => handle_chat_completion(id, request.params).await,
+        "list_models" => handle_list_models(id).await,
         "health_check" => handle_health_check(id).await,
         "shutdown" => handle_shutdown(id).await,
         _ => JsonRpcResponse::error(id, -32601, format!("Method not found: {}", request.method)),
@@ -110,21 +158,66 @@ async fn handle_request(request: JsonRpcRequest) -> JsonRpcResponse {
 /// 初始化
 async fn handle_initialize(
     id: serde_json::Value,
-    _params: Option<serde_json::Value>,
+    params: Option<serde_json::Value>,
 ) -> JsonRpcResponse {
     info!("初始化 Antigravity Provider");
 
+    let init_params: session::InitializeParams = match params {
+        Some(p) => match serde_json::from_value(p) {
+            Ok(p) => p,
+            Err(e) => return JsonRpcResponse::error(id, -32602, format!("Invalid params: {}", e)),
+        },
+        None => session::InitializeParams::default(),
+    };
+
+    let negotiated = match session::negotiate(&init_params) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("协议协商失败: {}", e);
+            return JsonRpcResponse::error_with_data(
+                id,
+                -32002,
+                e.to_string(),
+                json!({
+                    "protocol_version": session::PROTOCOL_VERSION,
+                    "min_protocol_version": session::MIN_PROTOCOL_VERSION,
+                    "supported_features": session::Feature::ALL
+                }),
+            );
+        }
+    };
+
+    if let Some(overrides) = init_params.config.clone() {
+        if let Err(e) = config::apply_host_overrides(overrides) {
+            return JsonRpcResponse::error(id, -32602, format!("Invalid config: {}", e));
+        }
+        info!("已应用宿主配置覆盖");
+    }
+
+    info!(
+        "协商完成: protocol_version={}, features={:?}",
+        negotiated.protocol_version, negotiated.features
+    );
+    session::set_current(negotiated.clone());
+
     JsonRpcResponse::success(
         id,
         json!({
             "provider_id": "antigravity",
             "display_name": "Antigravity (Gemini CLI)",
             "version": env!("CARGO_PKG_VERSION"),
+            "protocol_version": negotiated.protocol_version,
+            "features": negotiated.features,
             "supported_auth_types": ["oauth"],
             "capabilities": {
                 "token_refresh": true,
                 "pkce": true,
-                "code_assist": true
+                "code_assist": true,
+                "generate_content": true,
+                "stream_generate_content": true,
+                "anthropic_messages": true,
+                "openai_chat_completions": true,
+                "list_models": true
             }
         }),
     )
@@ -140,10 +233,27 @@ async fn handle_acquire_credential(
         None => return JsonRpcResponse::error(id, -32602, "Missing params".to_string()),
     };
 
-    // 解析凭证
-    let credential: AntigravityCredentials = match serde_json::from_value(params) {
-        Ok(c) => c,
-        Err(e) => return JsonRpcResponse::error(id, -32602, format!("Invalid params: {}", e)),
+    // 仅传入 credential_id 时从凭证池中查找
+    let credential: AntigravityCredentials = match params
+        .get("credential_id")
+        .and_then(|v| v.as_str())
+    {
+        Some(credential_id) if params.get("access_token").is_none() => {
+            match pool::get(credential_id) {
+                Some(c) => c,
+                None => {
+                    return JsonRpcResponse::error(
+                        id,
+                        -32602,
+                        format!("Credential not found: {}", credential_id),
+                    )
+                }
+            }
+        }
+        _ => match serde_json::from_value(params) {
+            Ok(c) => c,
+            Err(e) => return JsonRpcResponse::error(id, -32602, format!("Invalid params: {}", e)),
+        },
     };
 
     let token = match &credential.access_token {
@@ -153,6 +263,28 @@ async fn handle_acquire_credential(
         }
     };
 
+    // 凭证还没有项目时通过（带缓存的）setupUser 解析
+    let mut credential = credential;
+    if credential.project_id.is_none() && credential.temp_project_id.is_none() {
+        match api::project_cache::resolve(
+            &credential.id,
+            &token,
+            None,
+            &api::code_assist::OnboardControl::default(),
+            false,
+        )
+        .await
+        {
+            Ok(setup) => {
+                setup.apply_to(&mut credential);
+                if let Err(e) = pool::update(&credential.id, |c| setup.apply_to(c)) {
+                    error!("保存凭证失败: {}", e);
+                }
+            }
+            Err(e) => error!("解析凭证 {} 的项目失败: {}", credential.id, e),
+        }
+    }
+
     let acquired = AcquiredCredential {
         credential_id: credential.id.clone(),
         auth_type: AuthType::OAuth,
@@ -180,7 +312,28 @@ async fn handle_list_credentials(
     id: serde_json::Value,
     _params: Option<serde_json::Value>,
 ) -> JsonRpcResponse {
-    JsonRpcResponse::success(id, json!({"credentials": []}))
+    let credentials: Vec<serde_json::Value> = pool::list()
+        .into_iter()
+        .map(|c| {
+            json!({
+                "id": c.id,
+                "name": c.name,
+                "email": c.email,
+                "project_id": c.project_id,
+                "temp_project_id": c.temp_project_id,
+                "user_tier": c.user_tier,
+                "tier_info": c.tier_info,
+                "expiry_date": c.expiry_date,
+                "disabled": c.disabled,
+                "is_healthy": c.is_healthy,
+                "refresh_retry_at": c.refresh_retry_at,
+                "last_refresh": c.last_refresh,
+                "last_error": c.last_error
+            })
+        })
+        .collect();
+
+    JsonRpcResponse::success(id, json!({"credentials": credentials}))
 }
 
 /// 添加凭证
@@ -198,11 +351,16 @@ async fn handle_add_credential(
         Err(e) => return JsonRpcResponse::error(id, -32602, format!("Invalid params: {}", e)),
     };
 
+    let credential_id = credential.id.clone();
+    if let Err(e) = pool::upsert(credential) {
+        return JsonRpcResponse::error(id, -32000, format!("Failed to store credential: {}", e));
+    }
+
     JsonRpcResponse::success(
         id,
         json!({
             "success": true,
-            "credential_id": credential.id
+            "credential_id": credential_id
         }),
     )
 }
@@ -210,9 +368,21 @@ async fn handle_add_credential(
 /// 删除凭证
 async fn handle_remove_credential(
     id: serde_json::Value,
-    _params: Option<serde_json::Value>,
+    params: Option<serde_json::Value>,
 ) -> JsonRpcResponse {
-    JsonRpcResponse::success(id, json!({"success": true}))
+    let credential_id = match params
+        .as_ref()
+        .and_then(|p| p.get("credential_id").or_else(|| p.get("id")))
+        .and_then(|v| v.as_str())
+    {
+        Some(c) => c.to_string(),
+        None => return JsonRpcResponse::error(id, -32602, "Missing credential_id".to_string()),
+    };
+
+    match pool::remove(&credential_id) {
+        Ok(removed) => JsonRpcResponse::success(id, json!({"success": removed})),
+        Err(e) => JsonRpcResponse::error(id, -32000, format!("Failed to remove credential: {}", e)),
+    }
 }
 
 /// 刷新 Token
@@ -318,6 +488,13 @@ async fn handle_exchange_code(
         .and_then(|v| v.as_str())
         .unwrap_or(auth::oauth::OAUTH_REDIRECT_URI);
 
+    let project_id = params.get("project_id").and_then(|v| v.as_str());
+
+    let (control, _cancel_guard) = match onboard_control(&id) {
+        Ok(c) => c,
+        Err(e) => return duplicate_id_response(id, e),
+    };
+
     match auth::oauth::exchange_code_for_tokens(code, redirect_uri, code_verifier).await {
         Ok(result) => {
             // 尝试获取用户信息
@@ -325,16 +502,79 @@ async fn handle_exchange_code(
                 .await
                 .ok();
 
+            let email = user_info.as_ref().and_then(|u| u.email.clone());
+            // 同一账户重复登录时更新池中已有的凭证，而不是新增一个共享配额的条目
+            let mut credential = email
+                .as_deref()
+                .and_then(pool::find_by_email)
+                .unwrap_or_else(|| AntigravityCredentials {
+                    id: credentials::derive_id(
+                        email.as_deref(),
+                        result.refresh_token.as_deref(),
+                        Some(&result.access_token),
+                    )
+                    .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+                    ..Default::default()
+                });
+            credential.access_token = Some(result.access_token.clone());
+            if result.refresh_token.is_some() {
+                credential.refresh_token = result.refresh_token.clone();
+            }
+            credential.expiry_date = result.expiry_date;
+            credential.expire = result
+                .expiry_date
+                .and_then(chrono::DateTime::from_timestamp_millis)
+                .map(|dt| dt.to_rfc3339());
+            credential.scope = result.scope.clone();
+            credential.email = email;
+            if let Some(pid) = project_id {
+                credential.project_id = Some(pid.to_string());
+            }
+            credential.is_healthy = true;
+            credential.refresh_retry_at = None;
+            credential.last_error = None;
+            credential.updated_at = Some(chrono::Utc::now().to_rfc3339());
+
+            // 完成 Code Assist 用户设置，使新账户可以直接使用
+            let setup_error = match api::project_cache::resolve(
+                &credential.id,
+                &result.access_token,
+                credential.project_id.as_deref(),
+                &control,
+                false,
+            )
+            .await
+            {
+                Ok(setup) => {
+                    setup.apply_to(&mut credential);
+                    None
+                }
+                Err(e) => {
+                    error!("登录后 setupUser 失败: {}", e);
+                    credential.last_error = Some(e.to_string());
+                    Some(e.to_string())
+                }
+            };
+
+            if let Err(e) = pool::upsert(credential.clone()) {
+                error!("保存新凭证失败: {}", e);
+            }
+
             JsonRpcResponse::success(
                 id,
                 json!({
+                    "credential_id": credential.id,
                     "access_token": result.access_token,
                     "refresh_token": result.refresh_token,
                     "expiry_date": result.expiry_date,
                     "token_type": result.token_type,
                     "scope": result.scope,
                     "email": user_info.as_ref().and_then(|u| u.email.clone()),
-                    "user_id": user_info.as_ref().and_then(|u| u.id.clone())
+                    "user_id": user_info.as_ref().and_then(|u| u.id.clone()),
+                    "project_id": credential.project_id,
+                    "temp_project_id": credential.temp_project_id,
+                    "user_tier": credential.user_tier,
+                    "setup_error": setup_error
                 }),
             )
         }
@@ -342,14 +582,682 @@ async fn handle_exchange_code(
     }
 }
 
+/// 请求 ID 与进行中的请求重复
+fn duplicate_id_response(id: serde_json::Value, e: cancel::DuplicateId) -> JsonRpcResponse {
+    JsonRpcResponse::error(
+        id,
+        -32600,
+        format!("Duplicate in-flight request id: {}", e.0),
+    )
+}
+
+/// 为请求创建 onboard 控制：登记取消令牌，并以通知形式推送进度
+fn onboard_control(
+    id: &serde_json::Value,
+) -> Result<(api::code_assist::OnboardControl, cancel::CancelGuard), cancel::DuplicateId> {
+    let (token, guard) = cancel::register(id)?;
+    let request_id = id.clone();
+    let control = api::code_assist::OnboardControl {
+        cancel: Some(token),
+        progress: Some(std::sync::Arc::new(move |progress| {
+            notification::send(
+                "onboarding_progress",
+                json!({
+                    "request_id": request_id,
+                    "attempts": progress.attempts,
+                    "elapsed_ms": progress.elapsed_ms,
+                    "operation": progress.operation,
+                    "next_poll_ms": progress.next_poll_ms
+                }),
+            );
+        })),
+    };
+    Ok((control, guard))
+}
+
+/// 将 setupUser 失败转换为 JSON-RPC 错误（onboard 超时/取消带上进度信息）
+fn setup_error_response(id: serde_json::Value, e: anyhow::Error) -> JsonRpcResponse {
+    use api::code_assist::{OnboardError, SetupError};
+
+    match e.downcast_ref::<SetupError>() {
+        Some(SetupError::ProjectRequired { tier_id, tier_name }) => {
+            return JsonRpcResponse::error_with_data(
+                id,
+                -32003,
+                e.to_string(),
+                json!({
+                    "kind": "project_required",
+                    "tier_id": tier_id,
+                    "tier_name": tier_name,
+                    "missing": "project_id"
+                }),
+            )
+        }
+        Some(SetupError::InvalidProjectId(pid)) => {
+            return JsonRpcResponse::error_with_data(
+                id,
+                -32602,
+                e.to_string(),
+                json!({"kind": "invalid_project_id", "project_id": pid}),
+            )
+        }
+        _ => {}
+    }
+
+    match e.downcast_ref::<OnboardError>() {
+        Some(OnboardError::Cancelled {
+            attempts,
+            elapsed_ms,
+            operation,
+        }) => JsonRpcResponse::error_with_data(
+            id,
+            -32800,
+            e.to_string(),
+            json!({
+                "kind": "cancelled",
+                "attempts": attempts,
+                "elapsed_ms": elapsed_ms,
+                "operation": operation
+            }),
+        ),
+        Some(OnboardError::Timeout {
+            attempts,
+            elapsed_ms,
+            deadline_ms,
+            operation,
+        }) => JsonRpcResponse::error_with_data(
+            id,
+            -32001,
+            e.to_string(),
+            json!({
+                "kind": "onboarding_timeout",
+                "attempts": attempts,
+                "elapsed_ms": elapsed_ms,
+                "deadline_ms": deadline_ms,
+                "operation": operation
+            }),
+        ),
+        _ => JsonRpcResponse::error(id, -32000, format!("Setup user failed: {}", e)),
+    }
+}
+
+/// 取消进行中的请求
+async fn handle_cancel_request(
+    id: serde_json::Value,
+    params: Option<serde_json::Value>,
+) -> JsonRpcResponse {
+    let target = match params.as_ref().and_then(|p| p.get("id")) {
+        Some(t) => t.clone(),
+        None => return JsonRpcResponse::error(id, -32602, "Missing id".to_string()),
+    };
+
+    let cancelled = cancel::cancel(&target);
+    info!("取消请求 {}: {}", target, cancelled);
+    JsonRpcResponse::success(id, json!({"cancelled": cancelled}))
+}
+
+/// 从参数中解析凭证：传入 credential_id 时从凭证池查找，否则按凭证字段解析
+fn credential_from_params(params: &serde_json::Value) -> Result<AntigravityCredentials, String> {
+    if let Some(credential_id) = params.get("credential_id").and_then(|v| v.as_str()) {
+        return pool::get(credential_id)
+            .ok_or_else(|| format!("Credential not found: {}", credential_id));
+    }
+
+    // 内联凭证按账户派生稳定 ID，多次调用可以复用项目缓存；加前缀避免与池中凭证混淆
+    let mut value = params.clone();
+    if let Some(obj) = value.as_object_mut() {
+        if !obj.contains_key("id") {
+            let field = |key: &str| params.get(key).and_then(|v| v.as_str());
+            let derived = credentials::derive_id(
+                field("email"),
+                field("refresh_token"),
+                field("access_token"),
+            )
+            .ok_or_else(|| "Missing access_token or refresh_token".to_string())?;
+            obj.insert("id".to_string(), json!(format!("inline-{}", derived)));
+        }
+    }
+    serde_json::from_value(value).map_err(|e| format!("Invalid params: {}", e))
+}
+
+/// 执行 Code Assist 用户设置，并将 Project ID 与层级写回凭证
+async fn handle_setup_user(
+    id: serde_json::Value,
+    params: Option<serde_json::Value>,
+) -> JsonRpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return JsonRpcResponse::error(id, -32602, "Missing params".to_string()),
+    };
+
+    let mut credential = match credential_from_params(&params) {
+        Ok(c) => c,
+        Err(e) => return JsonRpcResponse::error(id, -32602, e),
+    };
+
+    // 显式传入的 project_id 优先于凭证上已保存的项目
+    if let Some(pid) = params.get("project_id").and_then(|v| v.as_str()) {
+        credential.project_id = Some(pid.to_string());
+    }
+
+    let last_refresh = credential.last_refresh.clone();
+    let access_token = match token_refresh::ensure_valid_token(&mut credential).await {
+        Ok(t) => t,
+        Err(e) => return JsonRpcResponse::error(id, -32000, format!("Token unavailable: {}", e)),
+    };
+    let refreshed = credential.last_refresh != last_refresh;
+
+    let (control, _cancel_guard) = match onboard_control(&id) {
+        Ok(c) => c,
+        Err(e) => return duplicate_id_response(id, e),
+    };
+
+    // force: true 时跳过缓存重新向 Code Assist 解析
+    let force = params
+        .get("force")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    match api::project_cache::resolve(
+        &credential.id,
+        &access_token,
+        credential.project_id.as_deref(),
+        &control,
+        force,
+    )
+    .await
+    {
+        Ok(setup) => {
+            setup.apply_to(&mut credential);
+            let updated = pool::update(&credential.id, |c| {
+                if refreshed {
+                    c.apply_token_from(&credential);
+                }
+                setup.apply_to(c);
+            });
+            if let Err(e) = updated {
+                error!("保存凭证失败: {}", e);
+            }
+
+            let mut result = setup.to_json();
+            result["credential_id"] = json!(credential.id);
+            result["access_token"] = json!(access_token);
+            result["expiry_date"] = json!(credential.expiry_date);
+            JsonRpcResponse::success(id, result)
+        }
+        Err(e) => {
+            let updated = pool::update(&credential.id, |c| {
+                if refreshed {
+                    c.apply_token_from(&credential);
+                }
+                c.last_error = Some(e.to_string());
+            });
+            if let Err(e) = updated {
+                error!("保存凭证失败: {}", e);
+            }
+            setup_error_response(id, e)
+        }
+    }
+}
+
+/// 调用 loadCodeAssist
+async fn handle_load_code_assist(
+    id: serde_json::Value,
+    params: Option<serde_json::Value>,
+) -> JsonRpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return JsonRpcResponse::error(id, -32602, "Missing params".to_string()),
+    };
+
+    let mut credential = match credential_from_params(&params) {
+        Ok(c) => c,
+        Err(e) => return JsonRpcResponse::error(id, -32602, e),
+    };
+
+    let last_refresh = credential.last_refresh.clone();
+    let access_token = match token_refresh::ensure_valid_token(&mut credential).await {
+        Ok(t) => t,
+        Err(e) => return JsonRpcResponse::error(id, -32000, format!("Token unavailable: {}", e)),
+    };
+    let refreshed = credential.last_refresh != last_refresh;
+
+    let project_id = credential
+        .project_id
+        .as_deref()
+        .or(credential.temp_project_id.as_deref());
+
+    match api::code_assist::load_code_assist(&access_token, project_id).await {
+        Ok(load_res) => {
+            let tier = api::code_assist::get_onboard_tier(&load_res);
+            let tier_info = api::code_assist::TierInfo::from(&load_res);
+
+            // 记录到凭证池，便于排查账户无法 onboard 的原因
+            let updated = pool::update(&credential.id, |c| {
+                if refreshed {
+                    c.apply_token_from(&credential);
+                }
+                c.tier_info = Some(tier_info.clone());
+            });
+            if let Err(e) = updated {
+                error!("保存凭证失败: {}", e);
+            }
+
+            let mut result = serde_json::to_value(&load_res).unwrap();
+            result["user_tier"] = json!(tier.as_str());
+            result["tier_info"] = json!(tier_info);
+            JsonRpcResponse::success(id, result)
+        }
+        Err(e) => JsonRpcResponse::error(id, -32000, format!("loadCodeAssist failed: {}", e)),
+    }
+}
+
+/// 上游调用失败的响应：请求本身不合法时返回 -32602
+fn upstream_error_response(
+    id: serde_json::Value,
+    method: &str,
+    e: anyhow::Error,
+) -> JsonRpcResponse {
+    if let Some(convert) = e.downcast_ref::<protocol::ConvertError>() {
+        return JsonRpcResponse::error(id, -32602, format!("Invalid params: {}", convert));
+    }
+    if let Some(upstream) = e.downcast_ref::<api::error::UpstreamError>() {
+        return JsonRpcResponse::error_with_data(
+            id,
+            -32000,
+            format!("{} failed: {}", method, upstream),
+            json!(upstream),
+        );
+    }
+    if let Some(mismatch) = e.downcast_ref::<protocol::structured::StructuredOutputError>() {
+        return JsonRpcResponse::error_with_data(
+            id,
+            -32004,
+            format!("Structured output validation failed: {}", mismatch),
+            json!({"errors": mismatch.errors()}),
+        );
+    }
+    JsonRpcResponse::error(id, -32000, format!("{} failed: {}", method, e))
+}
+
+/// 在响应结果上附加依次尝试过的凭证与模型
+fn with_attempts(
+    mut result: serde_json::Value,
+    attempts: &[dispatch::Attempt],
+) -> serde_json::Value {
+    if let Some(object) = result.as_object_mut() {
+        object.insert("attempts".to_string(), json!(attempts));
+    }
+    result
+}
+
+/// 是否允许模型回退：params.model_fallback 优先，其次是请求体中的同名字段，默认允许
+fn model_fallback_param(params: &serde_json::Value, request: Option<bool>) -> bool {
+    params
+        .get("model_fallback")
+        .and_then(|v| v.as_bool())
+        .or(request)
+        .unwrap_or(true)
+}
+
+/// 通过 Code Assist v1internal 转发 Gemini generateContent 请求
+async fn handle_generate_content(
+    id: serde_json::Value,
+    params: Option<serde_json::Value>,
+) -> JsonRpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return JsonRpcResponse::error(id, -32602, "Missing params".to_string()),
+    };
+
+    let model = match params.get("model").and_then(|v| v.as_str()) {
+        Some(m) => m.to_string(),
+        None => return JsonRpcResponse::error(id, -32602, "Missing model".to_string()),
+    };
+
+    let request = match params.get("request") {
+        Some(r) if r.is_object() => r.clone(),
+        _ => return JsonRpcResponse::error(id, -32602, "Missing request".to_string()),
+    };
+
+    let credential_id = params.get("credential_id").and_then(|v| v.as_str());
+    let fallback = model_fallback_param(&params, None);
+
+    match dispatch::generate_content(&model, request, credential_id, fallback).await {
+        Ok(generated) => {
+            JsonRpcResponse::success(id, with_attempts(generated.response, &generated.attempts))
+        }
+        Err(e) => upstream_error_response(id, "generateContent", e),
+    }
+}
+
+/// 流式调用 streamGenerateContent：每个分块以 stream_chunk 通知推送，最终响应携带汇总的 usage
+async fn handle_stream_generate_content(
+    id: serde_json::Value,
+    params: Option<serde_json::Value>,
+) -> JsonRpcResponse {
+    if !session::supports(session::Feature::Streaming) {
+        return JsonRpcResponse::error(
+            id,
+            -32002,
+            "Feature not negotiated: streaming".to_string(),
+        );
+    }
+
+    let params = match params {
+        Some(p) => p,
+        None => return JsonRpcResponse::error(id, -32602, "Missing params".to_string()),
+    };
+
+    let model = match params.get("model").and_then(|v| v.as_str()) {
+        Some(m) => m.to_string(),
+        None => return JsonRpcResponse::error(id, -32602, "Missing model".to_string()),
+    };
+
+    let request = match params.get("request") {
+        Some(r) if r.is_object() => r.clone(),
+        _ => return JsonRpcResponse::error(id, -32602, "Missing request".to_string()),
+    };
+
+    let credential_id = params.get("credential_id").and_then(|v| v.as_str());
+    let fallback = model_fallback_param(&params, None);
+    let (token, _cancel_guard) = match cancel::register(&id) {
+        Ok(c) => c,
+        Err(e) => return duplicate_id_response(id, e),
+    };
+
+    let mut index = 0usize;
+    let request_id = id.clone();
+    let on_chunk = move |chunk: serde_json::Value| {
+        notification::send_stream(
+            "stream_chunk",
+            json!({
+                "request_id": request_id,
+                "index": index,
+                "chunk": chunk
+            }),
+        );
+        index += 1;
+    };
+
+    match dispatch::stream_generate_content(
+        &model,
+        request,
+        credential_id,
+        Some(token.clone()),
+        fallback,
+        on_chunk,
+    )
+    .await
+    {
+        Ok(streamed) => JsonRpcResponse::success(
+            id,
+            json!({
+                "credential_id": streamed.credential_id,
+                "chunks": streamed.summary.chunks,
+                "usageMetadata": streamed.summary.usage_metadata,
+                "finishReason": streamed.summary.finish_reason,
+                "modelVersion": streamed.summary.model_version,
+                "model": streamed.model,
+                "modelFallback": streamed.fallback,
+                "attempts": streamed.attempts
+            }),
+        ),
+        Err(_) if token.is_cancelled() => {
+            JsonRpcResponse::error(id, -32800, "Request cancelled".to_string())
+        }
+        Err(e) => upstream_error_response(id, "streamGenerateContent", e),
+    }
+}
+
+/// Anthropic Messages 请求：转换为 Gemini 格式转发后再转换回 Messages 响应
+async fn handle_create_message(
+    id: serde_json::Value,
+    params: Option<serde_json::Value>,
+) -> JsonRpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return JsonRpcResponse::error(id, -32602, "Missing params".to_string()),
+    };
+
+    let request: protocol::anthropic::MessagesRequest = match params.get("request") {
+        Some(r) => match serde_json::from_value(r.clone()) {
+            Ok(r) => r,
+            Err(e) => return JsonRpcResponse::error(id, -32602, format!("Invalid params: {}", e)),
+        },
+        None => return JsonRpcResponse::error(id, -32602, "Missing request".to_string()),
+    };
+
+    let gemini_request = match protocol::anthropic::to_gemini(&request) {
+        Ok(r) => r,
+        Err(e) => return JsonRpcResponse::error(id, -32602, format!("Invalid params: {}", e)),
+    };
+
+    let credential_id = params.get("credential_id").and_then(|v| v.as_str());
+    let fallback = model_fallback_param(&params, request.model_fallback);
+
+    if request.stream {
+        return stream_message(id, &request, gemini_request, credential_id, fallback).await;
+    }
+
+    match dispatch::generate_content(&request.model, gemini_request, credential_id, fallback).await
+    {
+        Ok(generated) => {
+            let message = protocol::anthropic::from_gemini(&generated.response, &request.model);
+            JsonRpcResponse::success(id, with_attempts(json!(message), &generated.attempts))
+        }
+        Err(e) => upstream_error_response(id, "generateContent", e),
+    }
+}
+
+/// 流式 Messages：每个 Anthropic 事件以 stream_event 通知推送，最终响应为累积的完整消息
+async fn stream_message(
+    id: serde_json::Value,
+    request: &protocol::anthropic::MessagesRequest,
+    gemini_request: serde_json::Value,
+    credential_id: Option<&str>,
+    fallback: bool,
+) -> JsonRpcResponse {
+    if !session::supports(session::Feature::Streaming) {
+        return JsonRpcResponse::error(
+            id,
+            -32002,
+            "Feature not negotiated: streaming".to_string(),
+        );
+    }
+
+    let (token, _cancel_guard) = match cancel::register(&id) {
+        Ok(c) => c,
+        Err(e) => return duplicate_id_response(id, e),
+    };
+    let mut converter = protocol::anthropic_stream::StreamConverter::new(&request.model);
+    let request_id = id.clone();
+    let send = |event: protocol::anthropic_stream::StreamEvent| {
+        notification::send_stream(
+            "stream_event",
+            json!({
+                "request_id": request_id,
+                "event": event.event,
+                "data": event.data
+            }),
+        );
+    };
+
+    let result = dispatch::stream_generate_content(
+        &request.model,
+        gemini_request,
+        credential_id,
+        Some(token.clone()),
+        fallback,
+        |chunk| converter.push(&chunk).into_iter().for_each(send),
+    )
+    .await;
+
+    match result {
+        Ok(streamed) => {
+            converter.finish().into_iter().for_each(send);
+            JsonRpcResponse::success(
+                id,
+                with_attempts(json!(converter.message()), &streamed.attempts),
+            )
+        }
+        Err(_) if token.is_cancelled() => {
+            JsonRpcResponse::error(id, -32800, "Request cancelled".to_string())
+        }
+        Err(e) => {
+            send(protocol::anthropic_stream::StreamEvent::error(
+                "api_error",
+                &e.to_string(),
+            ));
+            upstream_error_response(id, "streamGenerateContent", e)
+        }
+    }
+}
+
+/// OpenAI Chat Completions 请求：转换为 Gemini 格式转发后再转换回 Chat Completions 响应
+async fn handle_chat_completion(
+    id: serde_json::Value,
+    params: Option<serde_json::Value>,
+) -> JsonRpcResponse {
+    let params = match params {
+        Some(p) => p,
+        None => return JsonRpcResponse::error(id, -32602, "Missing params".to_string()),
+    };
+
+    let request: protocol::openai::ChatCompletionRequest = match params.get("request") {
+        Some(r) => match serde_json::from_value(r.clone()) {
+            Ok(r) => r,
+            Err(e) => return JsonRpcResponse::error(id, -32602, format!("Invalid params: {}", e)),
+        },
+        None => return JsonRpcResponse::error(id, -32602, "Missing request".to_string()),
+    };
+
+    let gemini_request = match protocol::openai::to_gemini(&request) {
+        Ok(r) => r,
+        Err(e) => return JsonRpcResponse::error(id, -32602, format!("Invalid params: {}", e)),
+    };
+
+    let credential_id = params.get("credential_id").and_then(|v| v.as_str());
+    let fallback = model_fallback_param(&params, request.model_fallback);
+
+    if request.stream {
+        return stream_chat_completion(id, &request, gemini_request, credential_id, fallback)
+            .await;
+    }
+
+    match dispatch::generate_content(&request.model, gemini_request, credential_id, fallback).await
+    {
+        Ok(generated) => {
+            let completion = protocol::openai::from_gemini(&generated.response, &request.model);
+            match protocol::openai::check_structured_output(&request, &completion) {
+                Ok(()) => JsonRpcResponse::success(
+                    id,
+                    with_attempts(json!(completion), &generated.attempts),
+                ),
+                Err(e) => upstream_error_response(id, "generateContent", e.into()),
+            }
+        }
+        Err(e) => upstream_error_response(id, "generateContent", e),
+    }
+}
+
+/// 流式 Chat Completions：每个 chat.completion.chunk 以 stream_chunk 通知推送，最终响应为累积的完整结果
+async fn stream_chat_completion(
+    id: serde_json::Value,
+    request: &protocol::openai::ChatCompletionRequest,
+    gemini_request: serde_json::Value,
+    credential_id: Option<&str>,
+    fallback: bool,
+) -> JsonRpcResponse {
+    if !session::supports(session::Feature::Streaming) {
+        return JsonRpcResponse::error(
+            id,
+            -32002,
+            "Feature not negotiated: streaming".to_string(),
+        );
+    }
+
+    let (token, _cancel_guard) = match cancel::register(&id) {
+        Ok(c) => c,
+        Err(e) => return duplicate_id_response(id, e),
+    };
+    let include_usage = request
+        .stream_options
+        .as_ref()
+        .map(|o| o.include_usage)
+        .unwrap_or(false);
+    let mut converter = protocol::openai::ChunkConverter::new(&request.model, include_usage);
+    let mut index = 0usize;
+    let request_id = id.clone();
+    let mut send = |chunk: serde_json::Value| {
+        notification::send_stream(
+            "stream_chunk",
+            json!({
+                "request_id": request_id,
+                "index": index,
+                "chunk": chunk
+            }),
+        );
+        index += 1;
+    };
+
+    let result = dispatch::stream_generate_content(
+        &request.model,
+        gemini_request,
+        credential_id,
+        Some(token.clone()),
+        fallback,
+        |chunk| converter.push(&chunk).into_iter().for_each(&mut send),
+    )
+    .await;
+
+    match result {
+        Ok(streamed) => {
+            converter.finish().into_iter().for_each(&mut send);
+            let Some(completion) = converter.completion() else {
+                return JsonRpcResponse::success(id, json!(null));
+            };
+            match protocol::openai::check_structured_output(request, completion) {
+                Ok(()) => JsonRpcResponse::success(
+                    id,
+                    with_attempts(json!(completion), &streamed.attempts),
+                ),
+                Err(e) => upstream_error_response(id, "streamGenerateContent", e.into()),
+            }
+        }
+        Err(_) if token.is_cancelled() => {
+            JsonRpcResponse::error(id, -32800, "Request cancelled".to_string())
+        }
+        Err(e) => {
+            // 与 OpenAI 流一致，以 error 块结束已推送的 chunk
+            let (kind, code) = e
+                .downcast_ref::<api::error::UpstreamError>()
+                .map_or(("api_error", None), |u| u.openai_type());
+            send(json!({"error": {"message": e.to_string(), "type": kind, "code": code}}));
+            upstream_error_response(id, "streamGenerateContent", e)
+        }
+    }
+}
+
+/// 列出支持的模型及其上游 ID、能力与 token 上限
+async fn handle_list_models(id: serde_json::Value) -> JsonRpcResponse {
+    JsonRpcResponse::success(id, json!({"models": protocol::models::MODELS}))
+}
+
 /// 健康检查
 async fn handle_health_check(id: serde_json::Value) -> JsonRpcResponse {
+    let cfg = config::current();
+    let api = &cfg.settings.api;
+
     JsonRpcResponse::success(
         id,
         json!({
             "status": "healthy",
             "provider": "antigravity",
-            "version": env!("CARGO_PKG_VERSION")
+            "version": env!("CARGO_PKG_VERSION"),
+            "environment": api.environment.as_str(),
+            "base_url": api.base_url(),
+            "api_version": api.api_version
         }),
     )
 }
@@ -360,16 +1268,83 @@ async fn handle_shutdown(id: serde_json::Value) -> JsonRpcResponse {
     JsonRpcResponse::success(id, json!({"success": true}))
 }
 
+/// 处理一行输入（单个请求或批量请求），返回序列化后的响应
+async fn handle_line(line: &str) -> Option<String> {
+    let value: serde_json::Value = match serde_json::from_str(line) {
+        Ok(v) => v,
+        Err(e) => {
+            let response = JsonRpcResponse::error(
+                serde_json::Value::Null,
+                -32700,
+                format!("Parse error: {}", e),
+            );
+            return serde_json::to_string(&response).ok();
+        }
+    };
+
+    let output = match value {
+        serde_json::Value::Array(items) => {
+            if !session::supports(session::Feature::Batch) {
+                let response = JsonRpcResponse::error(
+                    serde_json::Value::Null,
+                    -32600,
+                    "Batch requests were not negotiated".to_string(),
+                );
+                return serde_json::to_string(&response).ok();
+            }
+            if items.is_empty() {
+                let response = JsonRpcResponse::error(
+                    serde_json::Value::Null,
+                    -32600,
+                    "Empty batch".to_string(),
+                );
+                return serde_json::to_string(&response).ok();
+            }
+            let mut responses = Vec::with_capacity(items.len());
+            for item in items {
+                responses.push(handle_value(item).await);
+            }
+            serde_json::to_string(&responses)
+        }
+        other => serde_json::to_string(&handle_value(other).await),
+    };
+
+    match output {
+        Ok(o) => Some(o),
+        Err(e) => {
+            error!("序列化响应失败: {}", e);
+            None
+        }
+    }
+}
+
+/// 处理单个 JSON-RPC 请求对象
+async fn handle_value(value: serde_json::Value) -> JsonRpcResponse {
+    match serde_json::from_value::<JsonRpcRequest>(value) {
+        Ok(request) => handle_request(request).await,
+        Err(e) => JsonRpcResponse::error(
+            serde_json::Value::Null,
+            -32600,
+            format!("Invalid request: {}", e),
+        ),
+    }
+}
+
 /// 运行 JSON-RPC 服务
+///
+/// 每行输入在独立任务中处理，耗时请求不会阻塞后续请求；
+/// 响应与通知统一经由 notification::write_line 输出。
+/// 输入结束后等待所有进行中的请求写出响应再返回。
 async fn run_jsonrpc_server() -> Result<()> {
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut tasks = tokio::task::JoinSet::new();
 
     info!("Antigravity Provider CLI 已启动，等待 JSON-RPC 请求...");
 
-    for line in stdin.lock().lines() {
-        let line = match line {
-            Ok(l) => l,
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(l)) => l,
+            Ok(None) => break,
             Err(e) => {
                 error!("读取输入失败: {}", e);
                 continue;
@@ -380,30 +1355,49 @@ async fn run_jsonrpc_server() -> Result<()> {
             continue;
         }
 
-        let request: JsonRpcRequest = match serde_json::from_str(&line) {
-            Ok(r) => r,
-            Err(e) => {
-                let response = JsonRpcResponse::error(
-                    serde_json::Value::Null,
-                    -32700,
-                    format!("Parse error: {}", e),
-                );
-                let output = serde_json::to_string(&response)?;
-                writeln!(stdout, "{}", output)?;
-                stdout.flush()?;
-                continue;
+        // 回收已完成的任务，避免长时间运行时累积
+        while tasks.try_join_next().is_some() {}
+
+        // initialize 决定后续请求可用的特性，必须先于其它请求完成
+        let is_initialize = serde_json::from_str::<serde_json::Value>(&line)
+            .ok()
+            .and_then(|v| v.get("method").and_then(|m| m.as_str()).map(String::from))
+            .is_some_and(|m| m == "initialize");
+
+        let task = async move {
+            if let Some(output) = handle_line(&line).await {
+                if let Err(e) = notification::write_line(&output) {
+                    error!("写出响应失败: {}", e);
+                }
             }
         };
+        if is_initialize {
+            task.await;
+        } else {
+            tasks.spawn(task);
+        }
+    }
 
-        let response = handle_request(request).await;
-        let output = serde_json::to_string(&response)?;
-        writeln!(stdout, "{}", output)?;
-        stdout.flush()?;
+    while let Some(result) = tasks.join_next().await {
+        if let Err(e) = result {
+            error!("请求任务异常退出: {}", e);
+        }
     }
 
     Ok(())
 }
 
+/// 加载配置与凭证池，并启动热重载
+fn load_state(config_path: Option<&std::path::Path>) -> Result<()> {
+    let cfg = config::init(config_path)?;
+    if let Some(dir) = &cfg.settings.store.dir {
+        let diff = pool::reload_from_dir(dir)?;
+        info!("已从 {} 加载 {} 个凭证", dir.display(), diff.added.len());
+    }
+    reload::spawn();
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // 初始化日志
@@ -421,8 +1415,13 @@ async fn main() -> Result<()> {
             println!("antigravity-provider-cli {}", env!("CARGO_PKG_VERSION"));
         }
         Some(Commands::Serve) | None => {
+            load_state(cli.config.as_deref())?;
             run_jsonrpc_server().await?;
         }
+        Some(Commands::Proxy { listen }) => {
+            load_state(cli.config.as_deref())?;
+            proxy::run(listen).await?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,40 @@
+//! 向宿主输出 JSON-RPC 消息（响应与通知）
+
+use crate::session::{self, Feature};
+use serde::Serialize;
+use serde_json::json;
+use std::io::{self, Write};
+use tracing::{debug, error};
+
+/// 写出一行消息（持有 stdout 锁，保证并发任务的输出不会交错）
+pub fn write_line(line: &str) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", line)?;
+    stdout.flush()
+}
+
+/// 发送 JSON-RPC 通知（宿主未协商 notifications 特性时丢弃）
+pub fn send<T: Serialize>(method: &str, params: T) {
+    send_with(Feature::Notifications, method, params)
+}
+
+/// 发送流式分块通知（宿主未协商 streaming 特性时丢弃）
+pub fn send_stream<T: Serialize>(method: &str, params: T) {
+    send_with(Feature::Streaming, method, params)
+}
+
+fn send_with<T: Serialize>(feature: Feature, method: &str, params: T) {
+    if !session::supports(feature) {
+        debug!("宿主未启用 {}，丢弃 {}", feature.as_str(), method);
+        return;
+    }
+
+    let message = json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+    });
+    if let Err(e) = write_line(&message.to_string()) {
+        error!("发送通知 {} 失败: {}", method, e);
+    }
+}
@@ -0,0 +1,552 @@
+//! 凭证池（内存 + 可选的凭证文件目录）
+
+use crate::config;
+use crate::credentials::AntigravityCredentials;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// 文件指纹（修改时间 + 大小）
+pub type FileStamp = (SystemTime, u64);
+
+pub fn file_stamp(path: &Path) -> Option<FileStamp> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+/// 池中的凭证及其来源文件
+#[derive(Debug, Clone)]
+struct PoolEntry {
+    credential: AntigravityCredentials,
+    path: Option<PathBuf>,
+    /// 最近一次读取或写入文件时的指纹
+    stamp: Option<FileStamp>,
+}
+
+static POOL: RwLock<BTreeMap<String, PoolEntry>> = RwLock::new(BTreeMap::new());
+static CURSOR: AtomicUsize = AtomicUsize::new(0);
+
+/// 凭证池变化
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PoolDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub updated: Vec<String>,
+}
+
+impl PoolDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.updated.is_empty()
+    }
+}
+
+/// 列出所有凭证
+pub fn list() -> Vec<AntigravityCredentials> {
+    POOL.read()
+        .unwrap()
+        .values()
+        .map(|e| e.credential.clone())
+        .collect()
+}
+
+/// 按 ID 获取凭证
+pub fn get(id: &str) -> Option<AntigravityCredentials> {
+    POOL.read().unwrap().get(id).map(|e| e.credential.clone())
+}
+
+/// 按邮箱查找凭证（不区分大小写）
+pub fn find_by_email(email: &str) -> Option<AntigravityCredentials> {
+    POOL.read()
+        .unwrap()
+        .values()
+        .map(|e| &e.credential)
+        .find(|c| {
+            c.email
+                .as_deref()
+                .is_some_and(|e| e.eq_ignore_ascii_case(email.trim()))
+        })
+        .cloned()
+}
+
+/// 凭证对该模型的冷却截止时间（已过期时为 None）
+fn cooldown_until(
+    credential: &AntigravityCredentials,
+    model: &str,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    credential
+        .cooldowns
+        .get(model)
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.with_timezone(&Utc))
+        .filter(|until| *until > now)
+}
+
+/// 凭证是否可参与调度（Token 刷新暂时失败的凭证在重试时间之前跳过）
+fn is_eligible(credential: &AntigravityCredentials, now: DateTime<Utc>) -> bool {
+    let refresh_backoff = credential
+        .refresh_retry_at
+        .as_deref()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .is_some_and(|t| t > now);
+    !credential.disabled
+        && credential.is_healthy
+        && !refresh_backoff
+        && (credential.access_token.is_some() || credential.refresh_token.is_some())
+}
+
+/// 轮询选择一个可用于该模型的凭证（跳过 exclude 中的 ID 和冷却中的凭证）
+pub fn select(exclude: &[String], model: &str) -> Option<AntigravityCredentials> {
+    let now = Utc::now();
+    let pool = POOL.read().unwrap();
+    let eligible: Vec<&AntigravityCredentials> = pool
+        .values()
+        .map(|e| &e.credential)
+        .filter(|c| is_eligible(c, now) && !exclude.contains(&c.id))
+        .filter(|c| cooldown_until(c, model, now).is_none())
+        .collect();
+    if eligible.is_empty() {
+        return None;
+    }
+    let idx = CURSOR.fetch_add(1, Ordering::Relaxed) % eligible.len();
+    Some(eligible[idx].clone())
+}
+
+/// 所有可用凭证都在冷却该模型时，返回最早结束冷却的时间
+pub fn earliest_cooldown_end(model: &str) -> Option<DateTime<Utc>> {
+    let now = Utc::now();
+    POOL.read()
+        .unwrap()
+        .values()
+        .map(|e| &e.credential)
+        .filter(|c| is_eligible(c, now))
+        .map(|c| cooldown_until(c, model, now))
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .min()
+}
+
+/// 把凭证对该模型置入限流冷却，返回冷却截止时间
+pub fn cool_down(
+    id: &str,
+    model: &str,
+    delay: Duration,
+    reason: &str,
+) -> Result<Option<DateTime<Utc>>> {
+    let now = Utc::now();
+    let until = now + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::zero());
+    let updated = update(id, |credential| {
+        credential.rate_limit_status = Some(reason.to_string());
+        credential.rate_limited_at = Some(now.to_rfc3339());
+        credential
+            .cooldowns
+            .insert(model.to_string(), until.to_rfc3339());
+    })?;
+    Ok(updated.map(|_| until))
+}
+
+/// 请求成功后清除该模型的冷却记录，没有其他冷却中的模型时一并清除限流状态
+pub fn clear_cooldown(id: &str, model: &str) -> Result<()> {
+    update(id, |credential| {
+        let now = Utc::now();
+        let original = credential.clone();
+        credential.cooldowns.remove(model);
+        credential
+            .cooldowns
+            .retain(|m, _| cooldown_until(&original, m, now).is_some());
+        if credential.cooldowns.is_empty() {
+            credential.rate_limit_status = None;
+        }
+    })?;
+    Ok(())
+}
+
+/// 在写锁内修改池中的凭证，返回修改后的凭证（不存在时返回 None）
+///
+/// 读取、修改和写回之间不会插入其他请求的改动，并发请求各自改动的字段不会互相覆盖；
+/// 没有变化时不写文件。
+pub fn update(
+    id: &str,
+    f: impl FnOnce(&mut AntigravityCredentials),
+) -> Result<Option<AntigravityCredentials>> {
+    let mut pool = POOL.write().unwrap();
+    let Some(entry) = pool.get_mut(id) else {
+        return Ok(None);
+    };
+    let mut credential = entry.credential.clone();
+    f(&mut credential);
+    if credential != entry.credential {
+        if entry.path.is_none() {
+            entry.path = store_path(id);
+        }
+        if let Some(path) = &entry.path {
+            write_file(path, &credential)?;
+            entry.stamp = file_stamp(path);
+        }
+        entry.credential = credential.clone();
+    }
+    Ok(Some(credential))
+}
+
+/// 添加或更新凭证（配置了存储目录时同时写入文件）
+///
+/// 写文件和更新内存在同一把写锁内完成，热加载不会读到写了一半的状态。
+pub fn upsert(credential: AntigravityCredentials) -> Result<()> {
+    let mut pool = POOL.write().unwrap();
+    let path = pool
+        .get(&credential.id)
+        .and_then(|e| e.path.clone())
+        .or_else(|| store_path(&credential.id));
+    let stamp = match &path {
+        Some(p) => {
+            write_file(p, &credential)?;
+            file_stamp(p)
+        }
+        None => None,
+    };
+
+    pool.insert(
+        credential.id.clone(),
+        PoolEntry {
+            credential,
+            path,
+            stamp,
+        },
+    );
+    Ok(())
+}
+
+/// 删除凭证（同时删除对应文件），返回是否存在
+pub fn remove(id: &str) -> Result<bool> {
+    let mut pool = POOL.write().unwrap();
+    match pool.remove(id) {
+        Some(PoolEntry {
+            path: Some(path), ..
+        }) => {
+            if path.exists() {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("删除凭证文件 {} 失败", path.display()))?;
+            }
+            Ok(true)
+        }
+        Some(_) => Ok(true),
+        None => Ok(false),
+    }
+}
+
+/// 新凭证在存储目录中的文件路径
+fn store_path(id: &str) -> Option<PathBuf> {
+    let dir = config::current().settings.store.dir.clone()?;
+    let file_name: String = id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    Some(dir.join(format!("{}.json", file_name)))
+}
+
+/// 原子写入凭证文件（先写临时文件再 rename）
+fn write_file(path: &Path, credential: &AntigravityCredentials) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("创建凭证目录 {} 失败", dir.display()))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(credential)?)
+        .with_context(|| format!("写入凭证文件 {} 失败", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("写入凭证文件 {} 失败", path.display()))?;
+    Ok(())
+}
+
+/// 解析单个凭证文件，文件中缺少 id 时使用文件名
+fn read_file(path: &Path) -> Result<AntigravityCredentials> {
+    let content = std::fs::read_to_string(path)?;
+    let mut value: serde_json::Value = serde_json::from_str(&content)?;
+    if value.get("id").is_none() {
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_string();
+        if let Some(obj) = value.as_object_mut() {
+            obj.insert("id".to_string(), serde_json::Value::String(stem));
+        }
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// 扫描存储目录中的凭证文件（*.json），无法解析的文件会被跳过
+fn scan_dir(dir: &Path) -> Result<BTreeMap<String, PoolEntry>> {
+    let mut entries = BTreeMap::new();
+    if !dir.exists() {
+        return Ok(entries);
+    }
+
+    let read_dir =
+        std::fs::read_dir(dir).with_context(|| format!("读取凭证目录 {} 失败", dir.display()))?;
+    for item in read_dir.flatten() {
+        let path = item.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        // 先取指纹再读内容：读取期间文件被改写时，下一轮轮询仍会发现变化
+        let stamp = file_stamp(&path);
+        match read_file(&path) {
+            Ok(credential) => {
+                entries.insert(
+                    credential.id.clone(),
+                    PoolEntry {
+                        credential,
+                        path: Some(path),
+                        stamp,
+                    },
+                );
+            }
+            Err(e) => warn!("跳过无法解析的凭证文件 {}: {}", path.display(), e),
+        }
+    }
+    Ok(entries)
+}
+
+/// 把扫描结果合并进凭证池
+///
+/// 文件指纹与上次读写时一致的凭证保留内存中的状态（例如刚刷新的 token、冷却记录），
+/// 只有文件被外部修改过的凭证才用文件内容替换。
+fn merge_scanned(
+    pool: &mut BTreeMap<String, PoolEntry>,
+    scanned: BTreeMap<String, PoolEntry>,
+) -> PoolDiff {
+    let mut diff = PoolDiff::default();
+    pool.retain(|id, _| {
+        let keep = scanned.contains_key(id);
+        if !keep {
+            diff.removed.push(id.clone());
+        }
+        keep
+    });
+    for (id, entry) in scanned {
+        match pool.get(&id) {
+            None => diff.added.push(id.clone()),
+            Some(old) => {
+                // 文件自上次读写后没有变化
+                if old.path == entry.path && old.stamp.is_some() && old.stamp == entry.stamp {
+                    continue;
+                }
+                if old.credential != entry.credential {
+                    diff.updated.push(id.clone());
+                }
+            }
+        }
+        pool.insert(id, entry);
+    }
+    diff
+}
+
+fn log_diff(diff: &PoolDiff) {
+    if !diff.is_empty() {
+        info!(
+            "凭证池已更新: +{} -{} ~{}",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.updated.len()
+        );
+    }
+}
+
+/// 从存储目录重新加载凭证池并返回变化
+pub fn reload_from_dir(dir: &Path) -> Result<PoolDiff> {
+    // 扫描和合并都在写锁内进行，避免与 upsert / remove 交错
+    let mut pool = POOL.write().unwrap();
+    let scanned = scan_dir(dir)?;
+    let diff = merge_scanned(&mut pool, scanned);
+    log_diff(&diff);
+    Ok(diff)
+}
+
+/// 取消配置存储目录后，移除来自凭证文件的凭证
+pub fn unload_store() -> PoolDiff {
+    let mut pool = POOL.write().unwrap();
+    let mut diff = PoolDiff::default();
+    pool.retain(|id, entry| {
+        let keep = entry.path.is_none();
+        if !keep {
+            diff.removed.push(id.clone());
+        }
+        keep
+    });
+    log_diff(&diff);
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_file_uses_file_stem_as_id() {
+        let dir = std::env::temp_dir().join(format!("antigravity-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("alice.json");
+        std::fs::write(&path, r#"{"access_token": "t", "email": "a@example.com"}"#).unwrap();
+        std::fs::write(dir.join("notes.txt"), "ignored").unwrap();
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+
+        let entries = scan_dir(&dir).unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries["alice"];
+        assert_eq!(entry.credential.access_token.as_deref(), Some("t"));
+        assert_eq!(entry.path.as_deref(), Some(path.as_path()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn entry(
+        base: &AntigravityCredentials,
+        id: &str,
+        token: &str,
+        stamp: Option<FileStamp>,
+    ) -> PoolEntry {
+        PoolEntry {
+            credential: AntigravityCredentials {
+                id: id.to_string(),
+                access_token: Some(token.to_string()),
+                ..base.clone()
+            },
+            path: Some(PathBuf::from(format!("/store/{}.json", id))),
+            stamp,
+        }
+    }
+
+    #[test]
+    fn test_merge_scanned_diff() {
+        let t0 = (SystemTime::UNIX_EPOCH, 10);
+        let t1 = (SystemTime::UNIX_EPOCH + Duration::from_secs(1), 10);
+        // Default 带有创建时间，统一从同一个凭证派生
+        let base = AntigravityCredentials::default();
+        let mut pool = BTreeMap::new();
+        // 内存中已刷新 token，文件指纹未变
+        pool.insert(
+            "fresh".to_string(),
+            entry(&base, "fresh", "refreshed", Some(t0)),
+        );
+        pool.insert(
+            "edited".to_string(),
+            entry(&base, "edited", "old", Some(t0)),
+        );
+        pool.insert(
+            "touched".to_string(),
+            entry(&base, "touched", "same", Some(t0)),
+        );
+        pool.insert("gone".to_string(), entry(&base, "gone", "t", Some(t0)));
+
+        let scanned: BTreeMap<_, _> = [
+            entry(&base, "fresh", "stale", Some(t0)),
+            entry(&base, "edited", "new", Some(t1)),
+            entry(&base, "touched", "same", Some(t1)),
+            entry(&base, "new", "t", Some(t0)),
+        ]
+        .into_iter()
+        .map(|e| (e.credential.id.clone(), e))
+        .collect();
+
+        let diff = merge_scanned(&mut pool, scanned);
+        assert_eq!(
+            diff,
+            PoolDiff {
+                added: vec!["new".to_string()],
+                removed: vec!["gone".to_string()],
+                updated: vec!["edited".to_string()],
+            }
+        );
+        let token = |id: &str| pool[id].credential.access_token.clone().unwrap();
+        assert_eq!(token("fresh"), "refreshed");
+        assert_eq!(token("edited"), "new");
+        assert_eq!(pool["touched"].stamp, Some(t1));
+        assert_eq!(pool.len(), 4);
+    }
+
+    #[test]
+    fn test_cooldown_is_per_model() {
+        let now = Utc::now();
+        let mut credential = AntigravityCredentials {
+            access_token: Some("t".to_string()),
+            ..Default::default()
+        };
+        credential.cooldowns.insert(
+            "gemini-3-pro-preview".to_string(),
+            (now + chrono::Duration::seconds(60)).to_rfc3339(),
+        );
+        credential.cooldowns.insert(
+            "gemini-2.5-pro".to_string(),
+            (now - chrono::Duration::seconds(1)).to_rfc3339(),
+        );
+
+        assert!(cooldown_until(&credential, "gemini-3-pro-preview", now).is_some());
+        assert!(cooldown_until(&credential, "gemini-2.5-pro", now).is_none());
+        assert!(cooldown_until(&credential, "gemini-2.5-flash", now).is_none());
+    }
+
+    #[test]
+    fn test_refresh_backoff_is_temporary() {
+        let now = Utc::now();
+        let mut credential = AntigravityCredentials {
+            refresh_token: Some("r".to_string()),
+            refresh_retry_at: Some((now + chrono::Duration::seconds(60)).to_rfc3339()),
+            ..Default::default()
+        };
+        assert!(!is_eligible(&credential, now));
+        assert!(is_eligible(
+            &credential,
+            now + chrono::Duration::seconds(61)
+        ));
+
+        credential.refresh_retry_at = None;
+        assert!(is_eligible(&credential, now));
+        credential.is_healthy = false;
+        assert!(!is_eligible(&credential, now + chrono::Duration::days(1)));
+    }
+
+    #[test]
+    fn test_update_keeps_concurrent_changes() {
+        let credential = AntigravityCredentials {
+            id: "pool-update-test".to_string(),
+            access_token: Some("old".to_string()),
+            ..Default::default()
+        };
+        upsert(credential.clone()).unwrap();
+        assert!(update("pool-update-missing", |c| c.disabled = true)
+            .unwrap()
+            .is_none());
+
+        // 其他请求在此期间刷新了 Token 并冷却了另一个模型
+        let stale = get("pool-update-test").unwrap();
+        update(&stale.id, |c| c.access_token = Some("new".to_string())).unwrap();
+        cool_down(&stale.id, "gemini-2.5-pro", Duration::from_secs(60), "429").unwrap();
+        cool_down(
+            &stale.id,
+            "gemini-2.5-flash",
+            Duration::from_secs(60),
+            "429",
+        )
+        .unwrap();
+        clear_cooldown(&stale.id, "gemini-2.5-flash").unwrap();
+
+        let current = get(&stale.id).unwrap();
+        assert_eq!(current.access_token.as_deref(), Some("new"));
+        assert!(current.cooldowns.contains_key("gemini-2.5-pro"));
+        assert!(!current.cooldowns.contains_key("gemini-2.5-flash"));
+        assert_eq!(current.rate_limit_status.as_deref(), Some("429"));
+        remove(&stale.id).unwrap();
+    }
+}
@@ -0,0 +1,687 @@
+//! Anthropic Messages API 与 Gemini generateContent 之间的转换
+
+use super::media;
+use super::models;
+use super::safety::{self, SafetyBlock};
+use super::schema::{self, ToolChoice};
+use super::thinking::{self, ThinkingRequest};
+use super::ConvertError;
+use crate::config;
+use crate::fallback::ModelFallback;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+
+/// Messages 请求（未列出的字段会被忽略）
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessagesRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+    #[serde(default)]
+    pub system: Option<SystemPrompt>,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    #[serde(default)]
+    pub top_p: Option<f64>,
+    #[serde(default)]
+    pub top_k: Option<u32>,
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub tools: Vec<Tool>,
+    #[serde(default)]
+    pub tool_choice: Option<serde_json::Value>,
+    #[serde(default)]
+    pub thinking: Option<serde_json::Value>,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+    /// 扩展字段：按请求覆盖安全设置
+    #[serde(default)]
+    pub safety_settings: Option<serde_json::Value>,
+    /// 扩展字段：为 false 时不回退到其他模型
+    #[serde(default)]
+    pub model_fallback: Option<bool>,
+}
+
+/// system 可以是字符串或文本块数组
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum SystemPrompt {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+impl SystemPrompt {
+    fn text(&self) -> String {
+        match self {
+            SystemPrompt::Text(t) => t.clone(),
+            SystemPrompt::Blocks(blocks) => blocks
+                .iter()
+                .filter_map(|b| match b {
+                    ContentBlock::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+impl Role {
+    fn gemini_role(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "model",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: MessageContent,
+}
+
+/// 消息内容可以是字符串或内容块数组
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+/// 内容块
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    Document {
+        source: DocumentSource,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        #[serde(default)]
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        #[serde(default)]
+        content: Option<ToolResultContent>,
+        #[serde(default)]
+        is_error: bool,
+    },
+    Thinking {
+        thinking: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+    },
+    RedactedThinking {
+        data: String,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DocumentSource {
+    Base64 { media_type: String, data: String },
+    Text { data: String },
+    Url { url: String },
+}
+
+/// tool_result 的内容可以是字符串或内容块数组
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ToolResultContent {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub input_schema: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    EndTurn,
+    MaxTokens,
+    StopSequence,
+    ToolUse,
+    Refusal,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u64>,
+}
+
+/// Messages 响应
+#[derive(Debug, Clone, Serialize)]
+pub struct MessagesResponse {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub role: Role,
+    pub model: String,
+    pub content: Vec<ContentBlock>,
+    pub stop_reason: Option<StopReason>,
+    pub stop_sequence: Option<String>,
+    pub usage: Usage,
+    /// 扩展字段：因安全原因被拦截时的类别
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub safety_block: Option<SafetyBlock>,
+    /// 扩展字段：请求的模型配额耗尽、改用其他模型时的回退信息
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_fallback: Option<ModelFallback>,
+}
+
+/// 将 Messages 请求转换为 Gemini generateContent 请求
+pub fn to_gemini(request: &MessagesRequest) -> Result<serde_json::Value, ConvertError> {
+    if request.messages.is_empty() {
+        return Err(ConvertError::InvalidRequest(
+            "messages 不能为空".to_string(),
+        ));
+    }
+    let model = models::resolve(&request.model)?;
+    let max_tokens = request.max_tokens.map(|v| model.clamp_output(v));
+
+    // tool_result 只带 tool_use_id，functionResponse 需要函数名
+    let mut tool_names: HashMap<&str, &str> = HashMap::new();
+    let mut contents: Vec<serde_json::Value> = Vec::new();
+
+    for message in &request.messages {
+        let mut parts = Vec::new();
+        match &message.content {
+            MessageContent::Text(text) => {
+                if !text.is_empty() {
+                    parts.push(json!({"text": text}));
+                }
+            }
+            MessageContent::Blocks(blocks) => {
+                for block in blocks {
+                    if let ContentBlock::ToolUse { id, name, .. } = block {
+                        tool_names.insert(id, name);
+                    }
+                    block_to_parts(block, &tool_names, &mut parts)?;
+                }
+            }
+        }
+        if parts.is_empty() {
+            continue;
+        }
+
+        let role = message.role.gemini_role();
+        // Gemini 要求相邻消息角色交替，连续的同角色消息合并
+        match contents.last_mut() {
+            Some(last) if last["role"] == role => {
+                last["parts"].as_array_mut().unwrap().extend(parts);
+            }
+            _ => contents.push(json!({"role": role, "parts": parts})),
+        }
+    }
+
+    let mut gemini = json!({"contents": contents});
+
+    if let Some(system) = &request.system {
+        let text = system.text();
+        if !text.is_empty() {
+            gemini["systemInstruction"] = json!({"role": "user", "parts": [{"text": text}]});
+        }
+    }
+
+    let mut generation_config = serde_json::Map::new();
+    if let Some(v) = max_tokens {
+        generation_config.insert("maxOutputTokens".to_string(), json!(v));
+    }
+    if let Some(v) = request.temperature {
+        generation_config.insert("temperature".to_string(), json!(v));
+    }
+    if let Some(v) = request.top_p {
+        generation_config.insert("topP".to_string(), json!(v));
+    }
+    if let Some(v) = request.top_k {
+        generation_config.insert("topK".to_string(), json!(v));
+    }
+    if !request.stop_sequences.is_empty() {
+        generation_config.insert("stopSequences".to_string(), json!(request.stop_sequences));
+    }
+    if !generation_config.is_empty() {
+        gemini["generationConfig"] = serde_json::Value::Object(generation_config);
+    }
+    // 未开启 thinking 的 Claude 客户端不返回思考块
+    thinking::apply(
+        &mut gemini,
+        &model.upstream_model,
+        ThinkingRequest::from_anthropic(request.thinking.as_ref(), max_tokens)?,
+        &config::current().settings.reasoning,
+        false,
+    )?;
+
+    if !request.tools.is_empty() {
+        let declarations: Vec<serde_json::Value> = request
+            .tools
+            .iter()
+            .map(|tool| {
+                schema::function_declaration(
+                    &tool.name,
+                    tool.description.as_deref(),
+                    tool.input_schema.as_ref(),
+                )
+            })
+            .collect();
+        gemini["tools"] = json!([{"functionDeclarations": declarations}]);
+    }
+    if let Some(choice) = &request.tool_choice {
+        let choice = ToolChoice::from_anthropic(choice)?;
+        let names: Vec<&str> = request.tools.iter().map(|t| t.name.as_str()).collect();
+        schema::check_tool_choice(&choice, &names)?;
+        gemini["toolConfig"] = choice.to_gemini();
+    }
+
+    if let Some(overrides) = &request.safety_settings {
+        gemini["safetySettings"] = json!(safety::parse_overrides(overrides)?);
+    }
+    model.validate(&gemini)?;
+    media::check_total(&gemini)?;
+
+    Ok(gemini)
+}
+
+fn image_part(source: &ImageSource) -> Result<serde_json::Value, ConvertError> {
+    match source {
+        ImageSource::Base64 { media_type, data } => media::inline_data(Some(media_type), data),
+        ImageSource::Url { url } => Err(ConvertError::Unsupported(format!(
+            "URL 图片 ({})，请改用 base64",
+            url
+        ))),
+    }
+}
+
+/// 文档块：PDF 转为 inlineData，纯文本文档直接作为文本
+fn document_part(source: &DocumentSource) -> Result<serde_json::Value, ConvertError> {
+    match source {
+        DocumentSource::Base64 { media_type, data } => media::inline_data(Some(media_type), data),
+        DocumentSource::Text { data } => Ok(json!({"text": data})),
+        DocumentSource::Url { url } => Err(ConvertError::Unsupported(format!(
+            "URL 文档 ({})，请改用 base64",
+            url
+        ))),
+    }
+}
+
+fn block_to_parts(
+    block: &ContentBlock,
+    tool_names: &HashMap<&str, &str>,
+    parts: &mut Vec<serde_json::Value>,
+) -> Result<(), ConvertError> {
+    match block {
+        ContentBlock::Text { text } => {
+            if !text.is_empty() {
+                parts.push(json!({"text": text}));
+            }
+        }
+        ContentBlock::Image { source } => parts.push(image_part(source)?),
+        ContentBlock::Document { source, .. } => parts.push(document_part(source)?),
+        ContentBlock::ToolUse { id, name, input } => {
+            let args = if input.is_null() {
+                json!({})
+            } else {
+                input.clone()
+            };
+            parts.push(json!({"functionCall": {"id": id, "name": name, "args": args}}));
+        }
+        ContentBlock::ToolResult {
+            tool_use_id,
+            content,
+            is_error,
+        } => {
+            let name = tool_names.get(tool_use_id.as_str()).ok_or_else(|| {
+                ConvertError::InvalidRequest(format!(
+                    "tool_result 引用了未知的 tool_use_id: {}",
+                    tool_use_id
+                ))
+            })?;
+
+            let mut texts = Vec::new();
+            let mut extra = Vec::new();
+            match content {
+                Some(ToolResultContent::Text(t)) => texts.push(t.clone()),
+                Some(ToolResultContent::Blocks(blocks)) => {
+                    for b in blocks {
+                        match b {
+                            ContentBlock::Text { text } => texts.push(text.clone()),
+                            ContentBlock::Image { source } => extra.push(image_part(source)?),
+                            ContentBlock::Document { source, .. } => {
+                                extra.push(document_part(source)?)
+                            }
+                            _ => {
+                                return Err(ConvertError::Unsupported(
+                                    "tool_result 中只支持 text、image 和 document 块".to_string(),
+                                ))
+                            }
+                        }
+                    }
+                }
+                None => {}
+            }
+            let output = texts.join("\n");
+            let response = if *is_error {
+                json!({"error": output})
+            } else {
+                json!({"output": output})
+            };
+            parts.push(json!({
+                "functionResponse": {"id": tool_use_id, "name": name, "response": response}
+            }));
+            parts.extend(extra);
+        }
+        ContentBlock::Thinking {
+            thinking,
+            signature,
+        } => {
+            // 没有签名的思考内容无法回传给上游，直接丢弃
+            if let Some(signature) = signature {
+                parts.push(json!({
+                    "text": thinking,
+                    "thought": true,
+                    "thoughtSignature": signature
+                }));
+            }
+        }
+        ContentBlock::RedactedThinking { .. } => {}
+        ContentBlock::Unknown => {
+            return Err(ConvertError::Unsupported("未知的内容块类型".to_string()))
+        }
+    }
+    Ok(())
+}
+
+/// Gemini finishReason 对应的 stop_reason
+pub fn stop_reason(finish_reason: Option<&str>, has_tool_use: bool) -> Option<StopReason> {
+    let reason = finish_reason?;
+    Some(match reason {
+        _ if has_tool_use => StopReason::ToolUse,
+        "STOP" => StopReason::EndTurn,
+        "MAX_TOKENS" => StopReason::MaxTokens,
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" | "IMAGE_SAFETY" => {
+            StopReason::Refusal
+        }
+        _ => StopReason::EndTurn,
+    })
+}
+
+/// 从 Gemini usageMetadata 计算用量（思考 token 计入输出）
+pub fn usage(usage_metadata: &serde_json::Value) -> Usage {
+    let count = |key: &str| usage_metadata[key].as_u64().unwrap_or(0);
+    Usage {
+        input_tokens: count("promptTokenCount"),
+        output_tokens: count("candidatesTokenCount") + count("thoughtsTokenCount"),
+        cache_read_input_tokens: usage_metadata["cachedContentTokenCount"].as_u64(),
+    }
+}
+
+/// 将单个 Gemini part 转换为内容块
+///
+/// ordinal 为该 part 之前已出现的 functionCall 数量，用于生成稳定的 tool_use ID。
+pub fn part_to_block(
+    part: &serde_json::Value,
+    response_id: Option<&str>,
+    ordinal: usize,
+) -> Option<ContentBlock> {
+    if let Some(call) = part.get("functionCall") {
+        return Some(ContentBlock::ToolUse {
+            id: schema::stable_call_id("toolu_", response_id, ordinal, call),
+            name: call["name"].as_str().unwrap_or_default().to_string(),
+            input: call.get("args").cloned().unwrap_or_else(|| json!({})),
+        });
+    }
+    let text = part["text"].as_str()?;
+    if part["thought"].as_bool() == Some(true) {
+        Some(ContentBlock::Thinking {
+            thinking: text.to_string(),
+            signature: part["thoughtSignature"].as_str().map(String::from),
+        })
+    } else {
+        Some(ContentBlock::Text {
+            text: text.to_string(),
+        })
+    }
+}
+
+/// 将 Gemini 响应转换为 Messages 响应
+pub fn from_gemini(response: &serde_json::Value, model: &str) -> MessagesResponse {
+    let candidate = &response["candidates"][0];
+    let response_id = response["responseId"].as_str();
+    let mut calls = 0;
+    let mut content = Vec::new();
+    for part in candidate["content"]["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        if let Some(block) = part_to_block(part, response_id, calls) {
+            if matches!(block, ContentBlock::ToolUse { .. }) {
+                calls += 1;
+            }
+            content.push(block);
+        }
+    }
+
+    let has_tool_use = content
+        .iter()
+        .any(|b| matches!(b, ContentBlock::ToolUse { .. }));
+    // 提示词被拦截时没有候选结果
+    let stop = if response["promptFeedback"]["blockReason"].is_string() {
+        Some(StopReason::Refusal)
+    } else {
+        stop_reason(candidate["finishReason"].as_str(), has_tool_use)
+    };
+
+    let model_fallback = ModelFallback::from_response(response);
+    MessagesResponse {
+        id: message_id(response),
+        kind: "message",
+        role: Role::Assistant,
+        model: model_fallback
+            .as_ref()
+            .map_or(model, |f| f.model.as_str())
+            .to_string(),
+        content,
+        stop_reason: stop,
+        stop_sequence: None,
+        usage: usage(&response["usageMetadata"]),
+        safety_block: safety::blocked(response),
+        model_fallback,
+    }
+}
+
+/// 响应 ID：优先沿用上游 responseId
+pub fn message_id(response: &serde_json::Value) -> String {
+    match response["responseId"].as_str() {
+        Some(id) => format!("msg_{}", id),
+        None => format!("msg_{}", uuid::Uuid::new_v4().simple()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: serde_json::Value) -> MessagesRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_to_gemini_system_tools_and_merge() {
+        let request = parse(json!({
+            "model": "gemini-claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "system": [{"type": "text", "text": "You are helpful."}],
+            "messages": [
+                {"role": "user", "content": "What's the weather?"},
+                {"role": "assistant", "content": [
+                    {"type": "text", "text": "Let me check."},
+                    {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "Sunny"}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBO"}}
+                ]}
+            ],
+            "tools": [{"name": "get_weather", "description": "Weather", "input_schema": {"type": "object"}}]
+        }));
+
+        let gemini = to_gemini(&request).unwrap();
+        assert_eq!(
+            gemini["systemInstruction"]["parts"][0]["text"],
+            "You are helpful."
+        );
+        assert_eq!(gemini["generationConfig"]["maxOutputTokens"], 1024);
+        assert_eq!(
+            gemini["tools"][0]["functionDeclarations"][0]["name"],
+            "get_weather"
+        );
+
+        let contents = gemini["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(
+            contents[1]["parts"][1]["functionCall"],
+            json!({"id": "toolu_1", "name": "get_weather", "args": {"city": "Paris"}})
+        );
+        // 两条连续的 user 消息合并
+        assert_eq!(contents[2]["role"], "user");
+        assert_eq!(
+            contents[2]["parts"][0]["functionResponse"],
+            json!({"id": "toolu_1", "name": "get_weather", "response": {"output": "Sunny"}})
+        );
+        assert_eq!(
+            contents[2]["parts"][1]["inlineData"]["mimeType"],
+            "image/png"
+        );
+    }
+
+    #[test]
+    fn test_to_gemini_rejects_unknown_tool_result() {
+        let request = parse(json!({
+            "model": "gemini-claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": "missing", "content": "x"}
+            ]}]
+        }));
+        assert!(matches!(
+            to_gemini(&request),
+            Err(ConvertError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_gemini_tool_use_and_usage() {
+        let response = json!({
+            "responseId": "abc",
+            "candidates": [{
+                "content": {"role": "model", "parts": [
+                    {"text": "plan", "thought": true, "thoughtSignature": "sig"},
+                    {"text": "Checking."},
+                    {"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}}
+                ]},
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": {"promptTokenCount": 12, "candidatesTokenCount": 8, "thoughtsTokenCount": 4}
+        });
+
+        let message = from_gemini(&response, "gemini-claude-sonnet-4-5-thinking");
+        assert_eq!(message.id, "msg_abc");
+        assert_eq!(message.stop_reason, Some(StopReason::ToolUse));
+        assert_eq!(
+            message.content[0],
+            ContentBlock::Thinking {
+                thinking: "plan".to_string(),
+                signature: Some("sig".to_string())
+            }
+        );
+        match &message.content[2] {
+            ContentBlock::ToolUse { id, name, input } => {
+                assert!(id.starts_with("toolu_"));
+                assert_eq!(name, "get_weather");
+                assert_eq!(input["city"], "Paris");
+            }
+            other => panic!("unexpected block: {:?}", other),
+        }
+        assert_eq!(message.usage.input_tokens, 12);
+        assert_eq!(message.usage.output_tokens, 12);
+
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["type"], "message");
+        assert_eq!(value["stop_reason"], "tool_use");
+        assert!(value.get("model_fallback").is_none());
+    }
+
+    #[test]
+    fn test_from_gemini_model_fallback() {
+        let mut response = json!({"candidates": [{"content": {"parts": [{"text": "hi"}]}}]});
+        ModelFallback {
+            requested_model: "gemini-3-pro-preview".to_string(),
+            model: "gemini-2.5-pro".to_string(),
+        }
+        .annotate(&mut response);
+
+        let value = serde_json::to_value(from_gemini(&response, "gemini-3-pro-preview")).unwrap();
+        assert_eq!(value["model"], "gemini-2.5-pro");
+        assert_eq!(
+            value["model_fallback"],
+            json!({"requested_model": "gemini-3-pro-preview", "model": "gemini-2.5-pro"})
+        );
+    }
+
+    #[test]
+    fn test_stop_reason_mapping() {
+        assert_eq!(
+            stop_reason(Some("MAX_TOKENS"), false),
+            Some(StopReason::MaxTokens)
+        );
+        assert_eq!(
+            stop_reason(Some("SAFETY"), false),
+            Some(StopReason::Refusal)
+        );
+        assert_eq!(stop_reason(None, false), None);
+    }
+}
@@ -0,0 +1,388 @@
+//! Gemini 流式分块到 Anthropic Messages 流式事件的转换
+//!
+//! 事件顺序：message_start → (content_block_start → content_block_delta* →
+//! content_block_stop)* → message_delta → message_stop。
+
+use super::anthropic::{self, ContentBlock, MessagesResponse, Role, StopReason};
+use super::safety;
+use crate::fallback::ModelFallback;
+use serde_json::json;
+
+/// 一个 Anthropic 流式事件
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamEvent {
+    pub event: &'static str,
+    pub data: serde_json::Value,
+}
+
+impl StreamEvent {
+    fn new(event: &'static str, mut data: serde_json::Value) -> Self {
+        data["type"] = json!(event);
+        Self { event, data }
+    }
+
+    /// 流中途失败时发送的 error 事件
+    pub fn error(kind: &str, message: &str) -> Self {
+        Self::new(
+            "error",
+            json!({"error": {"type": kind, "message": message}}),
+        )
+    }
+
+    /// SSE 格式
+    pub fn to_sse(&self) -> String {
+        format!("event: {}\ndata: {}\n\n", self.event, self.data)
+    }
+}
+
+/// 流式转换器：逐个输入 Gemini 分块，输出对应的事件，同时累积完整消息
+pub struct StreamConverter {
+    model: String,
+    message: Option<MessagesResponse>,
+    /// 当前未关闭的内容块在 content 中的下标
+    open: Option<usize>,
+    finish_reason: Option<String>,
+    usage_metadata: serde_json::Value,
+    response_id: Option<String>,
+    /// 已输出的 tool_use 数量
+    tool_calls: usize,
+    blocked: bool,
+    finished: bool,
+}
+
+impl StreamConverter {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            message: None,
+            open: None,
+            finish_reason: None,
+            usage_metadata: json!({}),
+            response_id: None,
+            tool_calls: 0,
+            blocked: false,
+            finished: false,
+        }
+    }
+
+    fn start(&mut self, chunk: &serde_json::Value, events: &mut Vec<StreamEvent>) {
+        if self.message.is_some() {
+            return;
+        }
+        self.response_id = chunk["responseId"].as_str().map(String::from);
+        let model_fallback = ModelFallback::from_response(chunk);
+        let message = MessagesResponse {
+            id: anthropic::message_id(chunk),
+            kind: "message",
+            role: Role::Assistant,
+            model: model_fallback
+                .as_ref()
+                .map_or(&self.model, |f| &f.model)
+                .clone(),
+            content: Vec::new(),
+            stop_reason: None,
+            stop_sequence: None,
+            usage: anthropic::usage(&chunk["usageMetadata"]),
+            safety_block: None,
+            model_fallback,
+        };
+        let mut start = json!(message);
+        start["usage"]["output_tokens"] = json!(0);
+        events.push(StreamEvent::new("message_start", json!({"message": start})));
+        self.message = Some(message);
+    }
+
+    fn content(&mut self) -> &mut Vec<ContentBlock> {
+        &mut self.message.as_mut().unwrap().content
+    }
+
+    fn close_block(&mut self, events: &mut Vec<StreamEvent>) {
+        if let Some(index) = self.open.take() {
+            events.push(StreamEvent::new(
+                "content_block_stop",
+                json!({"index": index}),
+            ));
+        }
+    }
+
+    /// 打开新内容块，返回其下标
+    fn open_block(&mut self, block: ContentBlock, events: &mut Vec<StreamEvent>) -> usize {
+        self.close_block(events);
+        let index = self.content().len();
+        // 起始事件中内容为空，实际内容通过 delta 发送
+        let empty = match &block {
+            ContentBlock::Text { .. } => json!({"type": "text", "text": ""}),
+            ContentBlock::Thinking { .. } => json!({"type": "thinking", "thinking": ""}),
+            ContentBlock::ToolUse { id, name, .. } => {
+                json!({"type": "tool_use", "id": id, "name": name, "input": {}})
+            }
+            other => json!(other),
+        };
+        events.push(StreamEvent::new(
+            "content_block_start",
+            json!({"index": index, "content_block": empty}),
+        ));
+        self.content().push(block);
+        self.open = Some(index);
+        index
+    }
+
+    fn delta(&mut self, index: usize, delta: serde_json::Value, events: &mut Vec<StreamEvent>) {
+        events.push(StreamEvent::new(
+            "content_block_delta",
+            json!({"index": index, "delta": delta}),
+        ));
+    }
+
+    fn open_kind(&self) -> Option<&ContentBlock> {
+        let index = self.open?;
+        self.message.as_ref()?.content.get(index)
+    }
+
+    fn push_part(&mut self, part: &serde_json::Value, events: &mut Vec<StreamEvent>) {
+        let Some(block) =
+            anthropic::part_to_block(part, self.response_id.as_deref(), self.tool_calls)
+        else {
+            return;
+        };
+        match block {
+            ContentBlock::ToolUse { id, name, input } => {
+                self.tool_calls += 1;
+                // Gemini 一次返回完整的函数调用，打开后立即关闭
+                let index = self.open_block(
+                    ContentBlock::ToolUse {
+                        id,
+                        name,
+                        input: input.clone(),
+                    },
+                    events,
+                );
+                self.delta(
+                    index,
+                    json!({"type": "input_json_delta", "partial_json": input.to_string()}),
+                    events,
+                );
+                self.close_block(events);
+            }
+            ContentBlock::Thinking {
+                thinking,
+                signature,
+            } => {
+                let index = match self.open_kind() {
+                    Some(ContentBlock::Thinking { .. }) => self.open.unwrap(),
+                    _ => self.open_block(
+                        ContentBlock::Thinking {
+                            thinking: String::new(),
+                            signature: None,
+                        },
+                        events,
+                    ),
+                };
+                if !thinking.is_empty() {
+                    if let Some(ContentBlock::Thinking { thinking: acc, .. }) =
+                        self.content().get_mut(index)
+                    {
+                        acc.push_str(&thinking);
+                    }
+                    self.delta(
+                        index,
+                        json!({"type": "thinking_delta", "thinking": thinking}),
+                        events,
+                    );
+                }
+                if let Some(signature) = signature {
+                    if let Some(ContentBlock::Thinking { signature: acc, .. }) =
+                        self.content().get_mut(index)
+                    {
+                        *acc = Some(signature.clone());
+                    }
+                    self.delta(
+                        index,
+                        json!({"type": "signature_delta", "signature": signature}),
+                        events,
+                    );
+                }
+            }
+            ContentBlock::Text { text } => {
+                if text.is_empty() {
+                    return;
+                }
+                let index = match self.open_kind() {
+                    Some(ContentBlock::Text { .. }) => self.open.unwrap(),
+                    _ => self.open_block(
+                        ContentBlock::Text {
+                            text: String::new(),
+                        },
+                        events,
+                    ),
+                };
+                if let Some(ContentBlock::Text { text: acc }) = self.content().get_mut(index) {
+                    acc.push_str(&text);
+                }
+                self.delta(index, json!({"type": "text_delta", "text": text}), events);
+            }
+            _ => {}
+        }
+    }
+
+    /// 输入一个 Gemini 分块（已解包 response），返回产生的事件
+    pub fn push(&mut self, chunk: &serde_json::Value) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        self.start(chunk, &mut events);
+
+        let candidate = &chunk["candidates"][0];
+        if let Some(parts) = candidate["content"]["parts"].as_array() {
+            for part in parts {
+                self.push_part(part, &mut events);
+            }
+        }
+        if let Some(reason) = candidate["finishReason"].as_str() {
+            self.finish_reason = Some(reason.to_string());
+        }
+        if chunk["promptFeedback"]["blockReason"].is_string() {
+            self.blocked = true;
+        }
+        if let Some(block) = safety::blocked(chunk) {
+            self.message.as_mut().unwrap().safety_block = Some(block);
+        }
+        if let Some(usage) = chunk["usageMetadata"].as_object() {
+            for (k, v) in usage {
+                self.usage_metadata[k] = v.clone();
+            }
+        }
+        events
+    }
+
+    /// 结束流：关闭内容块并发送 message_delta 与 message_stop
+    pub fn finish(&mut self) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+        self.finished = true;
+        self.start(&json!({}), &mut events);
+        self.close_block(&mut events);
+
+        let has_tool_use = self
+            .content()
+            .iter()
+            .any(|b| matches!(b, ContentBlock::ToolUse { .. }));
+        let stop_reason = if self.blocked {
+            Some(StopReason::Refusal)
+        } else {
+            anthropic::stop_reason(self.finish_reason.as_deref(), has_tool_use)
+                .or(Some(StopReason::EndTurn))
+        };
+        let usage = anthropic::usage(&self.usage_metadata);
+
+        let message = self.message.as_mut().unwrap();
+        message.stop_reason = stop_reason;
+        message.usage = usage.clone();
+
+        let mut delta = json!({
+            "delta": {"stop_reason": stop_reason, "stop_sequence": null},
+            "usage": usage
+        });
+        if let Some(block) = &message.safety_block {
+            delta["safety_block"] = json!(block);
+        }
+        events.push(StreamEvent::new("message_delta", delta));
+        events.push(StreamEvent::new("message_stop", json!({})));
+        events
+    }
+
+    /// 流结束后累积的完整消息
+    pub fn message(&self) -> Option<&MessagesResponse> {
+        self.message.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(events: &[StreamEvent]) -> Vec<&'static str> {
+        events.iter().map(|e| e.event).collect()
+    }
+
+    #[test]
+    fn test_interleaved_blocks_keep_indices() {
+        let mut converter = StreamConverter::new("gemini-claude-sonnet-4-5-thinking");
+        let mut events = converter.push(&json!({
+            "responseId": "r1",
+            "candidates": [{"content": {"parts": [{"text": "think", "thought": true}]}}],
+            "usageMetadata": {"promptTokenCount": 7}
+        }));
+        events.extend(converter.push(&json!({
+            "candidates": [{"content": {"parts": [
+                {"text": "", "thought": true, "thoughtSignature": "sig"},
+                {"text": "Hel"}
+            ]}}]
+        })));
+        events.extend(converter.push(&json!({
+            "candidates": [{"content": {"parts": [
+                {"text": "lo"},
+                {"functionCall": {"id": "call_1", "name": "lookup", "args": {"q": "x"}}},
+                {"text": "after"}
+            ]}, "finishReason": "STOP"}],
+            "usageMetadata": {"promptTokenCount": 7, "candidatesTokenCount": 9}
+        })));
+        events.extend(converter.finish());
+
+        assert_eq!(
+            names(&events),
+            vec![
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        assert_eq!(events[0].data["message"]["id"], "msg_r1");
+        assert_eq!(events[0].data["message"]["usage"]["input_tokens"], 7);
+        assert_eq!(events[3].data["delta"]["type"], "signature_delta");
+        assert_eq!(events[9].data["index"], 2);
+        assert_eq!(events[9].data["content_block"]["type"], "tool_use");
+        assert_eq!(events[10].data["delta"]["partial_json"], "{\"q\":\"x\"}");
+        assert_eq!(events[12].data["index"], 3);
+        assert_eq!(events[15].data["delta"]["stop_reason"], "tool_use");
+        assert_eq!(events[15].data["usage"]["output_tokens"], 9);
+
+        let message = converter.message().unwrap();
+        assert_eq!(message.content.len(), 4);
+        assert_eq!(
+            message.content[1],
+            ContentBlock::Text {
+                text: "Hello".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_empty_stream_still_terminates() {
+        let mut converter = StreamConverter::new("m");
+        let events = converter.finish();
+        assert_eq!(
+            names(&events),
+            vec!["message_start", "message_delta", "message_stop"]
+        );
+        assert_eq!(events[1].data["delta"]["stop_reason"], "end_turn");
+        assert!(converter.finish().is_empty());
+        assert!(StreamEvent::error("api_error", "boom")
+            .to_sse()
+            .starts_with("event: error\ndata: {"));
+    }
+}
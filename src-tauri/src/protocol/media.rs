@@ -0,0 +1,229 @@
+//! 多模态内容：图片 / 文档转换为 Gemini inlineData，校验 MIME 类型与大小
+
+use super::ConvertError;
+use crate::config::{self, MediaSettings};
+use base64::Engine;
+use serde_json::json;
+use tracing::debug;
+
+/// 支持的图片类型
+pub const IMAGE_MIME_TYPES: [&str; 6] = [
+    "image/png",
+    "image/jpeg",
+    "image/webp",
+    "image/gif",
+    "image/heic",
+    "image/heif",
+];
+
+/// 支持的文档类型（纯文本文档直接转为文本，不经过 inlineData）
+pub const DOCUMENT_MIME_TYPES: [&str; 1] = ["application/pdf"];
+
+/// 常见别名
+fn normalize_mime(mime: &str) -> String {
+    let mime = mime
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match mime.as_str() {
+        "image/jpg" | "image/pjpeg" => "image/jpeg".to_string(),
+        "application/x-pdf" => "application/pdf".to_string(),
+        _ => mime,
+    }
+}
+
+/// 根据文件头识别类型
+fn sniff_mime(data: &str) -> Option<&'static str> {
+    // 24 个 base64 字符解码为 18 字节，足够识别常见文件头
+    let prefix: String = data.chars().take(24).collect();
+    let head = base64::engine::general_purpose::STANDARD_NO_PAD
+        .decode(prefix.trim_end_matches('='))
+        .ok()?;
+    if head.starts_with(b"\x89PNG") {
+        Some("image/png")
+    } else if head.starts_with(b"\xFF\xD8\xFF") {
+        Some("image/jpeg")
+    } else if head.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP") {
+        Some("image/webp")
+    } else if head.starts_with(b"%PDF") {
+        Some("application/pdf")
+    } else {
+        None
+    }
+}
+
+/// base64 数据解码后的字节数
+fn decoded_len(data: &str) -> usize {
+    let padding = data.bytes().rev().take_while(|b| *b == b'=').count();
+    (data.len() / 4 * 3 + data.len() % 4 * 3 / 4).saturating_sub(padding)
+}
+
+fn format_size(bytes: usize) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn is_image(mime: &str) -> bool {
+    IMAGE_MIME_TYPES.contains(&mime)
+}
+
+/// base64 数据转换为 inlineData part
+///
+/// 声明的类型与文件头不一致时以文件头为准（截图常被标成错误的类型）。
+pub fn inline_data(
+    declared_mime: Option<&str>,
+    data: &str,
+) -> Result<serde_json::Value, ConvertError> {
+    inline_data_with(&config::current().settings.media, declared_mime, data)
+}
+
+fn inline_data_with(
+    limits: &MediaSettings,
+    declared_mime: Option<&str>,
+    data: &str,
+) -> Result<serde_json::Value, ConvertError> {
+    if data.is_empty() {
+        return Err(ConvertError::InvalidRequest("内联数据为空".to_string()));
+    }
+    if !data
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'='))
+    {
+        return Err(ConvertError::InvalidRequest(
+            "内联数据不是合法的 base64".to_string(),
+        ));
+    }
+
+    let declared = declared_mime.map(normalize_mime);
+    let mime = match (declared.as_deref(), sniff_mime(data)) {
+        (Some(declared), Some(sniffed)) if declared != sniffed => {
+            debug!("内联数据声明为 {}，按文件头识别为 {}", declared, sniffed);
+            sniffed.to_string()
+        }
+        (_, Some(sniffed)) => sniffed.to_string(),
+        (Some(declared), None) => declared.to_string(),
+        (None, None) => {
+            return Err(ConvertError::InvalidRequest(
+                "无法识别内联数据的类型，请指定 media_type".to_string(),
+            ))
+        }
+    };
+    if !is_image(&mime) && !DOCUMENT_MIME_TYPES.contains(&mime.as_str()) {
+        return Err(ConvertError::Unsupported(format!(
+            "MIME 类型 {}（支持: {}, {}）",
+            mime,
+            IMAGE_MIME_TYPES.join(", "),
+            DOCUMENT_MIME_TYPES.join(", ")
+        )));
+    }
+
+    let (kind, limit) = if is_image(&mime) {
+        ("图片", limits.max_image_bytes)
+    } else {
+        ("文档", limits.max_document_bytes)
+    };
+    let size = decoded_len(data);
+    if size > limit as usize {
+        return Err(ConvertError::TooLarge(format!(
+            "{}（{}）大小 {} 超过上限 {}",
+            kind,
+            mime,
+            format_size(size),
+            format_size(limit as usize)
+        )));
+    }
+
+    Ok(json!({"inlineData": {"mimeType": mime, "data": data}}))
+}
+
+/// 解析 data URL（data:<mime>;base64,<data>）
+pub fn parse_data_url(url: &str) -> Result<(&str, &str), ConvertError> {
+    url.strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+        .ok_or_else(|| ConvertError::Unsupported("只支持 base64 data URL".to_string()))
+}
+
+/// 校验整个 Gemini 请求的内联数据总量
+pub fn check_total(request: &serde_json::Value) -> Result<(), ConvertError> {
+    check_total_with(&config::current().settings.media, request)
+}
+
+fn check_total_with(
+    limits: &MediaSettings,
+    request: &serde_json::Value,
+) -> Result<(), ConvertError> {
+    let total: usize = request["contents"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|content| content["parts"].as_array().into_iter().flatten())
+        .filter_map(|part| part["inlineData"]["data"].as_str())
+        .map(decoded_len)
+        .sum();
+    if total > limits.max_total_bytes as usize {
+        return Err(ConvertError::TooLarge(format!(
+            "请求中的内联数据共 {}，超过上限 {}",
+            format_size(total),
+            format_size(limits.max_total_bytes as usize)
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==";
+    const PDF: &str = "JVBERi0xLjQKJcfsj6IKMSAwIG9iago8PC9UeXBlL0NhdGFsb2c+PgplbmRvYmoK";
+
+    #[test]
+    fn test_mime_detection() {
+        let limits = MediaSettings::default();
+        // 声明为 jpeg 的 PNG 截图
+        let part = inline_data_with(&limits, Some("image/jpeg"), PNG).unwrap();
+        assert_eq!(part["inlineData"]["mimeType"], "image/png");
+
+        let part = inline_data_with(&limits, None, PDF).unwrap();
+        assert_eq!(part["inlineData"]["mimeType"], "application/pdf");
+
+        let part = inline_data_with(&limits, Some("image/jpg"), "AAAA").unwrap();
+        assert_eq!(part["inlineData"]["mimeType"], "image/jpeg");
+
+        assert!(matches!(
+            inline_data_with(&limits, Some("video/mp4"), "AAAA"),
+            Err(ConvertError::Unsupported(_))
+        ));
+        assert!(inline_data_with(&limits, None, "AAAA").is_err());
+        assert!(inline_data_with(&limits, Some("image/png"), "not base64!").is_err());
+    }
+
+    #[test]
+    fn test_size_limits() {
+        assert_eq!(decoded_len(PNG), 70);
+        assert_eq!(decoded_len("QUJD"), 3);
+        assert_eq!(decoded_len("QUI="), 2);
+
+        let limits = MediaSettings {
+            max_image_bytes: 64,
+            max_document_bytes: 1024,
+            max_total_bytes: 100,
+        };
+        let err = inline_data_with(&limits, None, PNG).unwrap_err();
+        assert!(matches!(err, ConvertError::TooLarge(_)));
+        assert!(err.to_string().contains("图片"), "{}", err);
+        assert!(inline_data_with(&limits, None, PDF).is_ok());
+
+        let request = json!({"contents": [
+            {"role": "user", "parts": [{"inlineData": {"mimeType": "image/png", "data": PNG}}]},
+            {"role": "user", "parts": [{"inlineData": {"mimeType": "image/png", "data": PNG}}]}
+        ]});
+        assert!(matches!(
+            check_total_with(&limits, &request),
+            Err(ConvertError::TooLarge(_))
+        ));
+    }
+}
@@ -0,0 +1,58 @@
+//! 客户端协议（Anthropic / OpenAI）与 Gemini generateContent 格式之间的转换
+
+pub mod anthropic;
+pub mod anthropic_stream;
+pub mod media;
+pub mod models;
+pub mod openai;
+pub mod safety;
+pub mod schema;
+pub mod signature;
+pub mod structured;
+pub mod thinking;
+
+use thiserror::Error;
+
+/// 请求转换错误（客户端请求不合法或无法映射到 Gemini）
+#[derive(Debug, Error)]
+pub enum ConvertError {
+    #[error("请求格式错误: {0}")]
+    InvalidRequest(String),
+
+    #[error("不支持的内容: {0}")]
+    Unsupported(String),
+
+    #[error("内容过大: {0}")]
+    TooLarge(String),
+
+    #[error("未知模型: {0}")]
+    UnknownModel(String),
+}
+
+/// gemini-claude-* 等对外模型名对应的上游模型 ID
+///
+/// 优先使用模型注册表；未注册的模型按命名规则推断。
+pub fn upstream_model(model: &str) -> &str {
+    if let Some(info) = models::find(model) {
+        return &info.upstream_model;
+    }
+    let model = model.strip_prefix("models/").unwrap_or(model);
+    match model.strip_prefix("gemini-") {
+        Some(rest) if rest.starts_with("claude-") => rest,
+        _ => model,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upstream_model() {
+        assert_eq!(
+            upstream_model("gemini-claude-sonnet-4-5-thinking"),
+            "claude-sonnet-4-5-thinking"
+        );
+        assert_eq!(upstream_model("models/gemini-2.5-pro"), "gemini-2.5-pro");
+    }
+}
@@ -0,0 +1,446 @@
+//! 模型注册表：对外模型名、上游模型 ID、能力与 token 上限
+//!
+//! plugin.json 的 supported_models 以通配符声明支持的模型；注册表之外但符合这些通配符的模型
+//! 按同系列的注册模型推断能力与上限。
+
+use super::ConvertError;
+use serde::Serialize;
+use std::borrow::Cow;
+use tracing::debug;
+
+/// 模型原生对应的客户端协议（plugin.json 的 protocol_rules 按它生成）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelProtocol {
+    Anthropic,
+    Gemini,
+}
+
+/// 模型能力
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    pub thinking: bool,
+    pub tools: bool,
+    /// 图片 / 文档等内联数据输入
+    pub vision: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModelInfo {
+    /// 对外模型名
+    pub id: Cow<'static, str>,
+    /// 上游 Code Assist 使用的模型 ID
+    pub upstream_model: Cow<'static, str>,
+    pub display_name: Cow<'static, str>,
+    pub protocol: ModelProtocol,
+    pub capabilities: Capabilities,
+    pub input_token_limit: u32,
+    pub output_token_limit: u32,
+}
+
+const GEMINI: Capabilities = Capabilities {
+    thinking: true,
+    tools: true,
+    vision: true,
+};
+
+const CLAUDE: Capabilities = Capabilities {
+    thinking: false,
+    tools: true,
+    vision: true,
+};
+
+const CLAUDE_THINKING: Capabilities = Capabilities {
+    thinking: true,
+    ..CLAUDE
+};
+
+/// 图片生成模型不支持函数调用
+const GEMINI_IMAGE: Capabilities = Capabilities {
+    tools: false,
+    ..GEMINI
+};
+
+/// plugin.json 的 supported_models
+pub const SUPPORTED_MODELS: &[&str] = &["gemini-3-pro-*", "gemini-2.5-*", "gemini-claude-*"];
+
+/// 支持的模型
+pub const MODELS: &[ModelInfo] = &[
+    ModelInfo {
+        id: Cow::Borrowed("gemini-3-pro-preview"),
+        upstream_model: Cow::Borrowed("gemini-3-pro-preview"),
+        display_name: Cow::Borrowed("Gemini 3 Pro Preview"),
+        protocol: ModelProtocol::Gemini,
+        capabilities: GEMINI,
+        input_token_limit: 1_048_576,
+        output_token_limit: 65_536,
+    },
+    ModelInfo {
+        id: Cow::Borrowed("gemini-3-pro-image-preview"),
+        upstream_model: Cow::Borrowed("gemini-3-pro-image-preview"),
+        display_name: Cow::Borrowed("Gemini 3 Pro Image Preview"),
+        protocol: ModelProtocol::Gemini,
+        capabilities: GEMINI_IMAGE,
+        input_token_limit: 65_536,
+        output_token_limit: 32_768,
+    },
+    ModelInfo {
+        id: Cow::Borrowed("gemini-2.5-pro"),
+        upstream_model: Cow::Borrowed("gemini-2.5-pro"),
+        display_name: Cow::Borrowed("Gemini 2.5 Pro"),
+        protocol: ModelProtocol::Gemini,
+        capabilities: GEMINI,
+        input_token_limit: 1_048_576,
+        output_token_limit: 65_536,
+    },
+    ModelInfo {
+        id: Cow::Borrowed("gemini-2.5-flash"),
+        upstream_model: Cow::Borrowed("gemini-2.5-flash"),
+        display_name: Cow::Borrowed("Gemini 2.5 Flash"),
+        protocol: ModelProtocol::Gemini,
+        capabilities: GEMINI,
+        input_token_limit: 1_048_576,
+        output_token_limit: 65_536,
+    },
+    ModelInfo {
+        id: Cow::Borrowed("gemini-2.5-flash-lite"),
+        upstream_model: Cow::Borrowed("gemini-2.5-flash-lite"),
+        display_name: Cow::Borrowed("Gemini 2.5 Flash Lite"),
+        protocol: ModelProtocol::Gemini,
+        capabilities: GEMINI,
+        input_token_limit: 1_048_576,
+        output_token_limit: 65_536,
+    },
+    ModelInfo {
+        id: Cow::Borrowed("gemini-claude-sonnet-4-5"),
+        upstream_model: Cow::Borrowed("claude-sonnet-4-5"),
+        display_name: Cow::Borrowed("Claude Sonnet 4.5"),
+        protocol: ModelProtocol::Anthropic,
+        capabilities: CLAUDE,
+        input_token_limit: 200_000,
+        output_token_limit: 64_000,
+    },
+    ModelInfo {
+        id: Cow::Borrowed("gemini-claude-sonnet-4-5-thinking"),
+        upstream_model: Cow::Borrowed("claude-sonnet-4-5-thinking"),
+        display_name: Cow::Borrowed("Claude Sonnet 4.5 (Thinking)"),
+        protocol: ModelProtocol::Anthropic,
+        capabilities: CLAUDE_THINKING,
+        input_token_limit: 200_000,
+        output_token_limit: 64_000,
+    },
+    ModelInfo {
+        id: Cow::Borrowed("gemini-claude-opus-4-5-thinking"),
+        upstream_model: Cow::Borrowed("claude-opus-4-5-thinking"),
+        display_name: Cow::Borrowed("Claude Opus 4.5 (Thinking)"),
+        protocol: ModelProtocol::Anthropic,
+        capabilities: CLAUDE_THINKING,
+        input_token_limit: 200_000,
+        output_token_limit: 64_000,
+    },
+];
+
+/// 按对外模型名或上游模型 ID 查找（忽略 `models/` 前缀）
+pub fn find(model: &str) -> Option<&'static ModelInfo> {
+    let model = model.strip_prefix("models/").unwrap_or(model);
+    MODELS
+        .iter()
+        .find(|m| m.id == model || m.upstream_model == model)
+}
+
+/// 模型名是否符合 `prefix-*` 形式的通配符
+fn matches_glob(glob: &str, model: &str) -> bool {
+    match glob.strip_suffix('*') {
+        Some(prefix) => model.starts_with(prefix),
+        None => glob == model,
+    }
+}
+
+/// 未注册模型所属系列的注册模型（按对外模型名或上游模型 ID）
+fn family(model: &str) -> Option<&'static ModelInfo> {
+    let model = match model.strip_prefix("claude-") {
+        Some(_) => Cow::Owned(format!("gemini-{}", model)),
+        None => Cow::Borrowed(model),
+    };
+    if !SUPPORTED_MODELS.iter().any(|g| matches_glob(g, &model)) {
+        return None;
+    }
+    let base = if model.starts_with("gemini-claude-") {
+        if model.ends_with("-thinking") {
+            "gemini-claude-sonnet-4-5-thinking"
+        } else {
+            "gemini-claude-sonnet-4-5"
+        }
+    } else if model.starts_with("gemini-3-pro-") {
+        "gemini-3-pro-preview"
+    } else if model.starts_with("gemini-2.5-flash-lite") {
+        "gemini-2.5-flash-lite"
+    } else if model.starts_with("gemini-2.5-flash") {
+        "gemini-2.5-flash"
+    } else {
+        "gemini-2.5-pro"
+    };
+    find(base)
+}
+
+/// 查找模型；未注册但符合 supported_models 的模型按同系列推断，其余返回 UnknownModel
+pub fn resolve(model: &str) -> Result<Cow<'static, ModelInfo>, ConvertError> {
+    if let Some(info) = find(model) {
+        return Ok(Cow::Borrowed(info));
+    }
+    let name = model.strip_prefix("models/").unwrap_or(model);
+    let family = family(name).ok_or_else(|| ConvertError::UnknownModel(model.to_string()))?;
+    debug!("模型 {} 未注册，按 {} 推断能力与上限", name, family.id);
+    Ok(Cow::Owned(ModelInfo {
+        id: Cow::Owned(name.to_string()),
+        upstream_model: Cow::Owned(super::upstream_model(name).to_string()),
+        display_name: Cow::Owned(name.to_string()),
+        ..family.clone()
+    }))
+}
+
+/// 模型原生对应的客户端协议，未知模型返回 None
+pub fn protocol(model: &str) -> Option<ModelProtocol> {
+    resolve(model).ok().map(|info| info.protocol)
+}
+
+/// 粗略估算请求的输入 token 数：文本按约 4 字节一个 token 计算，内联数据不计入（另有字节上限）
+pub fn estimate_input_tokens(request: &serde_json::Value) -> u64 {
+    fn bytes(value: &serde_json::Value) -> u64 {
+        match value {
+            serde_json::Value::String(s) => s.len() as u64,
+            serde_json::Value::Array(items) => items.iter().map(bytes).sum(),
+            serde_json::Value::Object(map) => map
+                .iter()
+                .filter(|(k, _)| !matches!(k.as_str(), "inlineData" | "fileData"))
+                .map(|(_, v)| bytes(v))
+                .sum(),
+            _ => 0,
+        }
+    }
+    ["contents", "systemInstruction", "tools"]
+        .iter()
+        .map(|field| bytes(&request[field]))
+        .sum::<u64>()
+        .div_ceil(4)
+}
+
+impl ModelInfo {
+    /// 超过模型上限的 max_tokens 截断到上限
+    pub fn clamp_output(&self, max_tokens: u32) -> u32 {
+        if max_tokens > self.output_token_limit {
+            debug!(
+                "max_tokens {} 超过模型 {} 的上限，截断为 {}",
+                max_tokens, self.id, self.output_token_limit
+            );
+            self.output_token_limit
+        } else {
+            max_tokens
+        }
+    }
+
+    /// 截断 Gemini 请求中的 generationConfig.maxOutputTokens
+    pub fn clamp_request(&self, request: &mut serde_json::Value) {
+        let Some(max_tokens) = request
+            .pointer_mut("/generationConfig/maxOutputTokens")
+            .filter(|v| v.is_u64())
+        else {
+            return;
+        };
+        let value = u32::try_from(max_tokens.as_u64().unwrap_or_default()).unwrap_or(u32::MAX);
+        *max_tokens = self.clamp_output(value).into();
+    }
+
+    /// 估算的输入 token 数超过模型上限时返回 TooLarge
+    pub fn check_input(&self, request: &serde_json::Value) -> Result<(), ConvertError> {
+        let tokens = estimate_input_tokens(request);
+        if tokens > u64::from(self.input_token_limit) {
+            return Err(ConvertError::TooLarge(format!(
+                "输入约 {} tokens，超过模型 {} 的上限 {}",
+                tokens, self.id, self.input_token_limit
+            )));
+        }
+        Ok(())
+    }
+
+    /// 检查转换后的 Gemini 请求是否用到了模型不支持的能力
+    pub fn validate(&self, request: &serde_json::Value) -> Result<(), ConvertError> {
+        let has_tools = request["tools"]
+            .as_array()
+            .is_some_and(|tools| !tools.is_empty());
+        if has_tools && !self.capabilities.tools {
+            return Err(ConvertError::Unsupported(format!(
+                "模型 {} 不支持工具调用",
+                self.id
+            )));
+        }
+
+        let has_media = request["contents"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|content| content["parts"].as_array().into_iter().flatten())
+            .any(|part| part.get("inlineData").is_some() || part.get("fileData").is_some());
+        if has_media && !self.capabilities.vision {
+            return Err(ConvertError::Unsupported(format!(
+                "模型 {} 不支持图片或文档输入",
+                self.id
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_find_by_alias_and_upstream_id() {
+        let model = find("gemini-claude-sonnet-4-5-thinking").unwrap();
+        assert_eq!(model.upstream_model, "claude-sonnet-4-5-thinking");
+        assert_eq!(model.protocol, ModelProtocol::Anthropic);
+        assert_eq!(find("claude-sonnet-4-5-thinking"), Some(model));
+        assert_eq!(find("models/gemini-2.5-pro").unwrap().id, "gemini-2.5-pro");
+        assert!(matches!(
+            resolve("gpt-4o"),
+            Err(ConvertError::UnknownModel(_))
+        ));
+
+        for model in MODELS {
+            assert_eq!(find(&model.id), Some(model), "{}", model.id);
+        }
+    }
+
+    #[test]
+    fn test_clamp_and_validate() {
+        let model = find("gemini-claude-sonnet-4-5").unwrap();
+        assert_eq!(model.clamp_output(100_000), 64_000);
+        assert_eq!(model.clamp_output(1024), 1024);
+        let mut request = json!({"generationConfig": {"maxOutputTokens": 128_000}});
+        model.clamp_request(&mut request);
+        assert_eq!(request["generationConfig"]["maxOutputTokens"], 64_000);
+
+        let without_tools = ModelInfo {
+            capabilities: Capabilities {
+                tools: false,
+                vision: false,
+                ..model.capabilities
+            },
+            ..model.clone()
+        };
+        let request = json!({
+            "contents": [{"role": "user", "parts": [
+                {"inlineData": {"mimeType": "image/png", "data": "AAAA"}}
+            ]}],
+            "tools": [{"functionDeclarations": []}]
+        });
+        assert!(model.validate(&request).is_ok());
+        let err = without_tools.validate(&request).unwrap_err();
+        assert!(err.to_string().contains("工具调用"), "{}", err);
+        let err = without_tools
+            .validate(&json!({"contents": request["contents"]}))
+            .unwrap_err();
+        assert!(err.to_string().contains("图片"), "{}", err);
+    }
+
+    #[test]
+    fn test_resolve_supported_globs() {
+        let model = resolve("gemini-2.5-flash-preview-09-2025").unwrap();
+        assert_eq!(model.upstream_model, "gemini-2.5-flash-preview-09-2025");
+        assert_eq!(model.output_token_limit, 65_536);
+        assert_eq!(
+            resolve("gemini-3-pro-high").unwrap().protocol,
+            ModelProtocol::Gemini
+        );
+
+        let model = resolve("gemini-claude-opus-4-1-thinking").unwrap();
+        assert_eq!(model.upstream_model, "claude-opus-4-1-thinking");
+        assert_eq!(model.protocol, ModelProtocol::Anthropic);
+        assert!(model.capabilities.thinking);
+        assert!(!resolve("claude-opus-4-1").unwrap().capabilities.thinking);
+
+        // 已注册的模型不复制
+        assert!(matches!(
+            resolve("gemini-2.5-pro").unwrap(),
+            Cow::Borrowed(_)
+        ));
+        for model in ["gpt-4o", "gemini-1.5-pro", "gemini-3-flash"] {
+            assert!(
+                matches!(resolve(model), Err(ConvertError::UnknownModel(_))),
+                "{}",
+                model
+            );
+        }
+    }
+
+    /// 按最长匹配的通配符取 protocol_rules 中的协议
+    fn rule_protocol(rules: &serde_json::Map<String, serde_json::Value>, model: &str) -> String {
+        rules
+            .iter()
+            .filter(|(glob, _)| matches_glob(glob, model))
+            .max_by_key(|(glob, _)| glob.len())
+            .and_then(|(_, protocol)| protocol.as_str())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    #[test]
+    fn test_registry_agrees_with_plugin_json() {
+        let plugin: serde_json::Value =
+            serde_json::from_str(include_str!("../../../plugin/plugin.json")).unwrap();
+        let provider = &plugin["provider"];
+        assert_eq!(provider["supported_models"], json!(SUPPORTED_MODELS));
+
+        let rules = provider["protocol_rules"].as_object().unwrap();
+        let examples = ["gemini-2.5-flash-image", "gemini-claude-opus-4-1-thinking"];
+        for model in MODELS.iter().map(|m| m.id.as_ref()).chain(examples) {
+            assert!(
+                SUPPORTED_MODELS.iter().any(|g| matches_glob(g, model)),
+                "{}",
+                model
+            );
+            let expected = json!(protocol(model).unwrap());
+            assert_eq!(rule_protocol(rules, model), expected, "{}", model);
+        }
+    }
+
+    #[test]
+    fn test_input_token_limit() {
+        let model = find("gemini-3-pro-image-preview").unwrap();
+        // role 字段的 "user" 也计入估算
+        let text = "a".repeat(65_536 * 4 - 4);
+        let request = json!({"contents": [{"role": "user", "parts": [{"text": text}]}]});
+        assert_eq!(estimate_input_tokens(&request), 65_536);
+        assert!(model.check_input(&request).is_ok());
+
+        let request = json!({
+            "systemInstruction": {"parts": [{"text": "more"}]},
+            "contents": request["contents"]
+        });
+        let err = model.check_input(&request).unwrap_err();
+        assert!(matches!(err, ConvertError::TooLarge(_)), "{}", err);
+
+        // 内联数据不计入
+        let request = json!({"contents": [{"parts": [
+            {"inlineData": {"mimeType": "image/png", "data": "A".repeat(1_000_000)}}
+        ]}]});
+        assert_eq!(estimate_input_tokens(&request), 0);
+    }
+
+    #[test]
+    fn test_image_model_rejects_tools() {
+        let model = find("gemini-3-pro-image-preview").unwrap();
+        assert!(!model.capabilities.tools);
+        let request = json!({
+            "contents": [{"role": "user", "parts": [{"text": "draw"}]}],
+            "tools": [{"functionDeclarations": [{"name": "f"}]}]
+        });
+        assert!(matches!(
+            model.validate(&request),
+            Err(ConvertError::Unsupported(_))
+        ));
+        assert!(find("gemini-2.5-pro").unwrap().validate(&request).is_ok());
+    }
+}
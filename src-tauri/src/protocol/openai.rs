@@ -0,0 +1,911 @@
+//! OpenAI Chat Completions 与 Gemini generateContent 之间的转换
+
+use super::media;
+use super::models;
+use super::safety::{self, SafetyBlock};
+use super::schema::{self, ToolChoice};
+use super::structured::{ResponseFormat, StructuredOutputError};
+use super::thinking::{self, ThinkingRequest};
+use super::ConvertError;
+use crate::config;
+use crate::fallback::ModelFallback;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+
+/// Chat Completions 请求（未列出的字段会被忽略）
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default)]
+    pub tools: Vec<ChatTool>,
+    #[serde(default)]
+    pub tool_choice: Option<serde_json::Value>,
+    #[serde(default)]
+    pub response_format: Option<serde_json::Value>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub stream_options: Option<StreamOptions>,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub max_completion_tokens: Option<u32>,
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    #[serde(default)]
+    pub top_p: Option<f64>,
+    #[serde(default)]
+    pub stop: Option<Stop>,
+    #[serde(default)]
+    pub reasoning_effort: Option<String>,
+    /// 扩展字段：为 false 时不回退到其他模型
+    #[serde(default)]
+    pub model_fallback: Option<bool>,
+    /// 扩展字段：按请求覆盖安全设置
+    #[serde(default)]
+    pub safety_settings: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StreamOptions {
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
+/// stop 可以是字符串或字符串数组
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Stop {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Stop {
+    fn sequences(&self) -> Vec<String> {
+        match self {
+            Stop::One(s) => vec![s.clone()],
+            Stop::Many(v) => v.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: Option<ChatContent>,
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(default)]
+    pub tool_call_id: Option<String>,
+}
+
+/// 消息内容可以是字符串或内容片段数组
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ChatContent {
+    Text(String),
+    Parts(Vec<ChatPart>),
+}
+
+impl ChatContent {
+    fn text(&self) -> String {
+        match self {
+            ChatContent::Text(t) => t.clone(),
+            ChatContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|p| match p {
+                    ChatPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatPart {
+    Text {
+        text: String,
+    },
+    ImageUrl {
+        image_url: ImageUrl,
+    },
+    File {
+        file: FileData,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+/// 文件片段（file_data 为 data URL；file_id 引用的上传文件无法转发）
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileData {
+    #[serde(default)]
+    pub file_data: Option<String>,
+    #[serde(default)]
+    pub file_id: Option<String>,
+    #[serde(default)]
+    pub filename: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "function_type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+/// 函数调用（arguments 为 JSON 字符串）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatTool {
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub parameters: Option<serde_json::Value>,
+}
+
+/// Chat Completions 响应
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatCompletion {
+    pub id: String,
+    pub object: &'static str,
+    pub created: i64,
+    pub model: String,
+    pub choices: Vec<Choice>,
+    pub usage: Usage,
+    /// 扩展字段：因安全原因被拦截时的类别
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub safety_block: Option<SafetyBlock>,
+    /// 扩展字段：请求的模型配额耗尽、改用其他模型时的回退信息
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_fallback: Option<ModelFallback>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Choice {
+    pub index: u32,
+    pub message: ResponseMessage,
+    pub finish_reason: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResponseMessage {
+    pub role: &'static str,
+    pub content: Option<String>,
+    /// 思考内容（Gemini thought part）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CompletionTokensDetails {
+    pub reasoning_tokens: u64,
+}
+
+/// data URL（data:<mime>;base64,<data>）转换为 inlineData
+fn image_part(url: &str) -> Result<serde_json::Value, ConvertError> {
+    let (mime_type, data) = media::parse_data_url(url)
+        .map_err(|_| ConvertError::Unsupported("image_url 只支持 base64 data URL".to_string()))?;
+    media::inline_data(Some(mime_type), data)
+}
+
+fn file_part(file: &FileData) -> Result<serde_json::Value, ConvertError> {
+    let Some(url) = &file.file_data else {
+        return Err(ConvertError::Unsupported(format!(
+            "file_id 引用的文件 ({})，请改用 file_data",
+            file.file_id.as_deref().unwrap_or_default()
+        )));
+    };
+    let (mime_type, data) = media::parse_data_url(url).map_err(|_| {
+        ConvertError::Unsupported(format!(
+            "文件 {} 只支持 base64 data URL",
+            file.filename.as_deref().unwrap_or_default()
+        ))
+    })?;
+    media::inline_data(Some(mime_type), data)
+}
+
+fn user_parts(content: &ChatContent) -> Result<Vec<serde_json::Value>, ConvertError> {
+    match content {
+        ChatContent::Text(text) if text.is_empty() => Ok(Vec::new()),
+        ChatContent::Text(text) => Ok(vec![json!({"text": text})]),
+        ChatContent::Parts(parts) => {
+            let mut out = Vec::new();
+            for part in parts {
+                match part {
+                    ChatPart::Text { text } if text.is_empty() => {}
+                    ChatPart::Text { text } => out.push(json!({"text": text})),
+                    ChatPart::ImageUrl { image_url } => out.push(image_part(&image_url.url)?),
+                    ChatPart::File { file } => out.push(file_part(file)?),
+                    ChatPart::Unknown => {
+                        return Err(ConvertError::Unsupported("未知的内容片段类型".to_string()))
+                    }
+                }
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// 将 Chat Completions 请求转换为 Gemini generateContent 请求
+pub fn to_gemini(request: &ChatCompletionRequest) -> Result<serde_json::Value, ConvertError> {
+    if request.messages.is_empty() {
+        return Err(ConvertError::InvalidRequest(
+            "messages 不能为空".to_string(),
+        ));
+    }
+    let model = models::resolve(&request.model)?;
+
+    let mut system = Vec::new();
+    // tool 消息只带 tool_call_id，functionResponse 需要函数名
+    let mut tool_names: HashMap<&str, &str> = HashMap::new();
+    let mut contents: Vec<serde_json::Value> = Vec::new();
+
+    for message in &request.messages {
+        let (role, parts) = match message.role.as_str() {
+            "system" | "developer" => {
+                if let Some(content) = &message.content {
+                    system.push(content.text());
+                }
+                continue;
+            }
+            "user" => (
+                "user",
+                match &message.content {
+                    Some(content) => user_parts(content)?,
+                    None => Vec::new(),
+                },
+            ),
+            "assistant" => {
+                let mut parts = Vec::new();
+                if let Some(content) = &message.content {
+                    let text = content.text();
+                    if !text.is_empty() {
+                        parts.push(json!({"text": text}));
+                    }
+                }
+                for call in &message.tool_calls {
+                    tool_names.insert(&call.id, &call.function.name);
+                    let args: serde_json::Value = if call.function.arguments.trim().is_empty() {
+                        json!({})
+                    } else {
+                        serde_json::from_str(&call.function.arguments).map_err(|e| {
+                            ConvertError::InvalidRequest(format!(
+                                "tool_calls[{}].function.arguments 不是有效的 JSON: {}",
+                                call.id, e
+                            ))
+                        })?
+                    };
+                    parts.push(json!({
+                        "functionCall": {"id": call.id, "name": call.function.name, "args": args}
+                    }));
+                }
+                ("model", parts)
+            }
+            "tool" => {
+                let call_id = message.tool_call_id.as_deref().ok_or_else(|| {
+                    ConvertError::InvalidRequest("tool 消息缺少 tool_call_id".to_string())
+                })?;
+                let name = tool_names.get(call_id).ok_or_else(|| {
+                    ConvertError::InvalidRequest(format!(
+                        "tool 消息引用了未知的 tool_call_id: {}",
+                        call_id
+                    ))
+                })?;
+                let output = message
+                    .content
+                    .as_ref()
+                    .map(|c| c.text())
+                    .unwrap_or_default();
+                (
+                    "user",
+                    vec![json!({
+                        "functionResponse": {
+                            "id": call_id,
+                            "name": name,
+                            "response": {"output": output}
+                        }
+                    })],
+                )
+            }
+            other => {
+                return Err(ConvertError::InvalidRequest(format!(
+                    "未知的消息角色: {}",
+                    other
+                )))
+            }
+        };
+        if parts.is_empty() {
+            continue;
+        }
+
+        // Gemini 要求相邻消息角色交替，连续的同角色消息合并
+        match contents.last_mut() {
+            Some(last) if last["role"] == role => {
+                last["parts"].as_array_mut().unwrap().extend(parts);
+            }
+            _ => contents.push(json!({"role": role, "parts": parts})),
+        }
+    }
+
+    let mut gemini = json!({"contents": contents});
+
+    let system = system.join("\n\n");
+    if !system.is_empty() {
+        gemini["systemInstruction"] = json!({"role": "user", "parts": [{"text": system}]});
+    }
+
+    let mut generation_config = serde_json::Map::new();
+    if let Some(v) = request.max_completion_tokens.or(request.max_tokens) {
+        generation_config.insert("maxOutputTokens".to_string(), json!(model.clamp_output(v)));
+    }
+    if let Some(v) = request.temperature {
+        generation_config.insert("temperature".to_string(), json!(v));
+    }
+    if let Some(v) = request.top_p {
+        generation_config.insert("topP".to_string(), json!(v));
+    }
+    if let Some(stop) = &request.stop {
+        generation_config.insert("stopSequences".to_string(), json!(stop.sequences()));
+    }
+    ResponseFormat::from_openai(request.response_format.as_ref())?.apply(&mut generation_config);
+    if !generation_config.is_empty() {
+        gemini["generationConfig"] = serde_json::Value::Object(generation_config);
+    }
+    thinking::apply(
+        &mut gemini,
+        &model.upstream_model,
+        ThinkingRequest::from_effort(request.reasoning_effort.as_deref())?,
+        &config::current().settings.reasoning,
+        true,
+    )?;
+
+    if !request.tools.is_empty() {
+        let declarations: Vec<serde_json::Value> = request
+            .tools
+            .iter()
+            .map(|tool| {
+                let function = &tool.function;
+                schema::function_declaration(
+                    &function.name,
+                    function.description.as_deref(),
+                    function.parameters.as_ref(),
+                )
+            })
+            .collect();
+        gemini["tools"] = json!([{"functionDeclarations": declarations}]);
+    }
+    if let Some(choice) = &request.tool_choice {
+        let choice = ToolChoice::from_openai(choice)?;
+        let names: Vec<&str> = request
+            .tools
+            .iter()
+            .map(|t| t.function.name.as_str())
+            .collect();
+        schema::check_tool_choice(&choice, &names)?;
+        gemini["toolConfig"] = choice.to_gemini();
+    }
+    if let Some(overrides) = &request.safety_settings {
+        gemini["safetySettings"] = json!(safety::parse_overrides(overrides)?);
+    }
+    model.validate(&gemini)?;
+    media::check_total(&gemini)?;
+
+    Ok(gemini)
+}
+
+/// 按 response_format 校验生成的文本（settings.structured_output.strict 决定是否报错）
+pub fn check_structured_output(
+    request: &ChatCompletionRequest,
+    completion: &ChatCompletion,
+) -> Result<(), StructuredOutputError> {
+    // response_format 在 to_gemini 时已校验过
+    let Ok(format) = ResponseFormat::from_openai(request.response_format.as_ref()) else {
+        return Ok(());
+    };
+    let text = completion
+        .choices
+        .first()
+        .and_then(|c| c.message.content.as_deref());
+    format.check(text, config::current().settings.structured_output.strict)
+}
+
+/// Gemini finishReason 对应的 finish_reason
+pub fn finish_reason(reason: Option<&str>, has_tool_calls: bool) -> Option<&'static str> {
+    let reason = reason?;
+    Some(match reason {
+        _ if has_tool_calls => "tool_calls",
+        "MAX_TOKENS" => "length",
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" | "IMAGE_SAFETY" => {
+            "content_filter"
+        }
+        _ => "stop",
+    })
+}
+
+/// 从 Gemini usageMetadata 计算用量（思考 token 计入 completion）
+pub fn usage(usage_metadata: &serde_json::Value) -> Usage {
+    let count = |key: &str| usage_metadata[key].as_u64().unwrap_or(0);
+    let prompt_tokens = count("promptTokenCount");
+    let reasoning_tokens = count("thoughtsTokenCount");
+    let completion_tokens = count("candidatesTokenCount") + reasoning_tokens;
+    Usage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+        completion_tokens_details: (reasoning_tokens > 0)
+            .then_some(CompletionTokensDetails { reasoning_tokens }),
+    }
+}
+
+fn completion_id(response: &serde_json::Value) -> String {
+    match response["responseId"].as_str() {
+        Some(id) => format!("chatcmpl-{}", id),
+        None => format!("chatcmpl-{}", uuid::Uuid::new_v4().simple()),
+    }
+}
+
+/// ordinal 为该调用在响应中的序号，用于生成稳定的调用 ID
+fn function_call(call: &serde_json::Value, response_id: Option<&str>, ordinal: usize) -> ToolCall {
+    ToolCall {
+        id: schema::stable_call_id("call_", response_id, ordinal, call),
+        kind: function_type(),
+        function: FunctionCall {
+            name: call["name"].as_str().unwrap_or_default().to_string(),
+            arguments: call
+                .get("args")
+                .cloned()
+                .unwrap_or_else(|| json!({}))
+                .to_string(),
+        },
+    }
+}
+
+/// 将 Gemini 响应转换为 Chat Completions 响应
+pub fn from_gemini(response: &serde_json::Value, model: &str) -> ChatCompletion {
+    let candidate = &response["candidates"][0];
+    let mut message = ResponseMessage {
+        role: "assistant",
+        ..Default::default()
+    };
+    let mut text = String::new();
+    let mut reasoning = String::new();
+    for part in candidate["content"]["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        if let Some(call) = part.get("functionCall") {
+            let ordinal = message.tool_calls.len();
+            message.tool_calls.push(function_call(
+                call,
+                response["responseId"].as_str(),
+                ordinal,
+            ));
+        } else if let Some(t) = part["text"].as_str() {
+            if part["thought"].as_bool() == Some(true) {
+                reasoning.push_str(t);
+            } else {
+                text.push_str(t);
+            }
+        }
+    }
+    if !text.is_empty() {
+        message.content = Some(text);
+    }
+    if !reasoning.is_empty() {
+        message.reasoning_content = Some(reasoning);
+    }
+
+    let finish = if response["promptFeedback"]["blockReason"].is_string() {
+        Some("content_filter")
+    } else {
+        finish_reason(
+            candidate["finishReason"].as_str(),
+            !message.tool_calls.is_empty(),
+        )
+    };
+
+    let model_fallback = ModelFallback::from_response(response);
+    ChatCompletion {
+        id: completion_id(response),
+        object: "chat.completion",
+        created: chrono::Utc::now().timestamp(),
+        model: model_fallback
+            .as_ref()
+            .map_or(model, |f| f.model.as_str())
+            .to_string(),
+        choices: vec![Choice {
+            index: 0,
+            message,
+            finish_reason: finish,
+        }],
+        usage: usage(&response["usageMetadata"]),
+        safety_block: safety::blocked(response),
+        model_fallback,
+    }
+}
+
+/// 流式转换器：逐个输入 Gemini 分块，输出 chat.completion.chunk，同时累积完整响应
+pub struct ChunkConverter {
+    completion: Option<ChatCompletion>,
+    model: String,
+    include_usage: bool,
+    finish_reason: Option<String>,
+    usage_metadata: serde_json::Value,
+    blocked: bool,
+    finished: bool,
+}
+
+impl ChunkConverter {
+    pub fn new(model: &str, include_usage: bool) -> Self {
+        Self {
+            completion: None,
+            model: model.to_string(),
+            include_usage,
+            finish_reason: None,
+            usage_metadata: json!({}),
+            blocked: false,
+            finished: false,
+        }
+    }
+
+    fn chunk(&self, delta: serde_json::Value, finish_reason: Option<&str>) -> serde_json::Value {
+        let completion = self.completion.as_ref().unwrap();
+        json!({
+            "id": completion.id,
+            "object": "chat.completion.chunk",
+            "created": completion.created,
+            "model": completion.model,
+            "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+        })
+    }
+
+    fn start(&mut self, chunk: &serde_json::Value, out: &mut Vec<serde_json::Value>) {
+        if self.completion.is_some() {
+            return;
+        }
+        let model_fallback = ModelFallback::from_response(chunk);
+        self.completion = Some(ChatCompletion {
+            id: completion_id(chunk),
+            object: "chat.completion",
+            created: chrono::Utc::now().timestamp(),
+            model: model_fallback
+                .as_ref()
+                .map_or(&self.model, |f| &f.model)
+                .clone(),
+            choices: vec![Choice {
+                index: 0,
+                message: ResponseMessage {
+                    role: "assistant",
+                    ..Default::default()
+                },
+                finish_reason: None,
+            }],
+            usage: Usage::default(),
+            safety_block: None,
+            model_fallback,
+        });
+        let mut first = self.chunk(json!({"role": "assistant", "content": ""}), None);
+        if let Some(fallback) = &self.completion.as_ref().unwrap().model_fallback {
+            first["model_fallback"] = json!(fallback);
+        }
+        out.push(first);
+    }
+
+    fn message(&mut self) -> &mut ResponseMessage {
+        &mut self.completion.as_mut().unwrap().choices[0].message
+    }
+
+    /// 输入一个 Gemini 分块（已解包 response），返回产生的 chunk
+    pub fn push(&mut self, chunk: &serde_json::Value) -> Vec<serde_json::Value> {
+        let mut out = Vec::new();
+        self.start(chunk, &mut out);
+
+        let candidate = &chunk["candidates"][0];
+        for part in candidate["content"]["parts"]
+            .as_array()
+            .into_iter()
+            .flatten()
+        {
+            if let Some(call) = part.get("functionCall") {
+                let index = self.message().tool_calls.len();
+                let call = function_call(call, chunk["responseId"].as_str(), index);
+                out.push(self.chunk(
+                    json!({"tool_calls": [{
+                        "index": index,
+                        "id": call.id,
+                        "type": "function",
+                        "function": {"name": call.function.name, "arguments": call.function.arguments}
+                    }]}),
+                    None,
+                ));
+                self.message().tool_calls.push(call);
+            } else if let Some(text) = part["text"].as_str() {
+                if text.is_empty() {
+                    continue;
+                }
+                if part["thought"].as_bool() == Some(true) {
+                    self.message()
+                        .reasoning_content
+                        .get_or_insert_with(String::new)
+                        .push_str(text);
+                    out.push(self.chunk(json!({"reasoning_content": text}), None));
+                    continue;
+                }
+                self.message()
+                    .content
+                    .get_or_insert_with(String::new)
+                    .push_str(text);
+                out.push(self.chunk(json!({"content": text}), None));
+            }
+        }
+
+        if let Some(reason) = candidate["finishReason"].as_str() {
+            self.finish_reason = Some(reason.to_string());
+        }
+        if chunk["promptFeedback"]["blockReason"].is_string() {
+            self.blocked = true;
+        }
+        if let Some(block) = safety::blocked(chunk) {
+            self.completion.as_mut().unwrap().safety_block = Some(block);
+        }
+        if let Some(usage) = chunk["usageMetadata"].as_object() {
+            for (k, v) in usage {
+                self.usage_metadata[k] = v.clone();
+            }
+        }
+        out
+    }
+
+    /// 结束流：发送带 finish_reason 的 chunk，按需附加用量 chunk
+    pub fn finish(&mut self) -> Vec<serde_json::Value> {
+        let mut out = Vec::new();
+        if self.finished {
+            return out;
+        }
+        self.finished = true;
+        self.start(&json!({}), &mut out);
+
+        let has_tool_calls = !self.message().tool_calls.is_empty();
+        let finish = if self.blocked {
+            Some("content_filter")
+        } else {
+            finish_reason(self.finish_reason.as_deref(), has_tool_calls).or(Some("stop"))
+        };
+        let usage = usage(&self.usage_metadata);
+        {
+            let completion = self.completion.as_mut().unwrap();
+            completion.choices[0].finish_reason = finish;
+            completion.usage = usage.clone();
+        }
+
+        let mut last = self.chunk(json!({}), finish);
+        if let Some(block) = &self.completion.as_ref().unwrap().safety_block {
+            last["safety_block"] = json!(block);
+        }
+        out.push(last);
+        if self.include_usage {
+            let mut chunk = self.chunk(json!({}), None);
+            chunk["choices"] = json!([]);
+            chunk["usage"] = json!(usage);
+            out.push(chunk);
+        }
+        out
+    }
+
+    /// 流结束后累积的完整响应
+    pub fn completion(&self) -> Option<&ChatCompletion> {
+        self.completion.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: serde_json::Value) -> ChatCompletionRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_to_gemini_roles_tools_and_format() {
+        let request = parse(json!({
+            "model": "gemini-2.5-pro",
+            "max_tokens": 256,
+            "stop": "END",
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "Describe"},
+                    {"type": "image_url", "image_url": {"url": "data:image/jpeg;base64,/9j/"}}
+                ]},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "lookup", "arguments": "{\"q\":\"x\"}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "found"}
+            ],
+            "tools": [{"type": "function", "function": {"name": "lookup", "parameters": {"type": "object"}}}],
+            "tool_choice": {"type": "function", "function": {"name": "lookup"}},
+            "response_format": {"type": "json_object"}
+        }));
+
+        let gemini = to_gemini(&request).unwrap();
+        assert_eq!(gemini["systemInstruction"]["parts"][0]["text"], "Be brief.");
+        let contents = gemini["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 3);
+        assert_eq!(
+            contents[0]["parts"][1]["inlineData"]["mimeType"],
+            "image/jpeg"
+        );
+        assert_eq!(
+            contents[1]["parts"][0]["functionCall"]["args"],
+            json!({"q": "x"})
+        );
+        assert_eq!(
+            contents[2]["parts"][0]["functionResponse"],
+            json!({"id": "call_1", "name": "lookup", "response": {"output": "found"}})
+        );
+        assert_eq!(gemini["generationConfig"]["maxOutputTokens"], 256);
+        assert_eq!(
+            gemini["generationConfig"]["thinkingConfig"],
+            json!({"thinkingBudget": 8192, "includeThoughts": true})
+        );
+        assert_eq!(gemini["generationConfig"]["stopSequences"], json!(["END"]));
+        assert_eq!(
+            gemini["generationConfig"]["responseMimeType"],
+            "application/json"
+        );
+        assert_eq!(
+            gemini["toolConfig"]["functionCallingConfig"],
+            json!({"mode": "ANY", "allowedFunctionNames": ["lookup"]})
+        );
+    }
+
+    #[test]
+    fn test_to_gemini_rejects_bad_arguments() {
+        let request = parse(json!({
+            "model": "gemini-2.5-pro",
+            "messages": [{"role": "assistant", "tool_calls": [
+                {"id": "c", "type": "function", "function": {"name": "f", "arguments": "{"}}
+            ]}]
+        }));
+        assert!(matches!(
+            to_gemini(&request),
+            Err(ConvertError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_gemini_tool_calls_and_usage() {
+        let completion = from_gemini(
+            &json!({
+                "candidates": [{"content": {"parts": [
+                    {"text": "hidden", "thought": true},
+                    {"functionCall": {"id": "call_9", "name": "lookup", "args": {"q": "x"}}}
+                ]}, "finishReason": "STOP"}],
+                "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 2}
+            }),
+            "gemini-2.5-pro",
+        );
+        let choice = &completion.choices[0];
+        assert_eq!(choice.finish_reason, Some("tool_calls"));
+        assert_eq!(choice.message.content, None);
+        assert_eq!(choice.message.reasoning_content.as_deref(), Some("hidden"));
+        assert_eq!(choice.message.tool_calls[0].id, "call_9");
+        assert_eq!(
+            choice.message.tool_calls[0].function.arguments,
+            "{\"q\":\"x\"}"
+        );
+        assert_eq!(
+            completion.usage,
+            Usage {
+                prompt_tokens: 3,
+                completion_tokens: 2,
+                total_tokens: 5,
+                completion_tokens_details: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_chunk_converter() {
+        let mut converter = ChunkConverter::new("gemini-2.5-flash", true);
+        let mut chunks = converter.push(&json!({
+            "responseId": "r",
+            "candidates": [{"content": {"parts": [{"text": "Hi"}]}}]
+        }));
+        chunks.extend(converter.push(&json!({
+            "candidates": [{"content": {"parts": [{"text": " there"}]}, "finishReason": "MAX_TOKENS"}],
+            "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 2}
+        })));
+        chunks.extend(converter.finish());
+
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "Hi");
+        assert!(chunks.iter().all(|c| c["id"] == "chatcmpl-r"));
+        assert_eq!(chunks[3]["choices"][0]["finish_reason"], "length");
+        assert_eq!(chunks[4]["choices"], json!([]));
+        assert_eq!(chunks[4]["usage"]["total_tokens"], 6);
+
+        let completion = converter.completion().unwrap();
+        assert_eq!(
+            completion.choices[0].message.content.as_deref(),
+            Some("Hi there")
+        );
+    }
+
+    #[test]
+    fn test_chunk_converter_reasoning_content() {
+        let mut converter = ChunkConverter::new("gemini-2.5-pro", false);
+        let mut chunks = converter.push(&json!({
+            "responseId": "r",
+            "candidates": [{"content": {"parts": [
+                {"text": "Let me think", "thought": true},
+                {"text": "", "thought": true}
+            ]}}]
+        }));
+        chunks.extend(converter.push(&json!({
+            "candidates": [{"content": {"parts": [
+                {"text": " harder", "thought": true},
+                {"text": "Answer"}
+            ]}, "finishReason": "STOP"}]
+        })));
+        chunks.extend(converter.finish());
+
+        let deltas: Vec<_> = chunks.iter().map(|c| &c["choices"][0]["delta"]).collect();
+        assert_eq!(deltas.len(), 5);
+        assert_eq!(*deltas[1], json!({"reasoning_content": "Let me think"}));
+        assert_eq!(*deltas[2], json!({"reasoning_content": " harder"}));
+        // 思考内容不混入 content
+        assert_eq!(*deltas[3], json!({"content": "Answer"}));
+        assert_eq!(chunks[4]["choices"][0]["finish_reason"], "stop");
+
+        let message = &converter.completion().unwrap().choices[0].message;
+        assert_eq!(
+            message.reasoning_content.as_deref(),
+            Some("Let me think harder")
+        );
+        assert_eq!(message.content.as_deref(), Some("Answer"));
+    }
+}
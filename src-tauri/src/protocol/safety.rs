@@ -0,0 +1,201 @@
+//! 安全设置：按配置注入 safetySettings，支持按请求覆盖，并提取被拦截的类别
+
+use super::ConvertError;
+use crate::config::{is_valid_threshold, SafetySettings, SAFETY_THRESHOLDS};
+use serde::Serialize;
+use serde_json::json;
+
+/// 配置项名与 Gemini 类别的对应关系
+pub const CATEGORIES: [(&str, &str); 5] = [
+    ("harassment", "HARM_CATEGORY_HARASSMENT"),
+    ("hate_speech", "HARM_CATEGORY_HATE_SPEECH"),
+    ("sexually_explicit", "HARM_CATEGORY_SEXUALLY_EXPLICIT"),
+    ("dangerous_content", "HARM_CATEGORY_DANGEROUS_CONTENT"),
+    ("civic_integrity", "HARM_CATEGORY_CIVIC_INTEGRITY"),
+];
+
+/// 配置项名或 Gemini 类别名转换为 Gemini 类别名
+fn category(name: &str) -> Option<&'static str> {
+    CATEGORIES
+        .iter()
+        .find(|(short, full)| *short == name || *full == name)
+        .map(|(_, full)| *full)
+}
+
+/// 配置的安全设置转换为 Gemini safetySettings 列表（未配置的类别不下发）
+pub fn to_gemini(settings: &SafetySettings) -> Vec<serde_json::Value> {
+    settings
+        .entries()
+        .into_iter()
+        .filter_map(|(name, threshold)| {
+            Some(json!({"category": category(name)?, "threshold": threshold?}))
+        })
+        .collect()
+}
+
+fn setting(category_name: &str, threshold: &str) -> Result<serde_json::Value, ConvertError> {
+    let category = category(category_name).ok_or_else(|| {
+        ConvertError::InvalidRequest(format!("未知的安全类别: {}", category_name))
+    })?;
+    if !is_valid_threshold(threshold) {
+        return Err(ConvertError::InvalidRequest(format!(
+            "{} 的安全阈值 {:?} 无效，可选值: {}",
+            category_name,
+            threshold,
+            SAFETY_THRESHOLDS.join(", ")
+        )));
+    }
+    Ok(json!({"category": category, "threshold": threshold}))
+}
+
+/// 解析请求中的覆盖设置
+///
+/// 支持 `{"harassment": "BLOCK_NONE"}` 或 Gemini 格式的 `[{"category", "threshold"}]`。
+pub fn parse_overrides(value: &serde_json::Value) -> Result<Vec<serde_json::Value>, ConvertError> {
+    match value {
+        serde_json::Value::Object(map) => map
+            .iter()
+            .map(|(name, threshold)| {
+                let threshold = threshold.as_str().ok_or_else(|| {
+                    ConvertError::InvalidRequest(format!("{} 的安全阈值必须是字符串", name))
+                })?;
+                setting(name, threshold)
+            })
+            .collect(),
+        serde_json::Value::Array(items) => items
+            .iter()
+            .map(
+                |item| match (item["category"].as_str(), item["threshold"].as_str()) {
+                    (Some(category), Some(threshold)) => setting(category, threshold),
+                    _ => Err(ConvertError::InvalidRequest(
+                        "safetySettings 项必须包含 category 和 threshold".to_string(),
+                    )),
+                },
+            )
+            .collect(),
+        _ => Err(ConvertError::InvalidRequest(
+            "safety_settings 必须是对象或数组".to_string(),
+        )),
+    }
+}
+
+/// 为 Gemini 请求注入安全设置：请求中已有的类别优先，其余按配置补齐
+pub fn apply(
+    request: &mut serde_json::Value,
+    defaults: &SafetySettings,
+) -> Result<(), ConvertError> {
+    let mut settings = match request.get("safetySettings") {
+        Some(existing) => parse_overrides(existing)?,
+        None => Vec::new(),
+    };
+    for default in to_gemini(defaults) {
+        if !settings
+            .iter()
+            .any(|s| s["category"] == default["category"])
+        {
+            settings.push(default);
+        }
+    }
+    if !settings.is_empty() {
+        request["safetySettings"] = json!(settings);
+    }
+    Ok(())
+}
+
+/// 因安全原因被拦截的信息
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SafetyBlock {
+    /// prompt（提示词被拦截）或 response（生成内容被拦截）
+    pub source: &'static str,
+    pub reason: String,
+    pub categories: Vec<String>,
+}
+
+fn blocked_in(ratings: &serde_json::Value) -> Vec<String> {
+    ratings
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|r| r["blocked"].as_bool() == Some(true))
+        .filter_map(|r| r["category"].as_str().map(String::from))
+        .collect()
+}
+
+/// 提取 Gemini 响应中的安全拦截信息
+pub fn blocked(response: &serde_json::Value) -> Option<SafetyBlock> {
+    let feedback = &response["promptFeedback"];
+    if let Some(reason) = feedback["blockReason"].as_str() {
+        return Some(SafetyBlock {
+            source: "prompt",
+            reason: reason.to_string(),
+            categories: blocked_in(&feedback["safetyRatings"]),
+        });
+    }
+
+    let candidate = &response["candidates"][0];
+    let reason = candidate["finishReason"].as_str()?;
+    let categories = blocked_in(&candidate["safetyRatings"]);
+    if reason == "SAFETY" || !categories.is_empty() {
+        Some(SafetyBlock {
+            source: "response",
+            reason: reason.to_string(),
+            categories,
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_merges_defaults_and_overrides() {
+        let defaults = SafetySettings {
+            harassment: Some("OFF".to_string()),
+            civic_integrity: Some("BLOCK_NONE".to_string()),
+            ..Default::default()
+        };
+        let mut request = json!({
+            "contents": [],
+            "safetySettings": {"harassment": "BLOCK_ONLY_HIGH"}
+        });
+        apply(&mut request, &defaults).unwrap();
+        assert_eq!(
+            request["safetySettings"],
+            json!([
+                {"category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_ONLY_HIGH"},
+                {"category": "HARM_CATEGORY_CIVIC_INTEGRITY", "threshold": "BLOCK_NONE"}
+            ])
+        );
+
+        let mut request = json!({"safetySettings": [
+            {"category": "HARM_CATEGORY_HATE_SPEECH", "threshold": "BLOCK_SOME"}
+        ]});
+        assert!(apply(&mut request, &defaults).is_err());
+    }
+
+    #[test]
+    fn test_blocked_categories() {
+        let response = json!({"candidates": [{
+            "finishReason": "SAFETY",
+            "safetyRatings": [
+                {"category": "HARM_CATEGORY_HARASSMENT", "probability": "LOW"},
+                {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true}
+            ]
+        }]});
+        assert_eq!(
+            blocked(&response),
+            Some(SafetyBlock {
+                source: "response",
+                reason: "SAFETY".to_string(),
+                categories: vec!["HARM_CATEGORY_DANGEROUS_CONTENT".to_string()],
+            })
+        );
+
+        let response = json!({"promptFeedback": {"blockReason": "SAFETY", "safetyRatings": []}});
+        assert_eq!(blocked(&response).unwrap().source, "prompt");
+        assert!(blocked(&json!({"candidates": [{"finishReason": "STOP"}]})).is_none());
+    }
+}
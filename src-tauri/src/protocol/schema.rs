@@ -0,0 +1,489 @@
+//! 工具定义翻译：JSON Schema 清洗、functionDeclarations、tool_choice 与函数调用 ID
+//!
+//! Gemini 只接受 OpenAPI 3.0 Schema 的一个子集，Anthropic / OpenAI 工具定义中常见的
+//! `$schema`、`additionalProperties`、`$ref`、`anyOf` + null、各种 `format` 都需要改写或去掉。
+
+use super::ConvertError;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+/// Gemini Schema 支持的字段
+const ALLOWED_KEYWORDS: [&str; 16] = [
+    "type",
+    "format",
+    "title",
+    "description",
+    "nullable",
+    "enum",
+    "items",
+    "properties",
+    "required",
+    "minItems",
+    "maxItems",
+    "minimum",
+    "maximum",
+    "minLength",
+    "maxLength",
+    "anyOf",
+];
+
+/// $ref 展开的最大深度（递归引用在此截断为任意对象）
+const MAX_REF_DEPTH: usize = 8;
+
+/// 清洗 JSON Schema 使其可以作为 Gemini functionDeclaration.parameters / responseSchema
+pub fn sanitize(schema: &Value) -> Value {
+    let defs = definitions(schema);
+    sanitize_node(schema, &defs, 0)
+}
+
+fn definitions(root: &Value) -> Map<String, Value> {
+    let mut defs = Map::new();
+    for key in ["$defs", "definitions"] {
+        if let Some(map) = root[key].as_object() {
+            for (name, schema) in map {
+                defs.insert(format!("#/{}/{}", key, name), schema.clone());
+            }
+        }
+    }
+    defs
+}
+
+fn sanitize_node(node: &Value, defs: &Map<String, Value>, depth: usize) -> Value {
+    let Some(obj) = node.as_object() else {
+        // `true` / 缺失等宽松写法视为任意值
+        return json!({});
+    };
+
+    if let Some(reference) = obj.get("$ref").and_then(|r| r.as_str()) {
+        return match defs.get(reference) {
+            Some(target) if depth < MAX_REF_DEPTH => {
+                let mut resolved = sanitize_node(target, defs, depth + 1);
+                if let Some(description) = obj.get("description") {
+                    resolved["description"] = description.clone();
+                }
+                resolved
+            }
+            _ => json!({"type": "object"}),
+        };
+    }
+
+    // allOf：合并各子 schema
+    if let Some(all_of) = obj.get("allOf").and_then(|a| a.as_array()) {
+        let mut merged = obj.clone();
+        merged.remove("allOf");
+        for sub in all_of {
+            let sub = sanitize_node(sub, defs, depth + 1);
+            merge_into(&mut merged, &sub);
+        }
+        return sanitize_node(&Value::Object(merged), defs, depth);
+    }
+
+    let mut out = Map::new();
+    let mut nullable = obj.get("nullable").and_then(|n| n.as_bool()) == Some(true);
+
+    // type: ["string", "null"] → type: string + nullable
+    match obj.get("type") {
+        Some(Value::Array(types)) => {
+            let non_null: Vec<&Value> = types.iter().filter(|t| *t != "null").collect();
+            nullable |= non_null.len() < types.len();
+            match non_null.as_slice() {
+                [] => {}
+                [single] => {
+                    out.insert("type".to_string(), (*single).clone());
+                }
+                many => {
+                    let variants: Vec<Value> = many
+                        .iter()
+                        .map(|t| {
+                            let mut variant = obj.clone();
+                            variant.insert("type".to_string(), (*t).clone());
+                            sanitize_node(&Value::Object(variant), defs, depth + 1)
+                        })
+                        .collect();
+                    out.insert("anyOf".to_string(), Value::Array(variants));
+                }
+            }
+        }
+        Some(t @ Value::String(_)) if t != "null" => {
+            out.insert("type".to_string(), t.clone());
+        }
+        Some(_) => nullable = true,
+        None => {}
+    }
+
+    // anyOf / oneOf：去掉 null 分支，只剩一个分支时直接展开
+    if let Some(variants) = obj
+        .get("anyOf")
+        .or_else(|| obj.get("oneOf"))
+        .and_then(|v| v.as_array())
+    {
+        let mut kept = Vec::new();
+        for variant in variants {
+            if variant["type"] == "null" {
+                nullable = true;
+            } else {
+                kept.push(sanitize_node(variant, defs, depth + 1));
+            }
+        }
+        match kept.len() {
+            0 => {}
+            1 => {
+                let single = kept.pop().unwrap();
+                if let Some(map) = single.as_object() {
+                    for (k, v) in map {
+                        out.entry(k.clone()).or_insert_with(|| v.clone());
+                    }
+                }
+            }
+            _ => {
+                out.insert("anyOf".to_string(), Value::Array(kept));
+            }
+        }
+    }
+
+    for (key, value) in obj {
+        match key.as_str() {
+            "type" | "anyOf" | "oneOf" | "nullable" => {}
+            "properties" => {
+                let properties: Map<String, Value> = value
+                    .as_object()
+                    .map(|props| {
+                        props
+                            .iter()
+                            .map(|(k, v)| (k.clone(), sanitize_node(v, defs, depth + 1)))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                out.insert(key.clone(), Value::Object(properties));
+            }
+            "items" => {
+                // 元组形式的 items 取第一项
+                let item = match value {
+                    Value::Array(items) => items.first().cloned().unwrap_or(json!({})),
+                    other => other.clone(),
+                };
+                out.insert(key.clone(), sanitize_node(&item, defs, depth + 1));
+            }
+            "const" if value.is_string() => {
+                out.insert("enum".to_string(), json!([value]));
+            }
+            "const" => {}
+            // Gemini 只接受字符串枚举
+            "enum" => {
+                if let Some(values) = value.as_array() {
+                    if values.iter().all(|v| v.is_string()) {
+                        out.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            "format" => {
+                if let Some(format) = value.as_str() {
+                    if is_supported_format(obj.get("type"), format) {
+                        out.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            k if ALLOWED_KEYWORDS.contains(&k) => {
+                out.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+            _ => {}
+        }
+    }
+
+    // required 只能引用已声明的属性
+    if let Some(required) = out.get("required").and_then(|r| r.as_array()) {
+        let properties = out.get("properties").and_then(|p| p.as_object());
+        let kept: Vec<Value> = required
+            .iter()
+            .filter(|name| {
+                name.as_str()
+                    .is_some_and(|n| properties.is_some_and(|p| p.contains_key(n)))
+            })
+            .cloned()
+            .collect();
+        if kept.is_empty() {
+            out.remove("required");
+        } else {
+            out.insert("required".to_string(), Value::Array(kept));
+        }
+    }
+    if out.contains_key("enum") && !out.contains_key("type") {
+        out.insert("type".to_string(), json!("string"));
+    }
+    if nullable {
+        out.insert("nullable".to_string(), json!(true));
+    }
+    Value::Object(out)
+}
+
+fn is_supported_format(schema_type: Option<&Value>, format: &str) -> bool {
+    match schema_type.and_then(|t| t.as_str()) {
+        Some("string") => matches!(format, "enum" | "date-time"),
+        Some("integer") => matches!(format, "int32" | "int64"),
+        Some("number") => matches!(format, "float" | "double"),
+        _ => false,
+    }
+}
+
+fn merge_into(target: &mut Map<String, Value>, source: &Value) {
+    let Some(source) = source.as_object() else {
+        return;
+    };
+    for (key, value) in source {
+        match (key.as_str(), target.get_mut(key)) {
+            ("properties", Some(Value::Object(existing))) => {
+                if let Some(props) = value.as_object() {
+                    for (k, v) in props {
+                        existing.insert(k.clone(), v.clone());
+                    }
+                }
+            }
+            ("required", Some(Value::Array(existing))) => {
+                for name in value.as_array().into_iter().flatten() {
+                    if !existing.contains(name) {
+                        existing.push(name.clone());
+                    }
+                }
+            }
+            (_, Some(_)) => {}
+            (_, None) => {
+                target.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// 生成一个 functionDeclaration（无参数的工具不带 parameters）
+pub fn function_declaration(
+    name: &str,
+    description: Option<&str>,
+    parameters: Option<&Value>,
+) -> Value {
+    let mut declaration = json!({"name": name});
+    if let Some(description) = description {
+        declaration["description"] = json!(description);
+    }
+    if let Some(parameters) = parameters {
+        let sanitized = sanitize(parameters);
+        let empty_object = sanitized["type"] == "object"
+            && sanitized["properties"]
+                .as_object()
+                .is_none_or(|p| p.is_empty());
+        if !empty_object {
+            declaration["parameters"] = sanitized;
+        }
+    }
+    declaration
+}
+
+/// 工具选择方式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolChoice {
+    Auto,
+    None,
+    /// 必须调用某个工具
+    Any,
+    /// 必须调用指定工具
+    Function(String),
+}
+
+impl ToolChoice {
+    /// OpenAI：`"none" | "auto" | "required" | {"type": "function", "function": {"name"}}`
+    pub fn from_openai(value: &Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::String(mode) => match mode.as_str() {
+                "none" => Ok(ToolChoice::None),
+                "auto" => Ok(ToolChoice::Auto),
+                "required" => Ok(ToolChoice::Any),
+                other => Err(ConvertError::InvalidRequest(format!(
+                    "无效的 tool_choice: {}",
+                    other
+                ))),
+            },
+            Value::Object(_) => match value["function"]["name"].as_str() {
+                Some(name) => Ok(ToolChoice::Function(name.to_string())),
+                None => Err(ConvertError::InvalidRequest(
+                    "tool_choice 缺少 function.name".to_string(),
+                )),
+            },
+            _ => Err(ConvertError::InvalidRequest(
+                "无效的 tool_choice".to_string(),
+            )),
+        }
+    }
+
+    /// Anthropic：`{"type": "auto" | "any" | "none" | "tool", "name"}`
+    pub fn from_anthropic(value: &Value) -> Result<Self, ConvertError> {
+        match value["type"].as_str() {
+            Some("auto") => Ok(ToolChoice::Auto),
+            Some("any") => Ok(ToolChoice::Any),
+            Some("none") => Ok(ToolChoice::None),
+            Some("tool") => match value["name"].as_str() {
+                Some(name) => Ok(ToolChoice::Function(name.to_string())),
+                None => Err(ConvertError::InvalidRequest(
+                    "tool_choice 缺少 name".to_string(),
+                )),
+            },
+            _ => Err(ConvertError::InvalidRequest(
+                "无效的 tool_choice".to_string(),
+            )),
+        }
+    }
+
+    /// Gemini toolConfig
+    pub fn to_gemini(&self) -> Value {
+        let config = match self {
+            ToolChoice::Auto => json!({"mode": "AUTO"}),
+            ToolChoice::None => json!({"mode": "NONE"}),
+            ToolChoice::Any => json!({"mode": "ANY"}),
+            ToolChoice::Function(name) => {
+                json!({"mode": "ANY", "allowedFunctionNames": [name]})
+            }
+        };
+        json!({"functionCallingConfig": config})
+    }
+}
+
+/// 校验 tool_choice 指定的工具存在
+pub fn check_tool_choice(choice: &ToolChoice, tool_names: &[&str]) -> Result<(), ConvertError> {
+    match choice {
+        ToolChoice::Function(name) if !tool_names.contains(&name.as_str()) => Err(
+            ConvertError::InvalidRequest(format!("tool_choice 指定的工具 {} 不存在", name)),
+        ),
+        ToolChoice::Any | ToolChoice::Function(_) if tool_names.is_empty() => Err(
+            ConvertError::InvalidRequest("tool_choice 要求调用工具，但没有定义 tools".to_string()),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// 上游未返回 functionCall.id 时生成稳定的调用 ID
+///
+/// 由响应 ID、调用在响应中的序号、函数名与参数决定，同一响应无论流式与否得到相同 ID。
+/// 没有响应 ID 时混入随机数，避免不同轮次中相同的调用得到相同 ID。
+pub fn stable_call_id(
+    prefix: &str,
+    response_id: Option<&str>,
+    ordinal: usize,
+    call: &Value,
+) -> String {
+    if let Some(id) = call["id"].as_str() {
+        return id.to_string();
+    }
+    let mut hasher = Sha256::new();
+    match response_id {
+        Some(response_id) => hasher.update(response_id.as_bytes()),
+        None => hasher.update(uuid::Uuid::new_v4().as_bytes()),
+    }
+    hasher.update(b"\0");
+    hasher.update(ordinal.to_string().as_bytes());
+    hasher.update(b"\0");
+    hasher.update(call["name"].as_str().unwrap_or_default().as_bytes());
+    hasher.update(b"\0");
+    hasher.update(call.get("args").map(|a| a.to_string()).unwrap_or_default());
+    let digest = hex::encode(hasher.finalize());
+    format!("{}{}", prefix, &digest[..24])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_strips_and_rewrites() {
+        let schema = json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "url": {"type": "string", "format": "uri"},
+                "when": {"type": "string", "format": "date-time"},
+                "note": {"anyOf": [{"type": "string"}, {"type": "null"}], "default": null},
+                "count": {"type": ["integer", "null"], "minimum": 0},
+                "mode": {"const": "fast"},
+                "target": {"$ref": "#/$defs/Target"},
+                "tags": {"type": "array", "items": {"type": "string"}, "uniqueItems": true}
+            },
+            "required": ["url", "missing"],
+            "$defs": {"Target": {"type": "object", "properties": {"id": {"type": "string"}}}}
+        });
+
+        assert_eq!(
+            sanitize(&schema),
+            json!({
+                "type": "object",
+                "properties": {
+                    "url": {"type": "string"},
+                    "when": {"type": "string", "format": "date-time"},
+                    "note": {"type": "string", "nullable": true},
+                    "count": {"type": "integer", "minimum": 0, "nullable": true},
+                    "mode": {"type": "string", "enum": ["fast"]},
+                    "target": {"type": "object", "properties": {"id": {"type": "string"}}},
+                    "tags": {"type": "array", "items": {"type": "string"}}
+                },
+                "required": ["url"]
+            })
+        );
+    }
+
+    #[test]
+    fn test_sanitize_recursive_ref_and_all_of() {
+        let schema = json!({
+            "allOf": [
+                {"type": "object", "properties": {"a": {"type": "string"}}, "required": ["a"]},
+                {"properties": {"node": {"$ref": "#/definitions/Node"}}}
+            ],
+            "definitions": {"Node": {"type": "object", "properties": {"next": {"$ref": "#/definitions/Node"}}}}
+        });
+        let sanitized = sanitize(&schema);
+        assert_eq!(sanitized["required"], json!(["a"]));
+        assert_eq!(sanitized["properties"]["a"], json!({"type": "string"}));
+        assert_eq!(sanitized["properties"]["node"]["type"], "object");
+    }
+
+    #[test]
+    fn test_function_declaration_drops_empty_parameters() {
+        let declaration = function_declaration(
+            "now",
+            Some("Current time"),
+            Some(&json!({"type": "object", "properties": {}, "additionalProperties": false})),
+        );
+        assert_eq!(
+            declaration,
+            json!({"name": "now", "description": "Current time"})
+        );
+    }
+
+    #[test]
+    fn test_tool_choice() {
+        let choice = ToolChoice::from_anthropic(&json!({"type": "tool", "name": "f"})).unwrap();
+        assert_eq!(
+            choice.to_gemini(),
+            json!({"functionCallingConfig": {"mode": "ANY", "allowedFunctionNames": ["f"]}})
+        );
+        assert_eq!(
+            ToolChoice::from_openai(&json!("required")).unwrap(),
+            ToolChoice::Any
+        );
+        assert!(check_tool_choice(&choice, &["g"]).is_err());
+        assert!(check_tool_choice(&ToolChoice::Auto, &[]).is_ok());
+    }
+
+    #[test]
+    fn test_stable_call_id() {
+        let call = json!({"name": "f", "args": {"x": 1}});
+        let a = stable_call_id("toolu_", Some("r1"), 0, &call);
+        assert_eq!(a, stable_call_id("toolu_", Some("r1"), 0, &call));
+        assert_ne!(a, stable_call_id("toolu_", Some("r1"), 1, &call));
+        assert!(a.starts_with("toolu_") && a.len() == 30);
+        // 没有响应 ID 时，不同轮次中相同的调用不会重复
+        assert_ne!(
+            stable_call_id("toolu_", None, 0, &call),
+            stable_call_id("toolu_", None, 0, &call)
+        );
+        assert_eq!(
+            stable_call_id("call_", None, 0, &json!({"id": "up", "name": "f"})),
+            "up"
+        );
+    }
+}
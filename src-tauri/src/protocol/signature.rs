@@ -0,0 +1,249 @@
+//! thoughtSignature 的保存与恢复
+//!
+//! 思考模型要求后续轮次原样带回 functionCall 上的 thoughtSignature。Anthropic 的 tool_use
+//! 和 OpenAI 的 tool_calls 没有对应字段，因此缓存在服务端，下次请求时补回。
+//! 缓存键由产生该调用的对话前文与调用内容共同决定，不同对话中相同的调用不会互相借用签名。
+//! 思考块的签名由 Anthropic thinking.signature 字段直接携带，不经过缓存。
+
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// 缓存上限，超过后淘汰最早写入的条目
+pub const MAX_ENTRIES: usize = 4096;
+
+/// 缓存未命中时使用的占位签名（上游接受它跳过签名校验）
+pub const SKIP_VALIDATOR: &str = "skip_thought_signature_validator";
+
+#[derive(Default)]
+struct SignatureCache {
+    entries: HashMap<String, String>,
+    order: VecDeque<String>,
+}
+
+static CACHE: Mutex<Option<SignatureCache>> = Mutex::new(None);
+
+/// 去掉客户端回传时可能变化的字段：签名本身，以及 functionCall / functionResponse 的 id
+/// （上游不一定返回 id，客户端看到的 id 可能是本地生成的）
+fn normalize(content: &serde_json::Value) -> serde_json::Value {
+    let mut content = content.clone();
+    for part in content["parts"].as_array_mut().into_iter().flatten() {
+        if let Some(part) = part.as_object_mut() {
+            part.remove("thoughtSignature");
+        }
+        for field in ["functionCall", "functionResponse"] {
+            if let Some(inner) = part.get_mut(field).and_then(|v| v.as_object_mut()) {
+                inner.remove("id");
+            }
+        }
+    }
+    content
+}
+
+/// 对话前文的哈希，按 contents 逐条累加
+#[derive(Clone, Default)]
+pub struct History(Sha256);
+
+impl History {
+    fn push(&mut self, content: &serde_json::Value) {
+        // serde_json 的对象按键排序，序列化结果稳定
+        self.0.update(normalize(content).to_string());
+        self.0.update(b"\0");
+    }
+
+    /// 前文之后的 functionCall 的缓存键，非 functionCall 返回 None
+    fn call_key(&self, part: &serde_json::Value) -> Option<String> {
+        let call = part.get("functionCall")?;
+        let mut hasher = self.0.clone();
+        hasher.update(b"call\0");
+        hasher.update(call["name"].as_str()?.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(call.get("args").map(|a| a.to_string()).unwrap_or_default());
+        Some(hex::encode(hasher.finalize()))
+    }
+}
+
+fn insert(key: String, signature: &str) {
+    let mut guard = CACHE.lock().unwrap();
+    let cache = guard.get_or_insert_with(SignatureCache::default);
+    if cache
+        .entries
+        .insert(key.clone(), signature.to_string())
+        .is_none()
+    {
+        cache.order.push_back(key);
+        while cache.order.len() > MAX_ENTRIES {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// 记录 Gemini 响应（或流式分块）中带签名的 functionCall，history 为产生该响应的请求前文
+pub fn remember_response(history: &History, response: &serde_json::Value) {
+    for part in response["candidates"][0]["content"]["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        let Some(signature) = part["thoughtSignature"].as_str() else {
+            continue;
+        };
+        if signature == SKIP_VALIDATOR {
+            continue;
+        }
+        if let Some(key) = history.call_key(part) {
+            insert(key, signature);
+        }
+    }
+}
+
+fn lookup(key: &str) -> Option<String> {
+    CACHE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|c| c.entries.get(key).cloned())
+}
+
+/// 为请求中 model 轮次缺少签名的 functionCall 补回签名，返回整个请求的前文哈希
+///
+/// 思考模型上，若某轮的第一个 functionCall 在缓存中找不到签名（例如服务重启后），
+/// 使用占位签名避免上游校验失败。
+pub fn restore_request(request: &mut serde_json::Value, thinking_model: bool) -> History {
+    let mut history = History::default();
+    let Some(contents) = request["contents"].as_array_mut() else {
+        return history;
+    };
+    for content in contents.iter_mut() {
+        if content["role"] == "model" {
+            restore_turn(&history, content, thinking_model);
+        }
+        history.push(content);
+    }
+    history
+}
+
+fn restore_turn(history: &History, content: &mut serde_json::Value, thinking_model: bool) {
+    let Some(parts) = content["parts"].as_array_mut() else {
+        return;
+    };
+    let mut first_call = true;
+    for part in parts.iter_mut() {
+        let Some(key) = history.call_key(part) else {
+            continue;
+        };
+        if part.get("thoughtSignature").is_none() {
+            if let Some(signature) = lookup(&key) {
+                part["thoughtSignature"] = serde_json::Value::String(signature);
+            } else if first_call && thinking_model {
+                part["thoughtSignature"] = serde_json::Value::String(SKIP_VALIDATOR.to_string());
+            }
+        }
+        first_call = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn call_response(name: &str, args: serde_json::Value, signature: &str) -> serde_json::Value {
+        json!({"candidates": [{"content": {"parts": [
+            {"functionCall": {"name": name, "args": args}, "thoughtSignature": signature}
+        ]}}]})
+    }
+
+    #[test]
+    fn test_roundtrip_keyed_by_history() {
+        let mut first = json!({"contents": [
+            {"role": "user", "parts": [{"text": "sig_test_roundtrip"}]}
+        ]});
+        let history = restore_request(&mut first, true);
+        remember_response(
+            &history,
+            &call_response("sig_test_lookup", json!({"b": 2, "a": 1}), "SIG1"),
+        );
+
+        // 客户端回传时带着本地生成的 id，参数顺序也可能不同
+        let mut second = json!({"contents": [
+            {"role": "user", "parts": [{"text": "sig_test_roundtrip"}]},
+            {"role": "model", "parts": [
+                {"functionCall": {"id": "toolu_x", "name": "sig_test_lookup", "args": {"a": 1, "b": 2}}},
+                {"functionCall": {"id": "toolu_y", "name": "sig_test_other", "args": {}}}
+            ]},
+            {"role": "user", "parts": [
+                {"functionResponse": {"id": "toolu_x", "name": "sig_test_lookup", "response": {}}}
+            ]}
+        ]});
+        let history = restore_request(&mut second, true);
+        let parts = &second["contents"][1]["parts"];
+        assert_eq!(parts[0]["thoughtSignature"], "SIG1");
+        assert!(parts[1].get("thoughtSignature").is_none());
+        assert!(second["contents"][0]["parts"][0]
+            .get("thoughtSignature")
+            .is_none());
+
+        // 补回的签名不影响前文哈希，下一轮仍能命中
+        remember_response(
+            &history,
+            &call_response("sig_test_lookup", json!({"a": 3}), "SIG2"),
+        );
+        let mut third = second.clone();
+        let contents = third["contents"].as_array_mut().unwrap();
+        contents[1]["parts"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("thoughtSignature");
+        contents.push(json!({"role": "model", "parts": [
+            {"functionCall": {"name": "sig_test_lookup", "args": {"a": 3}}}
+        ]}));
+        restore_request(&mut third, true);
+        assert_eq!(third["contents"][1]["parts"][0]["thoughtSignature"], "SIG1");
+        assert_eq!(third["contents"][3]["parts"][0]["thoughtSignature"], "SIG2");
+    }
+
+    #[test]
+    fn test_same_call_in_other_conversation_is_not_reused() {
+        let mut request = json!({"contents": [
+            {"role": "user", "parts": [{"text": "sig_test_conversation_a"}]}
+        ]});
+        let history = restore_request(&mut request, true);
+        remember_response(
+            &history,
+            &call_response("sig_test_shared", json!({}), "SIG_A"),
+        );
+
+        let mut other = json!({"contents": [
+            {"role": "user", "parts": [{"text": "sig_test_conversation_b"}]},
+            {"role": "model", "parts": [{"functionCall": {"name": "sig_test_shared", "args": {}}}]}
+        ]});
+        restore_request(&mut other, true);
+        assert_eq!(
+            other["contents"][1]["parts"][0]["thoughtSignature"],
+            SKIP_VALIDATOR
+        );
+    }
+
+    #[test]
+    fn test_missing_signature_uses_placeholder_on_thinking_models() {
+        let request = json!({"contents": [{"role": "model", "parts": [
+            {"text": "sig_test_unseen"},
+            {"functionCall": {"name": "sig_test_unseen", "args": {}}},
+            {"functionCall": {"name": "sig_test_unseen_2", "args": {}}}
+        ]}]});
+
+        let mut thinking = request.clone();
+        restore_request(&mut thinking, true);
+        let parts = &thinking["contents"][0]["parts"];
+        assert_eq!(parts[1]["thoughtSignature"], SKIP_VALIDATOR);
+        assert!(parts[0].get("thoughtSignature").is_none());
+        assert!(parts[2].get("thoughtSignature").is_none());
+
+        let mut plain = request.clone();
+        restore_request(&mut plain, false);
+        assert_eq!(plain, request);
+    }
+}
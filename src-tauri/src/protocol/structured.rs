@@ -0,0 +1,298 @@
+//! 结构化输出：OpenAI response_format 到 responseMimeType / responseSchema 的映射与结果校验
+
+use super::{schema, ConvertError};
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+use tracing::warn;
+
+/// 客户端要求的响应格式
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResponseFormat {
+    Text,
+    /// 任意 JSON
+    JsonObject,
+    /// 符合给定 schema 的 JSON（schema 已清洗为 Gemini 格式）
+    JsonSchema {
+        name: String,
+        schema: Value,
+    },
+}
+
+/// 结构化输出校验失败
+#[derive(Debug, Error)]
+pub enum StructuredOutputError {
+    #[error("响应不是合法的 JSON: {0}")]
+    InvalidJson(String),
+
+    #[error("响应不符合 JSON Schema {name}: {}", errors.join("; "))]
+    SchemaMismatch { name: String, errors: Vec<String> },
+}
+
+impl StructuredOutputError {
+    /// 各处校验错误（JSON 解析失败时只有一条）
+    pub fn errors(&self) -> Vec<String> {
+        match self {
+            StructuredOutputError::InvalidJson(e) => vec![e.clone()],
+            StructuredOutputError::SchemaMismatch { errors, .. } => errors.clone(),
+        }
+    }
+}
+
+impl ResponseFormat {
+    /// 解析 OpenAI response_format
+    pub fn from_openai(value: Option<&Value>) -> Result<Self, ConvertError> {
+        let Some(value) = value else {
+            return Ok(ResponseFormat::Text);
+        };
+        match value["type"].as_str() {
+            Some("text") | None => Ok(ResponseFormat::Text),
+            Some("json_object") => Ok(ResponseFormat::JsonObject),
+            Some("json_schema") => {
+                let json_schema = &value["json_schema"];
+                let name = json_schema["name"]
+                    .as_str()
+                    .unwrap_or("response")
+                    .to_string();
+                match json_schema.get("schema") {
+                    Some(raw) if raw.is_object() => Ok(ResponseFormat::JsonSchema {
+                        name,
+                        schema: schema::sanitize(raw),
+                    }),
+                    _ => Err(ConvertError::InvalidRequest(
+                        "response_format.json_schema 缺少 schema".to_string(),
+                    )),
+                }
+            }
+            Some(other) => Err(ConvertError::InvalidRequest(format!(
+                "不支持的 response_format: {}",
+                other
+            ))),
+        }
+    }
+
+    /// 写入 generationConfig
+    pub fn apply(&self, generation_config: &mut Map<String, Value>) {
+        match self {
+            ResponseFormat::Text => {}
+            ResponseFormat::JsonObject => {
+                generation_config.insert("responseMimeType".to_string(), json!("application/json"));
+            }
+            ResponseFormat::JsonSchema { schema, .. } => {
+                generation_config.insert("responseMimeType".to_string(), json!("application/json"));
+                generation_config.insert("responseSchema".to_string(), schema.clone());
+            }
+        }
+    }
+
+    /// 校验模型返回的文本
+    pub fn validate(&self, text: &str) -> Result<(), StructuredOutputError> {
+        if *self == ResponseFormat::Text {
+            return Ok(());
+        }
+        let value: Value = serde_json::from_str(text)
+            .map_err(|e| StructuredOutputError::InvalidJson(e.to_string()))?;
+        if let ResponseFormat::JsonSchema { name, schema } = self {
+            let mut errors = Vec::new();
+            validate_node(&value, schema, "$", &mut errors);
+            if !errors.is_empty() {
+                return Err(StructuredOutputError::SchemaMismatch {
+                    name: name.clone(),
+                    errors,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// 校验结果；strict 模式下返回错误，否则只记录警告
+    pub fn check(&self, text: Option<&str>, strict: bool) -> Result<(), StructuredOutputError> {
+        // 没有文本（例如只返回了工具调用）时不校验
+        let Some(text) = text else {
+            return Ok(());
+        };
+        match self.validate(text) {
+            Err(e) if strict => Err(e),
+            Err(e) => {
+                warn!("结构化输出校验未通过: {}", e);
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+}
+
+fn type_matches(value: &Value, expected: &str) -> bool {
+    match expected {
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+/// 按清洗后的 Gemini schema 校验（只涉及 schema::sanitize 保留的字段）
+fn validate_node(value: &Value, schema: &Value, path: &str, errors: &mut Vec<String>) {
+    if value.is_null() {
+        if schema["nullable"].as_bool() != Some(true) && schema.get("type").is_some() {
+            errors.push(format!("{}: 不能为 null", path));
+        }
+        return;
+    }
+
+    if let Some(variants) = schema["anyOf"].as_array() {
+        let matched = variants.iter().any(|variant| {
+            let mut variant_errors = Vec::new();
+            validate_node(value, variant, path, &mut variant_errors);
+            variant_errors.is_empty()
+        });
+        if !matched {
+            errors.push(format!("{}: 不匹配 anyOf 中的任何一项", path));
+        }
+        return;
+    }
+
+    if let Some(expected) = schema["type"].as_str() {
+        if !type_matches(value, expected) {
+            errors.push(format!("{}: 期望类型 {}", path, expected));
+            return;
+        }
+    }
+
+    if let Some(allowed) = schema["enum"].as_array() {
+        if !allowed.contains(value) {
+            errors.push(format!("{}: {} 不在枚举值中", path, value));
+        }
+    }
+
+    let bound = |key: &str| schema[key].as_f64();
+    match value {
+        Value::String(s) => {
+            let len = s.chars().count() as f64;
+            if bound("minLength").is_some_and(|min| len < min) {
+                errors.push(format!("{}: 长度小于 minLength", path));
+            }
+            if bound("maxLength").is_some_and(|max| len > max) {
+                errors.push(format!("{}: 长度大于 maxLength", path));
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if bound("minimum").is_some_and(|min| n < min) {
+                errors.push(format!("{}: 小于 minimum", path));
+            }
+            if bound("maximum").is_some_and(|max| n > max) {
+                errors.push(format!("{}: 大于 maximum", path));
+            }
+        }
+        Value::Array(items) => {
+            let len = items.len() as f64;
+            if bound("minItems").is_some_and(|min| len < min) {
+                errors.push(format!("{}: 元素数少于 minItems", path));
+            }
+            if bound("maxItems").is_some_and(|max| len > max) {
+                errors.push(format!("{}: 元素数多于 maxItems", path));
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate_node(item, item_schema, &format!("{}[{}]", path, i), errors);
+                }
+            }
+        }
+        Value::Object(map) => {
+            for name in schema["required"].as_array().into_iter().flatten() {
+                if let Some(name) = name.as_str() {
+                    if !map.contains_key(name) {
+                        errors.push(format!("{}: 缺少必需字段 {}", path, name));
+                    }
+                }
+            }
+            if let Some(properties) = schema["properties"].as_object() {
+                for (name, property) in properties {
+                    if let Some(field) = map.get(name) {
+                        validate_node(field, property, &format!("{}.{}", path, name), errors);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn person_format() -> ResponseFormat {
+        ResponseFormat::from_openai(Some(&json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": "person",
+                "strict": true,
+                "schema": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "properties": {
+                        "name": {"type": "string", "minLength": 1},
+                        "age": {"type": ["integer", "null"], "minimum": 0},
+                        "role": {"type": "string", "enum": ["admin", "user"]},
+                        "tags": {"type": "array", "items": {"type": "string"}}
+                    },
+                    "required": ["name", "age", "role"]
+                }
+            }
+        })))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_apply_sanitized_schema() {
+        let mut config = Map::new();
+        person_format().apply(&mut config);
+        assert_eq!(config["responseMimeType"], "application/json");
+        assert!(config["responseSchema"]
+            .get("additionalProperties")
+            .is_none());
+        assert_eq!(
+            config["responseSchema"]["properties"]["age"],
+            json!({"type": "integer", "minimum": 0, "nullable": true})
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let format = person_format();
+        assert!(format
+            .validate(r#"{"name": "Ada", "age": null, "role": "admin", "tags": ["x"]}"#)
+            .is_ok());
+
+        let err = format
+            .validate(r#"{"name": "", "role": "root", "tags": [1]}"#)
+            .unwrap_err();
+        assert_eq!(
+            err.errors(),
+            vec![
+                "$: 缺少必需字段 age",
+                "$.name: 长度小于 minLength",
+                "$.role: \"root\" 不在枚举值中",
+                "$.tags[0]: 期望类型 string",
+            ]
+        );
+
+        assert!(matches!(
+            format.validate("not json"),
+            Err(StructuredOutputError::InvalidJson(_))
+        ));
+        assert!(ResponseFormat::JsonObject.validate("[1]").is_ok());
+    }
+
+    #[test]
+    fn test_check_strict_mode() {
+        let format = person_format();
+        assert!(format.check(Some("{}"), false).is_ok());
+        assert!(format.check(Some("{}"), true).is_err());
+        assert!(format.check(None, true).is_ok());
+    }
+}
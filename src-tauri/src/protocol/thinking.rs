@@ -0,0 +1,281 @@
+//! 推理强度 / 思考预算到 Gemini thinkingConfig 的映射
+
+use super::ConvertError;
+use crate::config::{ReasoningEffort, ReasoningSettings};
+use serde_json::json;
+use tracing::debug;
+
+/// Anthropic 要求的最小思考预算
+pub const MIN_BUDGET_TOKENS: u32 = 1024;
+
+/// 客户端请求的思考方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThinkingRequest {
+    /// 未指定，按配置的 default_effort
+    Default,
+    /// OpenAI reasoning_effort
+    Effort(ReasoningEffort),
+    /// Anthropic thinking.budget_tokens
+    Budget(u32),
+    /// 明确关闭
+    Disabled,
+}
+
+impl ThinkingRequest {
+    /// 解析 OpenAI reasoning_effort
+    pub fn from_effort(effort: Option<&str>) -> Result<Self, ConvertError> {
+        match effort {
+            None => Ok(ThinkingRequest::Default),
+            Some("low") => Ok(ThinkingRequest::Effort(ReasoningEffort::Low)),
+            Some("medium") => Ok(ThinkingRequest::Effort(ReasoningEffort::Medium)),
+            Some("high") => Ok(ThinkingRequest::Effort(ReasoningEffort::High)),
+            Some(other) => Err(ConvertError::InvalidRequest(format!(
+                "无效的 reasoning_effort: {}（可选 low / medium / high）",
+                other
+            ))),
+        }
+    }
+
+    /// 解析 Anthropic thinking 参数（`{"type": "enabled", "budget_tokens": N}` 或 `{"type": "disabled"}`）
+    pub fn from_anthropic(
+        thinking: Option<&serde_json::Value>,
+        max_tokens: Option<u32>,
+    ) -> Result<Self, ConvertError> {
+        let Some(thinking) = thinking else {
+            return Ok(ThinkingRequest::Default);
+        };
+        match thinking["type"].as_str() {
+            Some("disabled") => Ok(ThinkingRequest::Disabled),
+            Some("enabled") => {
+                let budget = thinking["budget_tokens"]
+                    .as_u64()
+                    .and_then(|b| u32::try_from(b).ok())
+                    .ok_or_else(|| {
+                        ConvertError::InvalidRequest(
+                            "thinking.budget_tokens 必须是正整数".to_string(),
+                        )
+                    })?;
+                if budget < MIN_BUDGET_TOKENS {
+                    return Err(ConvertError::InvalidRequest(format!(
+                        "thinking.budget_tokens 不能小于 {}",
+                        MIN_BUDGET_TOKENS
+                    )));
+                }
+                if let Some(max_tokens) = max_tokens {
+                    if budget >= max_tokens {
+                        return Err(ConvertError::InvalidRequest(
+                            "thinking.budget_tokens 必须小于 max_tokens".to_string(),
+                        ));
+                    }
+                }
+                Ok(ThinkingRequest::Budget(budget))
+            }
+            _ => Err(ConvertError::InvalidRequest(
+                "thinking.type 必须是 enabled 或 disabled".to_string(),
+            )),
+        }
+    }
+}
+
+/// 推理强度对应的思考预算
+pub fn effort_budget(effort: ReasoningEffort) -> u32 {
+    match effort {
+        ReasoningEffort::Low => 1024,
+        ReasoningEffort::Medium => 8192,
+        ReasoningEffort::High => 24576,
+    }
+}
+
+/// 模型允许的最大思考预算（上游模型 ID）：Flash 系列 24576，其余 32768
+pub fn max_budget(model: &str) -> u32 {
+    let model = model.strip_prefix("models/").unwrap_or(model);
+    if model.contains("flash") {
+        24576
+    } else {
+        32768
+    }
+}
+
+/// 模型是否支持思考（上游模型 ID）
+///
+/// 已注册的模型以注册表为准，其余按命名规则推断。
+pub fn supports_thinking(model: &str) -> bool {
+    if let Some(info) = super::models::find(model) {
+        return info.capabilities.thinking;
+    }
+    let model = model.strip_prefix("models/").unwrap_or(model);
+    model.starts_with("gemini-2.5-")
+        || model.starts_with("gemini-3-")
+        || (model.starts_with("claude-") && model.ends_with("-thinking"))
+}
+
+/// 按目标模型（上游模型 ID）调整 Gemini 请求中已有的 thinkingConfig
+///
+/// 模型不支持思考时移除，预算超过模型上限时截断；用于换用回退模型前的请求。
+pub fn fit(gemini: &mut serde_json::Value, model: &str) {
+    let Some(config) = gemini
+        .get_mut("generationConfig")
+        .and_then(|c| c.as_object_mut())
+    else {
+        return;
+    };
+    if !supports_thinking(model) {
+        if config.remove("thinkingConfig").is_some() {
+            debug!("模型 {} 不支持思考，移除 thinkingConfig", model);
+        }
+        return;
+    }
+    let max = max_budget(model);
+    if let Some(budget) = config
+        .get_mut("thinkingConfig")
+        .and_then(|t| t.get_mut("thinkingBudget"))
+        .filter(|b| b.as_u64().is_some_and(|b| b > u64::from(max)))
+    {
+        debug!(
+            "thinkingBudget {} 超过模型 {} 的上限，截断为 {}",
+            budget, model, max
+        );
+        *budget = max.into();
+    }
+}
+
+/// 计算 thinkingConfig，不需要时返回 None
+///
+/// include_thoughts 仅对未明确请求的情况生效；明确请求思考时总是返回思考内容。
+pub fn thinking_config(
+    model: &str,
+    request: ThinkingRequest,
+    settings: &ReasoningSettings,
+    include_thoughts: bool,
+) -> Result<Option<serde_json::Value>, ConvertError> {
+    let supported = supports_thinking(model) && settings.enable_thinking_models;
+    let (budget, include) = match request {
+        ThinkingRequest::Disabled => return Ok(None),
+        ThinkingRequest::Default if !supported => return Ok(None),
+        ThinkingRequest::Default => (effort_budget(settings.default_effort), include_thoughts),
+        ThinkingRequest::Effort(_) | ThinkingRequest::Budget(_) if !supported => {
+            return Err(ConvertError::Unsupported(
+                if settings.enable_thinking_models {
+                    format!("模型 {} 不支持思考", model)
+                } else {
+                    "思考模型已在配置中禁用".to_string()
+                },
+            ))
+        }
+        ThinkingRequest::Effort(effort) => (effort_budget(effort), true),
+        ThinkingRequest::Budget(budget) => {
+            let max = max_budget(model);
+            if budget > max {
+                debug!(
+                    "budget_tokens {} 超过模型 {} 的上限，截断为 {}",
+                    budget, model, max
+                );
+            }
+            (budget.min(max), true)
+        }
+    };
+    Ok(Some(json!({
+        "thinkingBudget": budget,
+        "includeThoughts": include
+    })))
+}
+
+/// 把 thinkingConfig 写入 Gemini 请求的 generationConfig
+pub fn apply(
+    gemini: &mut serde_json::Value,
+    model: &str,
+    request: ThinkingRequest,
+    settings: &ReasoningSettings,
+    include_thoughts: bool,
+) -> Result<(), ConvertError> {
+    if let Some(config) = thinking_config(model, request, settings, include_thoughts)? {
+        gemini["generationConfig"]["thinkingConfig"] = config;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thinking_config() {
+        let settings = ReasoningSettings::default();
+        assert_eq!(
+            thinking_config(
+                "gemini-2.5-pro",
+                ThinkingRequest::Effort(ReasoningEffort::High),
+                &settings,
+                false
+            )
+            .unwrap(),
+            Some(json!({"thinkingBudget": 24576, "includeThoughts": true}))
+        );
+        assert_eq!(
+            thinking_config(
+                "gemini-2.5-flash",
+                ThinkingRequest::Default,
+                &settings,
+                false
+            )
+            .unwrap(),
+            Some(json!({"thinkingBudget": 8192, "includeThoughts": false}))
+        );
+        assert_eq!(
+            thinking_config(
+                "claude-sonnet-4-5",
+                ThinkingRequest::Default,
+                &settings,
+                true
+            )
+            .unwrap(),
+            None
+        );
+        assert!(thinking_config(
+            "claude-sonnet-4-5",
+            ThinkingRequest::Budget(2048),
+            &settings,
+            true
+        )
+        .is_err());
+
+        // budget_tokens 截断到模型上限
+        let budget = |model: &str, budget: u32| {
+            thinking_config(model, ThinkingRequest::Budget(budget), &settings, false)
+                .unwrap()
+                .unwrap()["thinkingBudget"]
+                .clone()
+        };
+        assert_eq!(budget("gemini-2.5-flash", 60_000), 24576);
+        assert_eq!(budget("gemini-2.5-pro", 60_000), 32768);
+        assert_eq!(budget("gemini-3-pro-preview", 40_000), 32768);
+        assert_eq!(budget("gemini-2.5-flash", 16_000), 16_000);
+
+        let disabled = ReasoningSettings {
+            enable_thinking_models: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            thinking_config("gemini-2.5-pro", ThinkingRequest::Default, &disabled, true).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_requests() {
+        assert_eq!(
+            ThinkingRequest::from_anthropic(
+                Some(&json!({"type": "enabled", "budget_tokens": 4096})),
+                Some(8192)
+            )
+            .unwrap(),
+            ThinkingRequest::Budget(4096)
+        );
+        assert!(ThinkingRequest::from_anthropic(
+            Some(&json!({"type": "enabled", "budget_tokens": 4096})),
+            Some(2048)
+        )
+        .is_err());
+        assert!(ThinkingRequest::from_effort(Some("extreme")).is_err());
+    }
+}
@@ -0,0 +1,794 @@
+//! 本地 HTTP 代理：以 Anthropic / OpenAI / Gemini 接口对外提供凭证池
+//!
+//! - `POST /v1/messages`
+//! - `POST /v1/chat/completions`
+//! - `POST /v1beta/models/{model}:generateContent`
+//! - `POST /v1beta/models/{model}:streamGenerateContent[?alt=sse]`
+
+use crate::api::error::{retry_after_secs, UpstreamError};
+use crate::cancel::{self, CancelToken};
+use crate::config;
+use crate::dispatch::{self, Attempt};
+use crate::fallback::ModelFallback;
+use crate::protocol::structured::StructuredOutputError;
+use crate::protocol::{self, anthropic, anthropic_stream, openai};
+use anyhow::Result;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderValue, CONTENT_LENGTH, RETRY_AFTER};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::json;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+/// 客户端使用的接口协议，决定错误响应的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    Anthropic,
+    OpenAi,
+    Gemini,
+}
+
+impl Protocol {
+    fn error_response(&self, status: StatusCode, message: &str) -> Response<Body> {
+        let kind = match status {
+            StatusCode::BAD_REQUEST => "invalid_request_error",
+            StatusCode::NOT_FOUND => "not_found_error",
+            StatusCode::PAYLOAD_TOO_LARGE => "request_too_large",
+            _ => "api_error",
+        };
+        let body = match self {
+            Protocol::Anthropic => json!({
+                "type": "error",
+                "error": {"type": kind, "message": message}
+            }),
+            Protocol::OpenAi => json!({
+                "error": {"message": message, "type": kind, "param": null, "code": null}
+            }),
+            Protocol::Gemini => json!({
+                "error": {
+                    "code": status.as_u16(),
+                    "message": message,
+                    "status": match status {
+                        StatusCode::BAD_REQUEST | StatusCode::PAYLOAD_TOO_LARGE => {
+                            "INVALID_ARGUMENT"
+                        }
+                        StatusCode::NOT_FOUND => "NOT_FOUND",
+                        _ => "INTERNAL",
+                    }
+                }
+            }),
+        };
+        json_response(status, &body)
+    }
+
+    /// 请求转换失败：内容过大时返回 413，未知模型返回 404，其余返回 400
+    fn convert_error(&self, e: &protocol::ConvertError) -> Response<Body> {
+        let status = match e {
+            protocol::ConvertError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            protocol::ConvertError::UnknownModel(_) => StatusCode::NOT_FOUND,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        self.error_response(status, &e.to_string())
+    }
+
+    /// 上游调用失败：请求本身不合法时按 convert_error 处理，Google RPC 错误按 rpc_error
+    /// 处理，其余返回 502
+    fn upstream_error(&self, e: &anyhow::Error) -> Response<Body> {
+        if let Some(convert) = e.downcast_ref::<protocol::ConvertError>() {
+            return self.convert_error(convert);
+        }
+        if let Some(upstream) = e.downcast_ref::<UpstreamError>() {
+            return self.rpc_error(upstream);
+        }
+        self.error_response(StatusCode::BAD_GATEWAY, &e.to_string())
+    }
+
+    /// Google RPC 错误转换为客户端协议的错误格式与状态码，并带上 Retry-After
+    fn rpc_error(&self, e: &UpstreamError) -> Response<Body> {
+        let message = if e.message.is_empty() {
+            e.to_string()
+        } else {
+            e.message.clone()
+        };
+        let (status, body) = match self {
+            Protocol::Anthropic => (
+                e.client_status(),
+                json!({
+                    "type": "error",
+                    "error": {"type": e.anthropic_type(), "message": message}
+                }),
+            ),
+            Protocol::OpenAi => {
+                let (kind, code) = e.openai_type();
+                (
+                    e.client_status(),
+                    json!({
+                        "error": {"message": message, "type": kind, "param": null, "code": code}
+                    }),
+                )
+            }
+            // Gemini 客户端直接使用上游的错误格式
+            Protocol::Gemini => (
+                e.http_status,
+                json!({
+                    "error": {
+                        "code": e.http_status,
+                        "message": message,
+                        "status": e.status.as_str(),
+                        "details": e.details
+                    }
+                }),
+            ),
+        };
+        let status = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY);
+        let mut response = json_response(status, &body);
+        if let Some(delay) = e.retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after_secs(delay)));
+        }
+        response
+    }
+
+    /// 结构化输出校验失败（strict 模式）
+    fn structured_output_error(&self, e: &StructuredOutputError) -> Response<Body> {
+        self.error_response(StatusCode::BAD_GATEWAY, &e.to_string())
+    }
+}
+
+fn json_response(status: StatusCode, body: &serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// 请求头：值为 off / false / 0 时关闭本次请求的模型回退
+const MODEL_FALLBACK: &str = "x-model-fallback";
+/// 响应头：发生模型回退时为客户端请求的模型
+const MODEL_FALLBACK_FROM: &str = "x-model-fallback-from";
+/// 响应头：本次请求的上游尝试次数（含切换凭证与回退模型）
+const UPSTREAM_ATTEMPTS: &str = "x-upstream-attempts";
+
+fn fallback_disabled(request: &Request<Body>) -> bool {
+    request
+        .headers()
+        .get(MODEL_FALLBACK)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            matches!(
+                v.trim().to_ascii_lowercase().as_str(),
+                "off" | "false" | "0"
+            )
+        })
+}
+
+/// 附加上游尝试次数，发生模型回退时附加回退响应头
+fn with_dispatch_headers(
+    mut response: Response<Body>,
+    fallback: Option<&ModelFallback>,
+    attempts: &[Attempt],
+) -> Response<Body> {
+    let headers = response.headers_mut();
+    headers.insert(UPSTREAM_ATTEMPTS, HeaderValue::from(attempts.len()));
+    if let Some(value) = fallback.and_then(|f| HeaderValue::from_str(&f.requested_model).ok()) {
+        headers.insert(MODEL_FALLBACK_FROM, value);
+    }
+    response
+}
+
+/// 请求体中文本与 JSON 结构的余量
+const BODY_HEADROOM: u64 = 8 * 1024 * 1024;
+
+/// 请求体上限：内联数据总量按 base64 膨胀后再加上余量
+fn max_body_bytes() -> usize {
+    let media = config::current().settings.media.max_total_bytes;
+    usize::try_from(media.saturating_mul(4) / 3 + BODY_HEADROOM).unwrap_or(usize::MAX)
+}
+
+/// 读取请求体，超过 limit 时返回 413
+async fn read_body(
+    request: Request<Body>,
+    protocol: Protocol,
+    limit: usize,
+) -> Result<Vec<u8>, Response<Body>> {
+    let too_large = || {
+        protocol.error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            &format!("Request body exceeds {} bytes", limit),
+        )
+    };
+    let declared = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if declared.is_some_and(|len| len > limit) {
+        return Err(too_large());
+    }
+
+    let mut body = request.into_body();
+    let mut bytes = Vec::with_capacity(declared.unwrap_or_default());
+    while let Some(chunk) = body.data().await {
+        let chunk =
+            chunk.map_err(|e| protocol.error_response(StatusCode::BAD_REQUEST, &e.to_string()))?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// 读取并解析 JSON 请求体
+async fn read_json<T: serde::de::DeserializeOwned>(
+    request: Request<Body>,
+    protocol: Protocol,
+) -> Result<T, Response<Body>> {
+    let bytes = read_body(request, protocol, max_body_bytes()).await?;
+    serde_json::from_slice(&bytes).map_err(|e| {
+        protocol.error_response(
+            StatusCode::BAD_REQUEST,
+            &format!("Invalid request body: {}", e),
+        )
+    })
+}
+
+/// 启动流式上游请求并返回 SSE 响应
+///
+/// 等到第一段数据产生后才发送响应头，因此在此之前的失败仍能以普通错误响应返回。
+/// 客户端断开（写入失败或响应被丢弃）时通过 CancelToken 取消上游请求。
+async fn sse_response<P, F>(protocol: Protocol, producer: P) -> Response<Body>
+where
+    P: FnOnce(mpsc::UnboundedSender<String>, CancelToken) -> F,
+    F: Future<Output = Result<()>> + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let (token, canceller) = cancel::token();
+    let task = tokio::spawn(producer(tx, token));
+
+    let first = match rx.recv().await {
+        Some(first) => first,
+        None => {
+            let error = match task.await {
+                Ok(Err(e)) => e,
+                Ok(Ok(())) => anyhow::anyhow!("上游没有返回任何数据"),
+                Err(e) => e.into(),
+            };
+            return protocol.upstream_error(&error);
+        }
+    };
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        if sender.send_data(first.into()).await.is_err() {
+            canceller.cancel();
+            return;
+        }
+        while let Some(data) = rx.recv().await {
+            if sender.send_data(data.into()).await.is_err() {
+                warn!("客户端已断开流式连接，取消上游请求");
+                canceller.cancel();
+                break;
+            }
+        }
+    });
+
+    Response::builder()
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .body(body)
+        .unwrap()
+}
+
+/// POST /v1/messages
+async fn messages(request: Request<Body>) -> Response<Body> {
+    let protocol = Protocol::Anthropic;
+    let header_disabled = fallback_disabled(&request);
+    let request: anthropic::MessagesRequest = match read_json(request, protocol).await {
+        Ok(r) => r,
+        Err(response) => return response,
+    };
+    let gemini_request = match anthropic::to_gemini(&request) {
+        Ok(r) => r,
+        Err(e) => return protocol.convert_error(&e),
+    };
+    let model = request.model.clone();
+    let fallback = !header_disabled && request.model_fallback.unwrap_or(true);
+
+    if !request.stream {
+        return match dispatch::generate_content(&model, gemini_request, None, fallback).await {
+            Ok(generated) => with_dispatch_headers(
+                json_response(
+                    StatusCode::OK,
+                    &json!(anthropic::from_gemini(&generated.response, &model)),
+                ),
+                generated.fallback.as_ref(),
+                &generated.attempts,
+            ),
+            Err(e) => protocol.upstream_error(&e),
+        };
+    }
+
+    sse_response(protocol, move |tx, token| async move {
+        let mut converter = anthropic_stream::StreamConverter::new(&model);
+        let result = dispatch::stream_generate_content(
+            &model,
+            gemini_request,
+            None,
+            Some(token),
+            fallback,
+            |chunk| {
+                for event in converter.push(&chunk) {
+                    let _ = tx.send(event.to_sse());
+                }
+            },
+        )
+        .await;
+        match result {
+            Ok(_) => {
+                for event in converter.finish() {
+                    let _ = tx.send(event.to_sse());
+                }
+                Ok(())
+            }
+            Err(e) if converter.message().is_some() => {
+                let kind = e
+                    .downcast_ref::<UpstreamError>()
+                    .map_or("api_error", |u| u.anthropic_type());
+                let event = anthropic_stream::StreamEvent::error(kind, &e.to_string());
+                let _ = tx.send(event.to_sse());
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    })
+    .await
+}
+
+/// POST /v1/chat/completions
+async fn chat_completions(request: Request<Body>) -> Response<Body> {
+    let protocol = Protocol::OpenAi;
+    let header_disabled = fallback_disabled(&request);
+    let request: openai::ChatCompletionRequest = match read_json(request, protocol).await {
+        Ok(r) => r,
+        Err(response) => return response,
+    };
+    let gemini_request = match openai::to_gemini(&request) {
+        Ok(r) => r,
+        Err(e) => return protocol.convert_error(&e),
+    };
+    let model = request.model.clone();
+    let fallback = !header_disabled && request.model_fallback.unwrap_or(true);
+
+    if !request.stream {
+        return match dispatch::generate_content(&model, gemini_request, None, fallback).await {
+            Ok(generated) => {
+                let completion = openai::from_gemini(&generated.response, &model);
+                match openai::check_structured_output(&request, &completion) {
+                    Ok(()) => with_dispatch_headers(
+                        json_response(StatusCode::OK, &json!(completion)),
+                        generated.fallback.as_ref(),
+                        &generated.attempts,
+                    ),
+                    Err(e) => protocol.structured_output_error(&e),
+                }
+            }
+            Err(e) => protocol.upstream_error(&e),
+        };
+    }
+
+    let include_usage = request
+        .stream_options
+        .as_ref()
+        .map(|o| o.include_usage)
+        .unwrap_or(false);
+    sse_response(protocol, move |tx, token| async move {
+        let mut converter = openai::ChunkConverter::new(&model, include_usage);
+        let send = |chunk: serde_json::Value| {
+            let _ = tx.send(format!("data: {}\n\n", chunk));
+        };
+        let result = dispatch::stream_generate_content(
+            &model,
+            gemini_request,
+            None,
+            Some(token),
+            fallback,
+            |chunk| converter.push(&chunk).into_iter().for_each(send),
+        )
+        .await;
+        match result {
+            Ok(_) => {
+                converter.finish().into_iter().for_each(send);
+                // 内容已经发出，strict 模式下以 error 块结束
+                if let Some(completion) = converter.completion() {
+                    if let Err(e) = openai::check_structured_output(&request, completion) {
+                        send(json!({"error": {"message": e.to_string(), "type": "api_error"}}));
+                    }
+                }
+                let _ = tx.send("data: [DONE]\n\n".to_string());
+                Ok(())
+            }
+            Err(e) if converter.completion().is_some() => {
+                let (kind, code) = e
+                    .downcast_ref::<UpstreamError>()
+                    .map_or(("api_error", None), |u| u.openai_type());
+                send(json!({"error": {"message": e.to_string(), "type": kind, "code": code}}));
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    })
+    .await
+}
+
+/// POST /v1beta/models/{model}:{method}
+async fn gemini(request: Request<Body>, target: &str) -> Response<Body> {
+    let protocol = Protocol::Gemini;
+    let Some((model, method)) = target.rsplit_once(':') else {
+        return protocol.error_response(StatusCode::NOT_FOUND, "Unknown endpoint");
+    };
+    let model = model.to_string();
+    let fallback = !fallback_disabled(&request);
+    let sse = request
+        .uri()
+        .query()
+        .is_some_and(|q| q.split('&').any(|kv| kv == "alt=sse"));
+
+    match method {
+        "generateContent" => {
+            let body: serde_json::Value = match read_json(request, protocol).await {
+                Ok(b) => b,
+                Err(response) => return response,
+            };
+            match dispatch::generate_content(&model, body, None, fallback).await {
+                Ok(generated) => with_dispatch_headers(
+                    json_response(StatusCode::OK, &generated.response),
+                    generated.fallback.as_ref(),
+                    &generated.attempts,
+                ),
+                Err(e) => protocol.upstream_error(&e),
+            }
+        }
+        "streamGenerateContent" if sse => {
+            let body: serde_json::Value = match read_json(request, protocol).await {
+                Ok(b) => b,
+                Err(response) => return response,
+            };
+            sse_response(protocol, move |tx, token| async move {
+                let mut sent = false;
+                let result = dispatch::stream_generate_content(
+                    &model,
+                    body,
+                    None,
+                    Some(token),
+                    fallback,
+                    |chunk| {
+                        sent = true;
+                        let _ = tx.send(format!("data: {}\n\n", chunk));
+                    },
+                )
+                .await;
+                match result {
+                    Err(e) if sent => {
+                        let error = json!({"error": {"code": 500, "message": e.to_string()}});
+                        let _ = tx.send(format!("data: {}\n\n", error));
+                        Ok(())
+                    }
+                    other => other.map(|_| ()),
+                }
+            })
+            .await
+        }
+        "streamGenerateContent" => {
+            // 未指定 alt=sse 时与 Gemini API 一致，返回分块组成的 JSON 数组
+            let body: serde_json::Value = match read_json(request, protocol).await {
+                Ok(b) => b,
+                Err(response) => return response,
+            };
+            let mut chunks = Vec::new();
+            match dispatch::stream_generate_content(&model, body, None, None, fallback, |chunk| {
+                chunks.push(chunk)
+            })
+            .await
+            {
+                Ok(streamed) => with_dispatch_headers(
+                    json_response(StatusCode::OK, &json!(chunks)),
+                    streamed.fallback.as_ref(),
+                    &streamed.attempts,
+                ),
+                Err(e) => protocol.upstream_error(&e),
+            }
+        }
+        _ => protocol.error_response(
+            StatusCode::NOT_FOUND,
+            &format!("Unknown method: {}", method),
+        ),
+    }
+}
+
+async fn route(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    info!("{} {}", method, path);
+
+    let response = match (&method, path.as_str()) {
+        (&Method::GET, "/health") => json_response(StatusCode::OK, &json!({"status": "healthy"})),
+        (&Method::POST, "/v1/messages") => messages(request).await,
+        (&Method::POST, "/v1/chat/completions") => chat_completions(request).await,
+        (&Method::POST, p) if p.starts_with("/v1beta/models/") => {
+            gemini(request, &p["/v1beta/models/".len()..]).await
+        }
+        (_, p) if p.starts_with("/v1beta/") => {
+            Protocol::Gemini.error_response(StatusCode::NOT_FOUND, "Unknown endpoint")
+        }
+        (_, "/v1/chat/completions") => {
+            Protocol::OpenAi.error_response(StatusCode::NOT_FOUND, "Unknown endpoint")
+        }
+        _ => Protocol::Anthropic.error_response(StatusCode::NOT_FOUND, "Unknown endpoint"),
+    };
+    Ok(response)
+}
+
+/// 启动 HTTP 代理，直到收到 Ctrl-C
+pub async fn run(listen: SocketAddr) -> Result<()> {
+    if !listen.ip().is_loopback() {
+        warn!("代理监听在非回环地址 {}，请确认网络访问受控", listen);
+    }
+
+    let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(route)) });
+    let server = Server::try_bind(&listen)?.serve(make_service);
+    info!("HTTP 代理已启动: http://{}", listen);
+
+    if let Err(e) = server
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+    {
+        error!("HTTP 代理异常退出: {}", e);
+        return Err(e.into());
+    }
+    info!("HTTP 代理已停止");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn body_json(response: Response<Body>) -> serde_json::Value {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_route_errors_use_client_protocol() {
+        let request = Request::post("/v1/messages")
+            .body(Body::from("{not json"))
+            .unwrap();
+        let response = route(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = body_json(response).await;
+        assert_eq!(body["type"], "error");
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+
+        let request = Request::post("/v1beta/models/gemini-2.5-pro:countTokens")
+            .body(Body::from("{}"))
+            .unwrap();
+        let response = route(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body_json(response).await["error"]["status"], "NOT_FOUND");
+
+        let request = Request::post("/v1/chat/completions")
+            .body(Body::from(r#"{"model": "gemini-2.5-pro", "messages": []}"#))
+            .unwrap();
+        let response = route(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(body_json(response).await["error"]["message"].is_string());
+    }
+
+    async fn get(uri: &str) -> Response<Body> {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        route(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_routing() {
+        let response = get("/health").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await["status"], "healthy");
+
+        // 未知路径按路径前缀选择错误格式
+        let response = get("/v1/messages").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            body_json(response).await["error"]["type"],
+            "not_found_error"
+        );
+        let body = body_json(get("/v1/chat/completions").await).await;
+        assert_eq!(body["error"]["type"], "not_found_error");
+        assert!(body.get("type").is_none());
+        let body = body_json(get("/v1beta/models").await).await;
+        assert_eq!(body["error"]["status"], "NOT_FOUND");
+
+        let request = Request::post("/v1beta/models/gemini-2.5-pro")
+            .body(Body::from("{}"))
+            .unwrap();
+        let response = route(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // alt=sse 与普通流式都进入 Gemini 处理，先解析请求体
+        for uri in [
+            "/v1beta/models/gemini-2.5-pro:streamGenerateContent?alt=sse",
+            "/v1beta/models/gemini-2.5-pro:streamGenerateContent",
+            "/v1beta/models/gemini-2.5-pro:generateContent",
+        ] {
+            let request = Request::post(uri).body(Body::from("[")).unwrap();
+            let response = route(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            let body = body_json(response).await;
+            assert_eq!(body["error"]["status"], "INVALID_ARGUMENT", "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_error_statuses() {
+        let request = Request::post("/v1/messages")
+            .body(Body::from(
+                json!({
+                    "model": "gpt-4o",
+                    "max_tokens": 16,
+                    "messages": [{"role": "user", "content": "hi"}]
+                })
+                .to_string(),
+            ))
+            .unwrap();
+        let response = route(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            body_json(response).await["error"]["type"],
+            "not_found_error"
+        );
+
+        // 声明的长度超过上限时不读取请求体
+        let request = Request::post("/v1/chat/completions")
+            .header(CONTENT_LENGTH, (max_body_bytes() + 1).to_string())
+            .body(Body::empty())
+            .unwrap();
+        let response = route(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            body_json(response).await["error"]["type"],
+            "request_too_large"
+        );
+
+        // 未声明长度（分块传输）时按实际读取的字节数判断
+        let request = Request::post("/").body(Body::from("0123456789")).unwrap();
+        let response = read_body(request, Protocol::Gemini, 8).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let request = Request::post("/").body(Body::from("01234567")).unwrap();
+        assert_eq!(
+            read_body(request, Protocol::Gemini, 8).await.unwrap(),
+            b"01234567"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sse_response_streams_and_cancels_on_disconnect() {
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+        let response = sse_response(Protocol::OpenAi, move |tx, mut token| async move {
+            let _ = tx.send("data: first\n\n".to_string());
+            let ticker = async {
+                loop {
+                    let _ = tx.send("data: more\n\n".to_string());
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+            };
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = ticker => {}
+            }
+            let _ = done_tx.send(());
+            Ok(())
+        })
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["Content-Type"], "text/event-stream");
+
+        let mut body = response.into_body();
+        let first = body.data().await.unwrap().unwrap();
+        assert_eq!(&first[..], b"data: first\n\n");
+        drop(body);
+        tokio::time::timeout(Duration::from_secs(5), done_rx)
+            .await
+            .expect("客户端断开后应取消上游请求")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sse_response_error_before_first_chunk() {
+        let body = json!({"error": {
+            "code": 429,
+            "message": "Quota exceeded",
+            "status": "RESOURCE_EXHAUSTED"
+        }});
+        let error =
+            UpstreamError::from_response("streamGenerateContent", 429, &body.to_string(), None);
+        let response = sse_response(Protocol::Anthropic, move |_tx, _token| async move {
+            Err(error.into())
+        })
+        .await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            body_json(response).await["error"]["type"],
+            "rate_limit_error"
+        );
+    }
+
+    #[test]
+    fn test_dispatch_headers() {
+        let attempt = |status: Option<&str>| Attempt {
+            credential_id: "c".to_string(),
+            model: "gemini-2.5-pro".to_string(),
+            status: status.map(String::from),
+            error: None,
+        };
+        let attempts = [attempt(Some("RESOURCE_EXHAUSTED")), attempt(None)];
+        let fallback = ModelFallback {
+            requested_model: "gemini-3-pro-preview".to_string(),
+            model: "gemini-2.5-pro".to_string(),
+        };
+        let response = with_dispatch_headers(
+            json_response(StatusCode::OK, &json!({})),
+            Some(&fallback),
+            &attempts,
+        );
+        assert_eq!(response.headers()[UPSTREAM_ATTEMPTS], "2");
+        assert_eq!(
+            response.headers()[MODEL_FALLBACK_FROM],
+            "gemini-3-pro-preview"
+        );
+
+        let response = with_dispatch_headers(
+            json_response(StatusCode::OK, &json!({})),
+            None,
+            &attempts[1..],
+        );
+        assert_eq!(response.headers()[UPSTREAM_ATTEMPTS], "1");
+        assert!(!response.headers().contains_key(MODEL_FALLBACK_FROM));
+    }
+
+    #[tokio::test]
+    async fn test_rpc_error_rendering() {
+        let body = r#"{"error": {"code": 429, "message": "Quota exceeded", "status": "RESOURCE_EXHAUSTED",
+            "details": [{"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "30s"}]}}"#;
+        let error: anyhow::Error =
+            UpstreamError::from_response("generateContent", 429, body, None).into();
+
+        let response = Protocol::Anthropic.upstream_error(&error);
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "30");
+        let body = body_json(response).await;
+        assert_eq!(body["error"]["type"], "rate_limit_error");
+        assert_eq!(body["error"]["message"], "Quota exceeded");
+
+        let response = Protocol::OpenAi.upstream_error(&error);
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            body_json(response).await["error"]["code"],
+            "rate_limit_exceeded"
+        );
+
+        let response = Protocol::Gemini.upstream_error(&error);
+        let body = body_json(response).await;
+        assert_eq!(body["error"]["status"], "RESOURCE_EXHAUSTED");
+        assert_eq!(body["error"]["details"][0]["retryDelay"], "30s");
+    }
+}
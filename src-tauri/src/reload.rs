@@ -0,0 +1,185 @@
+//! 配置文件与凭证目录热加载
+//!
+//! 按 `settings.hot_reload.poll_interval_ms` 轮询文件的修改时间和大小，
+//! 发现变化后重新加载：配置整体替换，进行中的请求持有旧的快照；凭证按文件指纹合并。
+
+use crate::pool::{file_stamp, FileStamp, PoolDiff};
+use crate::{config, notification, pool};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{error, info};
+
+/// 凭证目录中所有 JSON 文件的指纹
+fn store_stamps(dir: &Path) -> BTreeMap<PathBuf, FileStamp> {
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return BTreeMap::new();
+    };
+    read_dir
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("json"))
+        .filter_map(|p| file_stamp(&p).map(|s| (p, s)))
+        .collect()
+}
+
+/// 重新加载配置并通知宿主
+fn reload_config(path: &Path) {
+    match config::reload() {
+        Ok(cfg) => {
+            info!("配置已重新加载: {}", path.display());
+            notification::send(
+                "config_reloaded",
+                json!({
+                    "path": path,
+                    "success": true,
+                    "environment": cfg.settings.api.environment,
+                }),
+            );
+        }
+        Err(e) => {
+            error!("重新加载配置失败，继续使用原配置: {}", e);
+            notification::send(
+                "config_reloaded",
+                json!({
+                    "path": path,
+                    "success": false,
+                    "error": e.to_string(),
+                }),
+            );
+        }
+    }
+}
+
+/// 凭证目录需要的处理
+#[derive(Debug, PartialEq)]
+enum StoreChange {
+    /// 目录内容或目录本身变化，重新加载
+    Reload(PathBuf),
+    /// 不再配置存储目录，移除来自文件的凭证
+    Unload,
+}
+
+fn store_change(
+    old_dir: Option<&Path>,
+    old_stamps: &BTreeMap<PathBuf, FileStamp>,
+    dir: Option<&Path>,
+    stamps: &BTreeMap<PathBuf, FileStamp>,
+) -> Option<StoreChange> {
+    match dir {
+        Some(d) if old_dir != dir || old_stamps != stamps => Some(StoreChange::Reload(d.into())),
+        None if old_dir.is_some() => Some(StoreChange::Unload),
+        _ => None,
+    }
+}
+
+fn notify_store_diff(dir: Option<&Path>, diff: PoolDiff) {
+    if diff.is_empty() {
+        return;
+    }
+    notification::send(
+        "credentials_reloaded",
+        json!({
+            "dir": dir,
+            "success": true,
+            "added": diff.added,
+            "removed": diff.removed,
+            "updated": diff.updated,
+        }),
+    );
+}
+
+/// 重新加载凭证目录并通知宿主
+fn reload_store(dir: &Path) {
+    match pool::reload_from_dir(dir) {
+        Ok(diff) => notify_store_diff(Some(dir), diff),
+        Err(e) => {
+            error!("重新加载凭证目录失败: {}", e);
+            notification::send(
+                "credentials_reloaded",
+                json!({
+                    "dir": dir,
+                    "success": false,
+                    "error": e.to_string(),
+                }),
+            );
+        }
+    }
+}
+
+/// 启动后台热加载任务
+pub fn spawn() -> Option<tokio::task::JoinHandle<()>> {
+    if !config::current().settings.hot_reload.enabled {
+        info!("热加载已关闭");
+        return None;
+    }
+    Some(tokio::spawn(run()))
+}
+
+async fn run() {
+    let config_path = config::path();
+    let mut config_stamp = config_path.as_deref().and_then(file_stamp);
+    let mut store_dir = config::current().settings.store.dir.clone();
+    let mut stamps = store_dir.as_deref().map(store_stamps).unwrap_or_default();
+
+    loop {
+        let settings = config::current().settings.hot_reload.clone();
+        if !settings.enabled {
+            info!("热加载已关闭，停止监视");
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(settings.poll_interval_ms)).await;
+
+        if let Some(path) = &config_path {
+            let current = file_stamp(path);
+            if current != config_stamp {
+                config_stamp = current;
+                reload_config(path);
+            }
+        }
+
+        // 存储目录可能随配置一起变化
+        let dir = config::current().settings.store.dir.clone();
+        let current = dir.as_deref().map(store_stamps).unwrap_or_default();
+        match store_change(store_dir.as_deref(), &stamps, dir.as_deref(), &current) {
+            Some(StoreChange::Reload(d)) => reload_store(&d),
+            Some(StoreChange::Unload) => {
+                info!("已取消凭证存储目录，移除来自文件的凭证");
+                notify_store_diff(None, pool::unload_store());
+            }
+            None => {}
+        }
+        store_dir = dir;
+        stamps = current;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_store_change() {
+        let dir = PathBuf::from("/store");
+        let other = PathBuf::from("/other");
+        let empty = BTreeMap::new();
+        let changed: BTreeMap<_, _> = [(dir.join("a.json"), (SystemTime::UNIX_EPOCH, 1))].into();
+
+        assert_eq!(store_change(Some(&dir), &empty, Some(&dir), &empty), None);
+        assert_eq!(
+            store_change(Some(&dir), &empty, Some(&dir), &changed),
+            Some(StoreChange::Reload(dir.clone()))
+        );
+        assert_eq!(
+            store_change(Some(&dir), &empty, Some(&other), &empty),
+            Some(StoreChange::Reload(other.clone()))
+        );
+        assert_eq!(
+            store_change(Some(&dir), &changed, None, &empty),
+            Some(StoreChange::Unload)
+        );
+        assert_eq!(store_change(None, &empty, None, &empty), None);
+    }
+}
@@ -0,0 +1,257 @@
+//! 宿主会话：协议版本与能力协商
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::RwLock;
+use thiserror::Error;
+
+/// 当前实现的协议版本
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 1 };
+/// 仍兼容的最低协议版本
+pub const MIN_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 0 };
+/// 宿主未声明版本时假定的旧版协议
+pub const LEGACY_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 0 };
+
+/// 协议版本（major.minor）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProtocolVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ProtocolVersion {
+    /// 解析 "1" / "1.1" 形式的版本号
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.trim().split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = match parts.next() {
+            Some(m) => m.parse().ok()?,
+            None => 0,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self { major, minor })
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl Serialize for ProtocolVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// 可协商的特性
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    /// Provider 主动向宿主发送 JSON-RPC 通知
+    Notifications,
+    /// JSON-RPC 批量请求
+    Batch,
+    /// 流式响应（以通知形式增量推送）
+    Streaming,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 3] = [Feature::Notifications, Feature::Batch, Feature::Streaming];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Feature::Notifications => "notifications",
+            Feature::Batch => "batch",
+            Feature::Streaming => "streaming",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.as_str() == s)
+    }
+}
+
+/// initialize 请求参数
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct InitializeParams {
+    /// 宿主实现的协议版本
+    #[serde(default)]
+    pub protocol_version: Option<String>,
+    /// 宿主支持的特性
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// 宿主要求必须启用的特性
+    #[serde(default)]
+    pub required_features: Vec<String>,
+    /// 配置覆盖（与 config.json 结构相同）
+    #[serde(default)]
+    pub config: Option<serde_json::Value>,
+}
+
+/// 协商结果
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Session {
+    pub protocol_version: ProtocolVersion,
+    pub features: BTreeSet<Feature>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            protocol_version: LEGACY_PROTOCOL_VERSION,
+            features: BTreeSet::new(),
+        }
+    }
+}
+
+impl Session {
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+/// 协商失败
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum NegotiationError {
+    #[error("Invalid protocol_version: {0:?}")]
+    InvalidVersion(String),
+    #[error(
+        "Incompatible protocol version {host}: provider supports {}..={}",
+        MIN_PROTOCOL_VERSION,
+        PROTOCOL_VERSION
+    )]
+    IncompatibleVersion { host: ProtocolVersion },
+    #[error("Unsupported required features: {}", .0.join(", "))]
+    UnsupportedFeatures(Vec<String>),
+}
+
+/// 根据宿主参数协商协议版本和特性集合
+pub fn negotiate(params: &InitializeParams) -> Result<Session, NegotiationError> {
+    let host_version = match &params.protocol_version {
+        Some(v) => {
+            ProtocolVersion::parse(v).ok_or_else(|| NegotiationError::InvalidVersion(v.clone()))?
+        }
+        None => LEGACY_PROTOCOL_VERSION,
+    };
+
+    if host_version.major != PROTOCOL_VERSION.major || host_version < MIN_PROTOCOL_VERSION {
+        return Err(NegotiationError::IncompatibleVersion { host: host_version });
+    }
+
+    let unsupported: Vec<String> = params
+        .required_features
+        .iter()
+        .filter(|f| Feature::parse(f).is_none())
+        .cloned()
+        .collect();
+    if !unsupported.is_empty() {
+        return Err(NegotiationError::UnsupportedFeatures(unsupported));
+    }
+
+    // 旧版协议不支持任何可选特性
+    let features = if host_version == LEGACY_PROTOCOL_VERSION {
+        if !params.required_features.is_empty() {
+            return Err(NegotiationError::UnsupportedFeatures(
+                params.required_features.clone(),
+            ));
+        }
+        BTreeSet::new()
+    } else {
+        params
+            .features
+            .iter()
+            .chain(params.required_features.iter())
+            .filter_map(|f| Feature::parse(f))
+            .collect()
+    };
+
+    Ok(Session {
+        protocol_version: host_version.min(PROTOCOL_VERSION),
+        features,
+    })
+}
+
+static SESSION: RwLock<Option<Session>> = RwLock::new(None);
+
+/// 保存协商结果
+pub fn set_current(session: Session) {
+    *SESSION.write().unwrap() = Some(session);
+}
+
+/// 当前会话（未初始化时为旧版协议、无特性）
+pub fn current() -> Session {
+    SESSION.read().unwrap().clone().unwrap_or_default()
+}
+
+/// 当前会话是否启用了某特性
+pub fn supports(feature: Feature) -> bool {
+    SESSION
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|s| s.supports(feature))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(version: Option<&str>, features: &[&str], required: &[&str]) -> InitializeParams {
+        InitializeParams {
+            protocol_version: version.map(String::from),
+            features: features.iter().map(|s| s.to_string()).collect(),
+            required_features: required.iter().map(|s| s.to_string()).collect(),
+            config: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            ProtocolVersion::parse("1.1"),
+            Some(ProtocolVersion { major: 1, minor: 1 })
+        );
+        assert_eq!(
+            ProtocolVersion::parse("2"),
+            Some(ProtocolVersion { major: 2, minor: 0 })
+        );
+        assert_eq!(ProtocolVersion::parse("1.x"), None);
+        assert_eq!(ProtocolVersion::parse("1.2.3"), None);
+    }
+
+    #[test]
+    fn test_negotiate_features() {
+        let session = negotiate(&params(Some("1.5"), &["batch", "streaming", "foo"], &[])).unwrap();
+        assert_eq!(session.protocol_version, PROTOCOL_VERSION);
+        assert!(session.supports(Feature::Batch));
+        assert!(session.supports(Feature::Streaming));
+        assert!(!session.supports(Feature::Notifications));
+    }
+
+    #[test]
+    fn test_negotiate_legacy_host() {
+        let session = negotiate(&InitializeParams::default()).unwrap();
+        assert_eq!(session, Session::default());
+
+        let err = negotiate(&params(None, &[], &["streaming"])).unwrap_err();
+        assert!(matches!(err, NegotiationError::UnsupportedFeatures(_)));
+    }
+
+    #[test]
+    fn test_negotiate_rejects_incompatible_host() {
+        let err = negotiate(&params(Some("2.0"), &[], &[])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Incompatible protocol version 2.0: provider supports 1.0..=1.1"
+        );
+
+        let err = negotiate(&params(Some("1.1"), &[], &["telepathy"])).unwrap_err();
+        assert_eq!(err.to_string(), "Unsupported required features: telepathy");
+    }
+}
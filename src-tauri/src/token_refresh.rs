@@ -2,7 +2,8 @@
 
 #![allow(dead_code)]
 
-use crate::auth::oauth::{is_token_valid, refresh_access_token};
+use crate::auth::oauth::{is_token_valid, refresh_access_token, OAuthError};
+use crate::config;
 use crate::credentials::AntigravityCredentials;
 use anyhow::Result;
 use chrono::Utc;
@@ -41,6 +42,7 @@ pub async fn refresh_credential_token(
     }
     credential.last_refresh = Some(Utc::now().to_rfc3339());
     credential.is_healthy = true;
+    credential.refresh_retry_at = None;
     credential.last_error = None;
 
     info!("Antigravity OAuth Token 刷新成功");
@@ -56,20 +58,30 @@ pub async fn refresh_credential_token(
 pub async fn ensure_valid_token(
     credential: &mut AntigravityCredentials,
 ) -> Result<String> {
-    // 检查是否有 access_token
-    let access_token = credential
-        .access_token
-        .as_ref()
-        .ok_or_else(|| anyhow::anyhow!("缺少 access_token"))?;
+    let settings = config::current().settings.token_refresh.clone();
+
+    // 只有 refresh_token 的凭证直接刷新换取 access_token
+    let Some(access_token) = credential.access_token.as_ref() else {
+        if credential.refresh_token.is_none() {
+            anyhow::bail!("缺少 access_token");
+        }
+        let result = refresh_token_with_retry(credential, settings.max_retry).await?;
+        return Ok(result.access_token);
+    };
 
     // 检查 token 是否有效
     if is_token_valid(credential.expiry_date) {
         return Ok(access_token.clone());
     }
 
+    if !settings.auto_refresh {
+        warn!("Token 即将过期，但 auto_refresh 已关闭");
+        return Ok(access_token.clone());
+    }
+
     // Token 已过期或即将过期，尝试刷新
     if credential.refresh_token.is_some() {
-        let result = refresh_credential_token(credential).await?;
+        let result = refresh_token_with_retry(credential, settings.max_retry).await?;
         return Ok(result.access_token);
     }
 
@@ -78,6 +90,12 @@ pub async fn ensure_valid_token(
     Ok(access_token.clone())
 }
 
+/// 刷新失败是否为永久性的（refresh_token 已失效），此时凭证需要重新登录
+pub fn is_permanent_failure(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<OAuthError>()
+        .is_some_and(OAuthError::is_permanent)
+}
+
 /// 带重试的 Token 刷新
 pub async fn refresh_token_with_retry(
     credential: &mut AntigravityCredentials,
@@ -88,6 +106,7 @@ pub async fn refresh_token_with_retry(
     for attempt in 0..max_retries {
         match refresh_credential_token(credential).await {
             Ok(result) => return Ok(result),
+            Err(e) if is_permanent_failure(&e) => return Err(e),
             Err(e) => {
                 warn!(
                     "Token 刷新失败 (尝试 {}/{}): {}",